@@ -26,9 +26,13 @@ CREATE TABLE IF NOT EXISTS boards (
 ALTER TABLE boards
     ADD COLUMN IF NOT EXISTS public_id uuid NOT NULL,
     ADD COLUMN IF NOT EXISTS private_id varchar(44) NOT NULL,
+    ADD COLUMN IF NOT EXISTS co_editor_private_id varchar(54),
     ADD COLUMN IF NOT EXISTS height SMALLINT DEFAULT 900,
     ADD COLUMN IF NOT EXISTS width SMALLINT DEFAULT 1720,
-    ADD COLUMN IF NOT EXISTS title varchar(36) DEFAULT 'untitled';
+    ADD COLUMN IF NOT EXISTS title varchar(36) DEFAULT 'untitled',
+    ADD COLUMN IF NOT EXISTS pages jsonb,
+    ADD COLUMN IF NOT EXISTS per_author_undo boolean NOT NULL DEFAULT false,
+    ADD COLUMN IF NOT EXISTS seq bigint NOT NULL DEFAULT 0;
 
 CREATE UNIQUE INDEX IF NOT EXISTS public_id_idx ON boards (public_id);
 
@@ -51,12 +55,77 @@ ALTER TABLE edits
     ADD COLUMN IF NOT EXISTS edit_id uuid NOT NULL,
     ADD COLUMN IF NOT EXISTS status edit_status NOT NULL,
     ADD COLUMN IF NOT EXISTS changed_at timestamp DEFAULT CURRENT_TIMESTAMP,
-    ADD COLUMN IF NOT EXISTS data bytea NOT NULL;
+    ADD COLUMN IF NOT EXISTS data bytea NOT NULL,
+    ADD COLUMN IF NOT EXISTS page_id varchar(36) NOT NULL DEFAULT '',
+    ADD COLUMN IF NOT EXISTS author varchar(64) NOT NULL DEFAULT '',
+    ADD COLUMN IF NOT EXISTS created_at timestamp;
 
 
 CREATE INDEX IF NOT EXISTS board_id_idx ON edits (board_id);
 CREATE INDEX IF NOT EXISTS edit_id_idx ON edits (edit_id);
 
+-- snapshots, current shapes of a page folded from edits beyond the undo horizon
+CREATE TABLE IF NOT EXISTS board_snapshots (
+    board_id uuid,
+    page_id varchar(36) NOT NULL DEFAULT '',
+    data bytea NOT NULL,
+    created_at timestamp DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY(board_id, page_id),
+    CONSTRAINT fk_board FOREIGN KEY(board_id) REFERENCES boards(public_id) ON DELETE CASCADE
+);
+
+-- roles
+DO $$ BEGIN
+    CREATE TYPE board_role AS ENUM ('viewer', 'commenter', 'editor', 'owner');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+CREATE TABLE IF NOT EXISTS board_roles (
+    id SERIAL PRIMARY KEY,
+    board_id uuid,
+    user_id INT,
+    CONSTRAINT fk_board FOREIGN KEY(board_id) REFERENCES boards(public_id) ON DELETE CASCADE,
+    CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+ALTER TABLE board_roles
+    ADD COLUMN IF NOT EXISTS role board_role NOT NULL DEFAULT 'viewer';
+
+CREATE UNIQUE INDEX IF NOT EXISTS board_user_idx ON board_roles (board_id, user_id);
+
+-- invites
+CREATE TABLE IF NOT EXISTS board_invites (
+    id SERIAL PRIMARY KEY,
+    board_id uuid,
+    CONSTRAINT fk_board FOREIGN KEY(board_id) REFERENCES boards(public_id) ON DELETE CASCADE
+);
+
+ALTER TABLE board_invites
+    ADD COLUMN IF NOT EXISTS token varchar(51) NOT NULL,
+    ADD COLUMN IF NOT EXISTS label varchar(36) DEFAULT 'untitled',
+    ADD COLUMN IF NOT EXISTS role board_role NOT NULL DEFAULT 'viewer',
+    ADD COLUMN IF NOT EXISTS expires_at timestamp,
+    ADD COLUMN IF NOT EXISTS max_uses INT,
+    ADD COLUMN IF NOT EXISTS uses INT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS revoked boolean NOT NULL DEFAULT false,
+    ADD COLUMN IF NOT EXISTS created_at timestamp DEFAULT CURRENT_TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS invite_board_id_idx ON board_invites (board_id);
+
+CREATE TABLE IF NOT EXISTS invite_uses (
+    id SERIAL PRIMARY KEY,
+    invite_id INT,
+    user_id INT,
+    CONSTRAINT fk_invite FOREIGN KEY(invite_id) REFERENCES board_invites(id) ON DELETE CASCADE,
+    CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE SET NULL
+);
+
+ALTER TABLE invite_uses
+    ADD COLUMN IF NOT EXISTS used_at timestamp DEFAULT CURRENT_TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS invite_id_idx ON invite_uses (invite_id);
+
 -- jwt
 CREATE TABLE IF NOT EXISTS expired_jwts (
 	  id SERIAL PRIMARY KEY,
@@ -84,3 +153,48 @@ CREATE TABLE IF NOT EXISTS board_folder(
     CONSTRAINT fk_folder FOREIGN KEY(folder_id) REFERENCES folders(id) ON DELETE CASCADE
 );
 
+
+-- assets
+CREATE TABLE IF NOT EXISTS assets(
+    hash char(64) PRIMARY KEY,
+    size INT NOT NULL,
+    created_at timestamp DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS user_assets(
+    user_id INT,
+    hash char(64),
+    PRIMARY KEY(user_id, hash),
+    CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT fk_asset FOREIGN KEY(hash) REFERENCES assets(hash) ON DELETE CASCADE
+);
+
+-- checkpoints
+CREATE TABLE IF NOT EXISTS board_checkpoints(
+    id SERIAL PRIMARY KEY,
+    board_id uuid,
+    CONSTRAINT fk_board FOREIGN KEY(board_id) REFERENCES boards(public_id) ON DELETE CASCADE
+);
+
+ALTER TABLE board_checkpoints
+    ADD COLUMN IF NOT EXISTS name varchar(36) NOT NULL DEFAULT 'untitled',
+    ADD COLUMN IF NOT EXISTS author_id INT REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS pages jsonb,
+    ADD COLUMN IF NOT EXISTS data bytea NOT NULL,
+    ADD COLUMN IF NOT EXISTS created_at timestamp DEFAULT CURRENT_TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS checkpoint_board_id_idx ON board_checkpoints (board_id);
+
+-- cluster
+CREATE TABLE IF NOT EXISTS room_leases(
+    board_id uuid PRIMARY KEY,
+    node_id varchar(64) NOT NULL,
+    expires_at timestamp NOT NULL,
+    CONSTRAINT fk_board FOREIGN KEY(board_id) REFERENCES boards(public_id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS bus_messages(
+    id BIGSERIAL PRIMARY KEY,
+    payload bytea NOT NULL,
+    created_at timestamp DEFAULT CURRENT_TIMESTAMP
+);
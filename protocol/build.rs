@@ -35,6 +35,8 @@ const IMPL_IN_FROM_JS: &str = "
 ";
 
 const MOD_LIST: [&str; 3] = ["Edit", "Msg", "Msg"];
+// messages which have got fields after clients and archives that omit them were made
+const SERDE_DEFAULT_LIST: [&str; 4] = ["Shape", "Modify", "Edit", "UserMessage"];
 
 fn main() -> Result<(), Box<dyn Error>> {
     // generate code from protofiles
@@ -65,7 +67,9 @@ fn main() -> Result<(), Box<dyn Error>> {
             || line.contains("ShapeType ")
             || line.contains("ActionType ")
             || line.contains("EmptyActionType ")
-            || line.contains("LineType ");
+            || line.contains("LineType ")
+            || line.contains("ShapeField ")
+            || line.contains("Role ");
         if line_has_data_type
             && !line.contains("fn ")
             && !line.contains("Msg ")
@@ -83,6 +87,13 @@ fn main() -> Result<(), Box<dyn Error>> {
         if line_has_data_type && line_has_copy_data_type {
             output.push_str("#[wasm_bindgen]\n");
         }
+        // let old clients omit fields that were added later
+        if SERDE_DEFAULT_LIST
+            .iter()
+            .any(|name| line.contains(&format!("pub struct {} ", name)))
+        {
+            output.push_str("#[serde(default)]\n");
+        }
         // push the line
         output.push_str(&line);
         output.push_str("\n");
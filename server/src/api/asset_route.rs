@@ -0,0 +1,95 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Path, State},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE},
+        StatusCode,
+    },
+    response::Response,
+    routing::{get, post},
+    Router,
+};
+use log::error;
+use serde::Serialize;
+
+use crate::{
+    libs::assets::{self, AssetError, ASSET_SCHEME},
+    AppState, ASSET_QUOTA, MAX_ASSET_SIZE,
+};
+
+use super::common::{generate_res, generate_res_json, UserDataFromJWT};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/",
+            post(upload_asset).layer(DefaultBodyLimit::max(*MAX_ASSET_SIZE)),
+        )
+        .route("/:hash", get(read_asset))
+}
+
+/// url - value for Shape.url
+#[derive(Serialize)]
+struct AssetInfo {
+    hash: String,
+    url: String,
+}
+
+async fn upload_asset(
+    State(state): State<AppState>,
+    UserDataFromJWT(user_data): UserDataFromJWT,
+    body: Bytes,
+) -> Response {
+    let user = match user_data {
+        Some(user) => user,
+        None => return generate_res(StatusCode::UNAUTHORIZED, None),
+    };
+    let mut client = state.pool.get().await;
+    match assets::store(
+        state.assets,
+        &mut client,
+        user.id,
+        body,
+        *MAX_ASSET_SIZE,
+        *ASSET_QUOTA,
+    )
+    .await
+    {
+        Ok(hash) => generate_res_json(AssetInfo {
+            url: format!("{ASSET_SCHEME}{hash}"),
+            hash,
+        }),
+        Err(AssetError::Internal(e)) => {
+            error!("cannot store an asset: {}", e);
+            generate_res(StatusCode::INTERNAL_SERVER_ERROR, None)
+        }
+        Err(AssetError::QuotaExceeded) => generate_res(
+            StatusCode::FORBIDDEN,
+            Some(&AssetError::QuotaExceeded.to_string()),
+        ),
+        Err(e) => generate_res(StatusCode::BAD_REQUEST, Some(&e.to_string())),
+    }
+}
+
+async fn read_asset(State(state): State<AppState>, Path(hash): Path<String>) -> Response {
+    if !assets::is_hash(&hash) {
+        return generate_res(StatusCode::BAD_REQUEST, Some("Not a hash"));
+    }
+    match state.assets.get(&hash).await {
+        Ok(Some(data)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(
+                CONTENT_TYPE,
+                assets::sniff_mime(&data).unwrap_or("application/octet-stream"),
+            )
+            // assets are immutable, their urls change with their content
+            .header(CACHE_CONTROL, "public, max-age=31536000, immutable")
+            .body(Body::from(data))
+            .unwrap(),
+        Ok(None) => generate_res(StatusCode::NOT_FOUND, None),
+        Err(e) => {
+            error!("cannot read an asset: {}", e);
+            generate_res(StatusCode::INTERNAL_SERVER_ERROR, None)
+        }
+    }
+}
@@ -3,8 +3,9 @@ use axum::{middleware, Router};
 
 use self::common::process_jwt;
 
+mod asset_route;
 mod auth_route;
-mod common;
+pub mod common;
 mod folder_route;
 mod room_route;
 mod user_route;
@@ -15,5 +16,6 @@ pub fn api(state: AppState) -> Router<AppState> {
         .nest("/auth", auth_route::router())
         .nest("/user", user_route::router())
         .nest("/folder", folder_route::router())
+        .nest("/asset", asset_route::router())
         .layer(middleware::from_fn_with_state(state, process_jwt))
 }
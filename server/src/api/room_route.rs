@@ -1,31 +1,53 @@
 use std::time::SystemTime;
 
 use axum::{
-    extract::{DefaultBodyLimit, Path, State},
-    http::StatusCode,
+    body::Body,
+    body::Bytes,
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        StatusCode,
+    },
     response::Response,
     routing::{delete, get, post, put},
     Json, Router,
 };
 use futures::future::join;
 use log::{debug, error, info};
-use protocol::board_protocol::{BoardSize, Edit};
+use protocol::board_protocol::{BoardSize, Edit, Page};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc::channel, oneshot};
+use tokio::{
+    sync::{mpsc::channel, oneshot},
+    task::spawn_blocking,
+};
 use uuid::Uuid;
 
 use crate::{
     entities::{
         board::{self, get_by_owner, RoomCredentials},
+        checkpoint,
         edit::EditStatus,
-        Paginated,
+        invite::{self, InviteInitials},
+        lease,
+        role::{self, BoardRole},
+        snapshot, user, Paginated,
     },
     libs::{
+        archive::Archive,
+        assets,
+        auth::UserData,
+        cluster::{RequestError, RoomReply, RoomRequest},
         db_queue::{BoardCreateChunk, EditCreateChunk},
-        room::{task, UserMessage},
-        state::{Board, Room},
+        excalidraw::{self, Report},
+        render::{self, png::PngOptions},
+        room::{task, RestoreError},
+        state::{
+            Board, BoardSnapshot, Room, DEFAULT_PAGE_ID, MAX_IMAGE_LENGTH, OWNER_AUTHOR,
+            USER_AUTHOR_PREFIX,
+        },
     },
-    AppState,
+    lifecycle::{find_room, retrive_room},
+    AppState, ASSET_QUOTA, LEASE_TTL, MAX_ASSET_SIZE,
 };
 
 use super::common::{generate_res, generate_res_json, UserDataFromJWT};
@@ -38,12 +60,38 @@ pub fn router() -> Router<AppState> {
             "/",
             post(create_room).layer(DefaultBodyLimit::max(ROOM_INITIAL_LIMIT)),
         )
+        .route(
+            "/import",
+            post(import_room).layer(DefaultBodyLimit::max(ROOM_INITIAL_LIMIT)),
+        )
+        .route(
+            "/import/excalidraw",
+            post(import_excalidraw).layer(DefaultBodyLimit::max(ROOM_INITIAL_LIMIT)),
+        )
         .route("/", delete(delete_room))
         .route("/own/:page", get(read_own_list))
         .route("/private", get(get_private_ids))
         .route("/co-editor/check", post(check_co_editor))
         .route("/co-editor", put(update_co_editor))
         .route("/co-editor/read", post(read_co_editors))
+        .route("/role", put(set_role))
+        .route("/role", delete(delete_role))
+        .route("/role/read", post(read_roles))
+        .route("/invite", post(create_invite))
+        .route("/invite", delete(revoke_invite))
+        .route("/invite/read", post(read_invites))
+        .route("/invite/uses", post(read_invite_uses))
+        .route("/undo-mode", put(set_undo_mode))
+        .route("/shape/authors", post(read_shape_authors))
+        .route("/checkpoint", post(create_checkpoint))
+        .route("/checkpoint/restore", post(restore_checkpoint))
+        .route("/checkpoint/read", post(read_checkpoints))
+        .route("/checkpoint/preview", post(read_checkpoint))
+        .route("/:public_id/export", get(export_archive))
+        .route("/:public_id/export.svg", get(export_svg))
+        .route("/:public_id/export.png", get(export_png))
+        .route("/:public_id/export.pdf", get(export_pdf))
+        .route("/:public_id/export.excalidraw", get(export_excalidraw))
 }
 
 #[derive(Deserialize, Serialize)]
@@ -52,6 +100,8 @@ struct RoomInitials {
     undone: Vec<Edit>,
     size: BoardSize,
     title: Box<str>,
+    #[serde(default)]
+    pages: Vec<Page>,
 }
 
 async fn create_room(
@@ -59,25 +109,143 @@ async fn create_room(
     UserDataFromJWT(user_data): UserDataFromJWT,
     Json(room_init): Json<RoomInitials>,
 ) -> Response {
-    // validate edits
-    for edit in room_init.current.iter() {
-        if let Err(e) = Board::validate_edit(edit) {
-            return generate_res(StatusCode::BAD_REQUEST, Some(&e.to_string()));
+    match create_room_from(state, user_data, room_init).await {
+        Ok(credentials) => generate_res_json(credentials),
+        Err(e) => generate_res(StatusCode::BAD_REQUEST, Some(&e)),
+    }
+}
+
+async fn import_room(
+    State(state): State<AppState>,
+    UserDataFromJWT(user_data): UserDataFromJWT,
+    body: Bytes,
+) -> Response {
+    let mut archive = match Archive::parse(&body) {
+        Ok(archive) => archive,
+        Err(e) => return generate_res(StatusCode::BAD_REQUEST, Some(&e.to_string())),
+    };
+    if let Err(e) = archive.embed_assets() {
+        return generate_res(StatusCode::BAD_REQUEST, Some(&e.to_string()));
+    }
+    let room_init = RoomInitials {
+        current: archive.current,
+        undone: archive.undone,
+        size: archive.size,
+        title: archive.title,
+        pages: archive.pages,
+    };
+    match create_room_from(state, user_data, room_init).await {
+        Ok(credentials) => generate_res_json(credentials),
+        Err(e) => generate_res(StatusCode::BAD_REQUEST, Some(&e)),
+    }
+}
+
+/// Credentials of a room created from an excalidraw scene with the list of losses
+#[derive(Serialize)]
+struct ExcalidrawImport {
+    #[serde(flatten)]
+    credentials: RoomCredentials,
+    #[serde(flatten)]
+    report: Report,
+}
+
+async fn import_excalidraw(
+    State(state): State<AppState>,
+    UserDataFromJWT(user_data): UserDataFromJWT,
+    body: Bytes,
+) -> Response {
+    let imported = match excalidraw::import(&body) {
+        Ok(imported) => imported,
+        Err(e) => return generate_res(StatusCode::BAD_REQUEST, Some(&e.to_string())),
+    };
+    let room_init = RoomInitials {
+        current: imported.current,
+        undone: vec![],
+        size: imported.size,
+        title: imported.title,
+        pages: vec![],
+    };
+    match create_room_from(state, user_data, room_init).await {
+        Ok(credentials) => generate_res_json(ExcalidrawImport {
+            credentials,
+            report: imported.report,
+        }),
+        Err(e) => generate_res(StatusCode::BAD_REQUEST, Some(&e)),
+    }
+}
+
+/// Validates initials and spawns a room with them,
+/// returns the reason if initials are invalid
+async fn create_room_from(
+    state: AppState,
+    user_data: Option<UserData>,
+    mut room_init: RoomInitials,
+) -> Result<RoomCredentials, String> {
+    // validate pages
+    let mut board = Board::new(
+        state.db_queue,
+        room_init.title.clone(),
+        room_init.size.clone(),
+        vec![],
+    );
+    for page in room_init.pages {
+        // the default page is always present
+        if page.id == DEFAULT_PAGE_ID {
+            continue;
+        }
+        if let Err(e) = board.create_page(page) {
+            return Err(e.to_string());
         }
     }
-    for edit in room_init.undone.iter() {
-        if let Err(e) = Board::validate_edit(edit) {
-            return generate_res(StatusCode::BAD_REQUEST, Some(&e.to_string()));
+    // validate edits, embedded images are validated when they are stored
+    for edit in room_init.current.iter().chain(room_init.undone.iter()) {
+        let mut edit = edit.clone();
+        assets::strip_embedded(&mut edit, MAX_IMAGE_LENGTH.into());
+        if let Err(e) = Board::validate_edit(&edit) {
+            return Err(e.to_string());
+        }
+        if !board.has_page(&edit.page_id) {
+            return Err("page does not exist".to_owned());
         }
     }
     // validate size
     if let Err(e) = Board::validate_size(room_init.size.height, room_init.size.width) {
-        return generate_res(StatusCode::BAD_REQUEST, Some(&e.to_string()));
+        return Err(e.to_string());
     }
     // validate title
     if let Err(e) = Board::validate_title(&room_init.title) {
-        return generate_res(StatusCode::BAD_REQUEST, Some(&e.to_string()));
+        return Err(e.to_string());
+    }
+    // imported edits are made by the importer, who gets the owner's token
+    let author = match &user_data {
+        Some(data) => format!("{USER_AUTHOR_PREFIX}{}", data.id),
+        None => OWNER_AUTHOR.to_owned(),
+    };
+    Board::adopt_imported(
+        room_init
+            .current
+            .iter_mut()
+            .chain(room_init.undone.iter_mut()),
+        &author,
+    );
+    // move large embedded images to the asset store after everything else is valid,
+    // so invalid initials do not consume the quota
+    let mut client = state.pool.get().await;
+    let user_id = user_data.as_ref().map(|user| user.id);
+    for edits in [&mut room_init.current, &mut room_init.undone] {
+        assets::store_embedded(
+            state.assets,
+            &mut client,
+            user_id,
+            edits,
+            MAX_IMAGE_LENGTH.into(),
+            *MAX_ASSET_SIZE,
+            *ASSET_QUOTA,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
     }
+    drop(client);
     // owner info
     let mut owner_id: Option<i32> = None;
     //add owner if user is authed
@@ -89,8 +257,7 @@ async fn create_room(
         );
     }
     // create Room instance
-    let board = Board::new(state.db_queue, room_init.title, room_init.size);
-    let room = Room::new(board).await;
+    let room = Room::new(board, owner_id).await;
     let (public_id, private_id) = (room.public_id(), room.private_id().into());
     // get client
     let now = SystemTime::now();
@@ -102,7 +269,9 @@ async fn create_room(
         .send(BoardCreateChunk {
             public_id: room.public_id(),
             private_id: room.private_id().into(),
+            co_editor_private_id: room.co_editor_private_id().into(),
             title: room.title().into(),
+            pages: room.board.pages().clone(),
             owner_id,
             ready: tx,
         })
@@ -135,18 +304,35 @@ async fn create_room(
     )
     .await;
     let _ = join(rx1, rx2).await;
+    // the board is new, so the node can host it
+    let node_id = state.cluster.node_id();
+    if let Err(e) = lease::acquire(&state.pool.get().await, public_id, node_id, *LEASE_TTL).await {
+        error!("cannot acquire lease of room {}: {}", public_id, e);
+    }
     // update rooms
     let (tx, rx) = channel(1);
     tokio::spawn(async move {
-        task(public_id, room, &state.pool, state.db_queue, rx).await;
+        task(
+            public_id,
+            room,
+            &state.pool,
+            state.db_queue,
+            state.assets,
+            rx,
+        )
+        .await;
     });
-    state.rooms.write().await.insert(public_id, tx);
+    tokio::spawn(
+        state
+            .cluster
+            .host_task(public_id, tx.downgrade(), state.rooms.clone()),
+    );
+    state.rooms.insert(public_id, tx);
     info!("Created room with public_id: {}", public_id);
-    // response
-    return generate_res_json(RoomCredentials {
+    Ok(RoomCredentials {
         public_id: public_id.to_string().into_boxed_str(),
         private_id,
-    });
+    })
 }
 
 async fn read_own_list(
@@ -178,34 +364,21 @@ async fn delete_room(
         Ok(id) => id,
         Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
     };
-    // get client
+    // prepare room for delete, it can be hosted by another node
+    let room = find_room(state.clone(), id).await;
     let client = state.pool.get().await;
-    // prepare room for delete
-    let rooms = state.rooms.read().await;
-
-    match rooms.get(&id) {
+    match room {
         Some(room) => {
-            let (tx, rx) = oneshot::channel();
-            let _ = room
-                .send(UserMessage::DeleteRoom {
-                    deleted: tx,
-                    private_id: room_info.private_id.clone(),
-                })
-                .await;
+            let request = RoomRequest::DeleteRoom {
+                private_id: room_info.private_id.clone(),
+            };
             // check if operation was successful
-            match rx.await {
-                Ok(res) => {
-                    if !res {
-                        return generate_res(
-                            StatusCode::UNAUTHORIZED,
-                            Some("private_id is invalid"),
-                        );
-                    }
-                }
-                Err(err) => {
-                    error!("cannot receive the DeleteRoom result: {err}");
-                    return generate_res(StatusCode::INTERNAL_SERVER_ERROR, None);
+            match room.request(request).await {
+                Ok(RoomReply::Done(true)) => (),
+                Ok(_) => {
+                    return generate_res(StatusCode::UNAUTHORIZED, Some("private_id is invalid"))
                 }
+                Err(e) => return request_error(id, e),
             }
         }
         None => {
@@ -213,21 +386,32 @@ async fn delete_room(
             if let Err(_) = board::delete(&client, id, &room_info.private_id).await {
                 return generate_res(StatusCode::NOT_FOUND, Some("no such room"));
             }
-            // response instantly to avoid locking the RwLock
             return generate_res(StatusCode::OK, Some("deleted"));
         }
     }
-    // drop read lock and create write lock
-    drop(rooms);
-    let mut rooms = state.rooms.write().await;
     // delete room from global state
-    rooms.remove(&id);
+    state.rooms.remove(&id);
     // delete room from db
     let _ = board::delete(&client, id, &room_info.private_id).await;
     // send response
     return generate_res(StatusCode::OK, Some("deleted"));
 }
 
+/// Passes the change to the room if it is loaded by any node,
+/// the change is saved in db already, so unloaded rooms get it on loading
+async fn notify_room(state: AppState, public_id: Uuid, request: RoomRequest) {
+    if let Some(room) = find_room(state, public_id).await {
+        if let Err(e) = room.request(request).await {
+            error!("cannot update room {}: {}", public_id, e);
+        }
+    }
+}
+
+fn request_error(public_id: Uuid, e: RequestError) -> Response {
+    error!("request to room {} has failed: {}", public_id, e);
+    generate_res(e.status(), None)
+}
+
 async fn get_private_ids(
     State(state): State<AppState>,
     UserDataFromJWT(user_data): UserDataFromJWT,
@@ -255,30 +439,21 @@ async fn read_co_editors(
         Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
     };
 
-    match state.rooms.read().await.get(&id) {
-        Some(room_chan) => {
-            let (tx, rx) = oneshot::channel();
-            let _ = room_chan
-                .send(UserMessage::GetCoEditorToken {
-                    private_id: room.private_id,
-                    sender: tx,
-                })
-                .await;
-            // check result of operation
-            if let Ok(res) = rx.await {
-                match res {
-                    Ok(id) => {
-                        return generate_res_json(CoEditorInfo {
-                            co_editor_private_id: id,
-                        })
-                    }
-                    Err(_) => return generate_res(StatusCode::UNAUTHORIZED, None),
-                }
-            } else {
-                generate_res(StatusCode::INTERNAL_SERVER_ERROR, None)
-            }
-        }
-        None => return generate_res(StatusCode::NOT_FOUND, None),
+    // load the room if it's not in RAM, so tokens survive cleanup and restarts
+    let room_link = match retrive_room(state, id).await {
+        Ok(room_link) => room_link,
+        Err(e) => return generate_res(e.status(), None),
+    };
+    let request = RoomRequest::GetCoEditorToken {
+        private_id: room.private_id,
+    };
+    // check result of operation
+    match room_link.request(request).await {
+        Ok(RoomReply::Token(Ok(id))) => generate_res_json(CoEditorInfo {
+            co_editor_private_id: id,
+        }),
+        Ok(_) => generate_res(StatusCode::UNAUTHORIZED, None),
+        Err(e) => request_error(id, e),
     }
 }
 
@@ -301,21 +476,17 @@ async fn check_co_editor(
         Ok(id) => id,
         Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
     };
-    match state.rooms.read().await.get(&id) {
-        Some(room) => {
-            let (tx, rx) = oneshot::channel();
-            let _ = room
-                .send(UserMessage::VerifyCoEditorToken {
-                    token: check_info.co_editor_private_id,
-                    sender: tx,
-                })
-                .await;
-            if let Ok(res) = rx.await {
-                return generate_res_json(CheckResult { valid: res });
-            }
-            return generate_res_json(CheckResult { valid: false });
-        }
-        None => generate_res(StatusCode::NOT_FOUND, None),
+    let room = match retrive_room(state, id).await {
+        Ok(room) => room,
+        Err(e) => return generate_res(e.status(), None),
+    };
+    let request = RoomRequest::VerifyCoEditorToken {
+        token: check_info.co_editor_private_id,
+    };
+    match room.request(request).await {
+        Ok(RoomReply::Done(valid)) => generate_res_json(CheckResult { valid }),
+        Ok(_) => generate_res_json(CheckResult { valid: false }),
+        Err(e) => request_error(id, e),
     }
 }
 
@@ -327,25 +498,628 @@ async fn update_co_editor(
         Ok(id) => id,
         Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
     };
-    match state.rooms.write().await.get_mut(&id) {
-        Some(room) => {
-            let (tx, rx) = oneshot::channel();
-            let _ = room
-                .send(UserMessage::GetUpdatedCoEditorToken {
-                    private_id: room_info.private_id,
-                    sender: tx,
-                })
-                .await;
-            if let Ok(res) = rx.await {
-                if let Ok(token) = res {
-                    return generate_res_json(CoEditorInfo {
-                        co_editor_private_id: token,
-                    });
-                }
-            }
+    let room = match retrive_room(state, id).await {
+        Ok(room) => room,
+        Err(e) => return generate_res(e.status(), None),
+    };
+    let request = RoomRequest::GetUpdatedCoEditorToken {
+        private_id: room_info.private_id,
+    };
+    match room.request(request).await {
+        Ok(RoomReply::Token(Ok(token))) => generate_res_json(CoEditorInfo {
+            co_editor_private_id: token,
+        }),
+        Ok(_) => generate_res(StatusCode::UNAUTHORIZED, Some("private_id is invalid")),
+        Err(e) => request_error(id, e),
+    }
+}
+
+#[derive(Deserialize)]
+struct RoleGrant {
+    public_id: Box<str>,
+    private_id: Box<str>,
+    public_login: Box<str>,
+    role: BoardRole,
+}
+
+#[derive(Deserialize)]
+struct RoleRevoke {
+    public_id: Box<str>,
+    private_id: Box<str>,
+    public_login: Box<str>,
+}
+
+async fn read_roles(State(state): State<AppState>, Json(room): Json<RoomCredentials>) -> Response {
+    let id = match Uuid::try_parse(&room.public_id) {
+        Ok(id) => id,
+        Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
+    };
+    match role::read_list(&state.pool.get().await, id, &room.private_id).await {
+        Ok(roles) => generate_res_json(roles),
+        Err(_) => generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+    }
+}
+
+async fn set_role(State(state): State<AppState>, Json(grant): Json<RoleGrant>) -> Response {
+    let id = match Uuid::try_parse(&grant.public_id) {
+        Ok(id) => id,
+        Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
+    };
+    let res = role::set(
+        &state.pool.get().await,
+        id,
+        &grant.private_id,
+        &grant.public_login,
+        grant.role,
+    )
+    .await;
+    match res {
+        Ok(Some(db_id)) => {
+            // update roles of the loaded room
+            let request = RoomRequest::SetRole {
+                db_id,
+                role: Some(grant.role),
+            };
+            notify_room(state, id, request).await;
+            generate_res(StatusCode::OK, Some("updated"))
+        }
+        Ok(None) => generate_res(
+            StatusCode::NOT_FOUND,
+            Some("no such room or user, or private_id is invalid"),
+        ),
+        Err(_) => generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+    }
+}
+
+async fn delete_role(State(state): State<AppState>, Json(revoke): Json<RoleRevoke>) -> Response {
+    let id = match Uuid::try_parse(&revoke.public_id) {
+        Ok(id) => id,
+        Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
+    };
+    let res = role::delete(
+        &state.pool.get().await,
+        id,
+        &revoke.private_id,
+        &revoke.public_login,
+    )
+    .await;
+    match res {
+        Ok(Some(db_id)) => {
+            // update roles of the loaded room
+            notify_room(state, id, RoomRequest::SetRole { db_id, role: None }).await;
+            generate_res(StatusCode::OK, Some("deleted"))
+        }
+        Ok(None) => generate_res(
+            StatusCode::NOT_FOUND,
+            Some("no such role, or private_id is invalid"),
+        ),
+        Err(_) => generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+    }
+}
+
+#[derive(Deserialize)]
+struct InviteCreate {
+    public_id: Box<str>,
+    private_id: Box<str>,
+    #[serde(flatten)]
+    invite: InviteInitials,
+}
+
+#[derive(Deserialize)]
+struct InviteRevoke {
+    public_id: Box<str>,
+    private_id: Box<str>,
+    id: i32,
+}
 
-            return generate_res(StatusCode::UNAUTHORIZED, Some("private_id is invalid"));
+#[derive(Serialize)]
+struct InviteInfo {
+    id: i32,
+    token: Box<str>,
+}
+
+async fn create_invite(State(state): State<AppState>, Json(data): Json<InviteCreate>) -> Response {
+    let id = match Uuid::try_parse(&data.public_id) {
+        Ok(id) => id,
+        Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
+    };
+    if let Err(e) = data.invite.validate() {
+        return generate_res(StatusCode::BAD_REQUEST, Some(e));
+    }
+    let token = Room::generate_invite_token().await;
+    let res = invite::create(
+        &state.pool.get().await,
+        id,
+        &data.private_id,
+        &token,
+        data.invite,
+    )
+    .await;
+    match res {
+        Ok(Some(invite)) => {
+            let info = InviteInfo {
+                id: invite.id,
+                token,
+            };
+            // add the invite to the loaded room
+            notify_room(state, id, RoomRequest::AddInvite(invite)).await;
+            generate_res_json(info)
         }
-        None => generate_res(StatusCode::NOT_FOUND, None),
+        Ok(None) => generate_res(StatusCode::UNAUTHORIZED, Some("private_id is invalid")),
+        Err(_) => generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
     }
 }
+
+async fn revoke_invite(State(state): State<AppState>, Json(data): Json<InviteRevoke>) -> Response {
+    let id = match Uuid::try_parse(&data.public_id) {
+        Ok(id) => id,
+        Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
+    };
+    match invite::revoke(&state.pool.get().await, id, &data.private_id, data.id).await {
+        Ok(true) => {
+            // remove the invite from the loaded room
+            notify_room(state, id, RoomRequest::RevokeInvite { id: data.id }).await;
+            generate_res(StatusCode::OK, Some("revoked"))
+        }
+        Ok(false) => generate_res(
+            StatusCode::NOT_FOUND,
+            Some("no such invite, or private_id is invalid"),
+        ),
+        Err(_) => generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+    }
+}
+
+async fn read_invites(
+    State(state): State<AppState>,
+    Json(room): Json<RoomCredentials>,
+) -> Response {
+    let id = match Uuid::try_parse(&room.public_id) {
+        Ok(id) => id,
+        Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
+    };
+    let client = state.pool.get().await;
+    match invite::is_owned(&client, id, &room.private_id).await {
+        Ok(true) => (),
+        Ok(false) => return generate_res(StatusCode::UNAUTHORIZED, Some("private_id is invalid")),
+        Err(_) => return generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+    }
+    match invite::read_by_board(&client, id).await {
+        Ok(invites) => generate_res_json(invites),
+        Err(_) => generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+    }
+}
+
+async fn read_invite_uses(
+    State(state): State<AppState>,
+    Json(room): Json<RoomCredentials>,
+) -> Response {
+    let id = match Uuid::try_parse(&room.public_id) {
+        Ok(id) => id,
+        Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
+    };
+    match invite::read_uses(&state.pool.get().await, id, &room.private_id).await {
+        Ok(uses) => generate_res_json(uses),
+        Err(_) => generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+    }
+}
+
+/// per_author - if true, users can undo and redo only their own edits, owners can undo any
+#[derive(Deserialize)]
+struct UndoMode {
+    public_id: Box<str>,
+    private_id: Box<str>,
+    per_author: bool,
+}
+
+async fn set_undo_mode(State(state): State<AppState>, Json(data): Json<UndoMode>) -> Response {
+    let id = match Uuid::try_parse(&data.public_id) {
+        Ok(id) => id,
+        Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
+    };
+    let room = match retrive_room(state, id).await {
+        Ok(room) => room,
+        Err(e) => return generate_res(e.status(), None),
+    };
+    let request = RoomRequest::SetUndoMode {
+        private_id: data.private_id,
+        per_author: data.per_author,
+    };
+    match room.request(request).await {
+        Ok(RoomReply::Done(true)) => generate_res(StatusCode::OK, None),
+        Ok(_) => generate_res(StatusCode::UNAUTHORIZED, Some("private_id is invalid")),
+        Err(e) => request_error(id, e),
+    }
+}
+
+/// private_id - owner's, co-editor's or invite token, not needed for members authed by jwt
+#[derive(Deserialize)]
+struct ShapeAuthorsRequest {
+    public_id: Box<str>,
+    shape_id: Box<str>,
+    private_id: Option<Box<str>>,
+}
+
+/// Returns current edits that have changed the shape with their authors
+async fn read_shape_authors(
+    State(state): State<AppState>,
+    UserDataFromJWT(user_data): UserDataFromJWT,
+    Json(data): Json<ShapeAuthorsRequest>,
+) -> Response {
+    let id = match Uuid::try_parse(&data.public_id) {
+        Ok(id) => id,
+        Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
+    };
+    let pool = state.pool;
+    let room = match retrive_room(state, id).await {
+        Ok(room) => room,
+        Err(e) => return generate_res(e.status(), None),
+    };
+    let request = RoomRequest::GetAuthorship {
+        shape_id: data.shape_id,
+        private_id: data.private_id,
+        db_id: user_data.map(|user| user.id),
+    };
+    let mut authorship = match room.request(request).await {
+        Ok(RoomReply::Authorship(Some(authorship))) => authorship,
+        Ok(_) => {
+            return generate_res(
+                StatusCode::UNAUTHORIZED,
+                Some("private_id is invalid or the user is not a member"),
+            )
+        }
+        Err(e) => return request_error(id, e),
+    };
+    // show names of authed authors
+    let user_id = |author: &str| {
+        author
+            .strip_prefix(USER_AUTHOR_PREFIX)
+            .and_then(|id| id.parse::<i32>().ok())
+    };
+    let ids: Vec<i32> = authorship
+        .iter()
+        .filter_map(|edit| user_id(&edit.author))
+        .collect();
+    if !ids.is_empty() {
+        match user::read_names(&pool.get().await, &ids).await {
+            Ok(names) => authorship.iter_mut().for_each(|edit| {
+                edit.name = user_id(&edit.author).and_then(|id| names.get(&id).cloned());
+            }),
+            Err(_) => return generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+        }
+    }
+    generate_res_json(authorship)
+}
+
+#[derive(Deserialize)]
+struct CheckpointInitials {
+    public_id: Box<str>,
+    private_id: Box<str>,
+    name: Box<str>,
+}
+
+#[derive(Deserialize)]
+struct CheckpointCredentials {
+    public_id: Box<str>,
+    private_id: Box<str>,
+    id: i32,
+}
+
+/// edits - number of edits pushed to turn the board into the checkpoint's state
+#[derive(Serialize)]
+struct RestoreResult {
+    edits: usize,
+}
+
+async fn create_checkpoint(
+    State(state): State<AppState>,
+    UserDataFromJWT(user_data): UserDataFromJWT,
+    Json(data): Json<CheckpointInitials>,
+) -> Response {
+    if data.name.is_empty() || data.name.len() > 36 {
+        return generate_res(StatusCode::BAD_REQUEST, Some("name is empty or too long"));
+    }
+    let id = match Uuid::try_parse(&data.public_id) {
+        Ok(id) => id,
+        Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
+    };
+    let room = match retrive_room(state.clone(), id).await {
+        Ok(room) => room,
+        Err(e) => return generate_res(e.status(), None),
+    };
+    // capture the merged state of db and the room's queue
+    let board = match room.request(RoomRequest::GetSnapshot).await {
+        Ok(RoomReply::Snapshot(board)) => board,
+        Ok(_) => return generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+        Err(e) => return request_error(id, e),
+    };
+    let current = snapshot::fold(&board.current)
+        .into_values()
+        .flatten()
+        .collect();
+    let res = checkpoint::create(
+        &state.pool.get().await,
+        id,
+        &data.private_id,
+        &data.name,
+        user_data.map(|user| user.id),
+        &board.pages,
+        current,
+    )
+    .await;
+    match res {
+        Ok(Some(checkpoint)) => generate_res_json(checkpoint),
+        Ok(None) => generate_res(
+            StatusCode::BAD_REQUEST,
+            Some("private_id is invalid or there are too many checkpoints"),
+        ),
+        Err(_) => generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+    }
+}
+
+async fn read_checkpoints(
+    State(state): State<AppState>,
+    Json(room): Json<RoomCredentials>,
+) -> Response {
+    let id = match Uuid::try_parse(&room.public_id) {
+        Ok(id) => id,
+        Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
+    };
+    let client = state.pool.get().await;
+    match invite::is_owned(&client, id, &room.private_id).await {
+        Ok(true) => (),
+        Ok(false) => return generate_res(StatusCode::UNAUTHORIZED, Some("private_id is invalid")),
+        Err(_) => return generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+    }
+    match checkpoint::read_list(&client, id).await {
+        Ok(list) => generate_res_json(list),
+        Err(_) => generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+    }
+}
+
+/// Returns the checkpoint's state to preview it, the board is not changed
+async fn read_checkpoint(
+    State(state): State<AppState>,
+    Json(data): Json<CheckpointCredentials>,
+) -> Response {
+    let id = match Uuid::try_parse(&data.public_id) {
+        Ok(id) => id,
+        Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
+    };
+    let client = state.pool.get().await;
+    match invite::is_owned(&client, id, &data.private_id).await {
+        Ok(true) => (),
+        Ok(false) => return generate_res(StatusCode::UNAUTHORIZED, Some("private_id is invalid")),
+        Err(_) => return generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+    }
+    match checkpoint::read(&client, id, data.id).await {
+        Ok(Some(mut checkpoint)) => {
+            assets::embed(state.assets, &mut checkpoint.current).await;
+            generate_res_json(checkpoint)
+        }
+        Ok(None) => generate_res(StatusCode::NOT_FOUND, None),
+        Err(_) => generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+    }
+}
+
+async fn restore_checkpoint(
+    State(state): State<AppState>,
+    Json(data): Json<CheckpointCredentials>,
+) -> Response {
+    let id = match Uuid::try_parse(&data.public_id) {
+        Ok(id) => id,
+        Err(_) => return generate_res(StatusCode::BAD_REQUEST, Some("Not a uuid")),
+    };
+    let checkpoint = match checkpoint::read(&state.pool.get().await, id, data.id).await {
+        Ok(Some(checkpoint)) => checkpoint,
+        Ok(None) => return generate_res(StatusCode::NOT_FOUND, None),
+        Err(_) => return generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+    };
+    let room = match retrive_room(state, id).await {
+        Ok(room) => room,
+        Err(e) => return generate_res(e.status(), None),
+    };
+    // the room pushes compensating edits, so connected users see the change
+    let request = RoomRequest::RestoreCheckpoint {
+        private_id: data.private_id,
+        pages: checkpoint.pages,
+        current: checkpoint.current,
+    };
+    match room.request(request).await {
+        Ok(RoomReply::Restored(Ok(edits))) => generate_res_json(RestoreResult { edits }),
+        Ok(RoomReply::Restored(Err(RestoreError::Unauthorized))) => {
+            generate_res(StatusCode::UNAUTHORIZED, Some("private_id is invalid"))
+        }
+        Ok(RoomReply::Restored(Err(RestoreError::Invalid(e)))) => {
+            generate_res(StatusCode::BAD_REQUEST, Some(&e))
+        }
+        Ok(_) => generate_res(StatusCode::INTERNAL_SERVER_ERROR, None),
+        Err(e) => request_error(id, e),
+    }
+}
+
+/// scale - png only, ratio of output pixels to board pixels
+/// dpi - png only, overrides scale, 96 is the board's own resolution
+/// crop - png only, render only the area covered by shapes
+#[derive(Deserialize)]
+struct ExportParams {
+    page_id: Option<Box<str>>,
+    scale: Option<f32>,
+    dpi: Option<f32>,
+    #[serde(default)]
+    crop: bool,
+}
+
+const MAX_EXPORT_SCALE: f32 = 8.0;
+const BOARD_DPI: f32 = 96.0;
+
+type ExportError = (StatusCode, Option<&'static str>);
+
+async fn get_snapshot(state: AppState, public_id: &str) -> Result<BoardSnapshot, ExportError> {
+    let id = match Uuid::try_parse(public_id) {
+        Ok(id) => id,
+        Err(_) => return Err((StatusCode::BAD_REQUEST, Some("Not a uuid"))),
+    };
+    let backend = state.assets;
+    let room = match retrive_room(state, id).await {
+        Ok(room) => room,
+        Err(e) => return Err((e.status(), None)),
+    };
+    let mut snapshot = match room.request(RoomRequest::GetSnapshot).await {
+        Ok(RoomReply::Snapshot(snapshot)) => snapshot,
+        Ok(_) => return Err((StatusCode::INTERNAL_SERVER_ERROR, None)),
+        Err(e) => {
+            error!("cannot get snapshot of room {}: {}", id, e);
+            return Err((e.status(), None));
+        }
+    };
+    // exported files must not depend on the server
+    assets::embed(backend, &mut snapshot.current).await;
+    assets::embed(backend, &mut snapshot.undone).await;
+    Ok(snapshot)
+}
+
+fn render_page(snapshot: &BoardSnapshot, page_id: &str) -> Result<String, ExportError> {
+    if !snapshot.pages.iter().any(|page| page.id == page_id) {
+        return Err((StatusCode::NOT_FOUND, Some("page does not exist")));
+    }
+    let shapes = render::shapes(&snapshot.current, page_id);
+    Ok(render::svg::render(&snapshot.size, &shapes))
+}
+
+fn generate_res_file(content_type: &str, body: impl Into<Body>) -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, content_type)
+        .body(body.into())
+        .unwrap()
+}
+
+async fn export_archive(State(state): State<AppState>, Path(public_id): Path<String>) -> Response {
+    let snapshot = match get_snapshot(state, &public_id).await {
+        Ok(snapshot) => snapshot,
+        Err((code, msg)) => return generate_res(code, msg),
+    };
+    let archive = Archive::new(
+        snapshot.title,
+        snapshot.size,
+        snapshot.pages,
+        snapshot.current,
+        snapshot.undone,
+    );
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.b4y.json\"", public_id),
+        )
+        .body(Body::from(serde_json::to_string(&archive).unwrap()))
+        .unwrap()
+}
+
+async fn export_svg(
+    State(state): State<AppState>,
+    Path(public_id): Path<String>,
+    Query(params): Query<ExportParams>,
+) -> Response {
+    let snapshot = match get_snapshot(state, &public_id).await {
+        Ok(snapshot) => snapshot,
+        Err((code, msg)) => return generate_res(code, msg),
+    };
+    let page_id = params.page_id.as_deref().unwrap_or(DEFAULT_PAGE_ID);
+    match render_page(&snapshot, page_id) {
+        Ok(svg) => generate_res_file("image/svg+xml", svg),
+        Err((code, msg)) => generate_res(code, msg),
+    }
+}
+
+async fn export_png(
+    State(state): State<AppState>,
+    Path(public_id): Path<String>,
+    Query(params): Query<ExportParams>,
+) -> Response {
+    let scale = match (params.dpi, params.scale) {
+        (Some(dpi), _) => dpi / BOARD_DPI,
+        (None, Some(scale)) => scale,
+        (None, None) => 1.0,
+    };
+    if !(scale > 0.0 && scale <= MAX_EXPORT_SCALE) {
+        return generate_res(
+            StatusCode::BAD_REQUEST,
+            Some("scale or dpi is out of range"),
+        );
+    }
+    let snapshot = match get_snapshot(state, &public_id).await {
+        Ok(snapshot) => snapshot,
+        Err((code, msg)) => return generate_res(code, msg),
+    };
+    let page_id = params.page_id.as_deref().unwrap_or(DEFAULT_PAGE_ID);
+    let svg = match render_page(&snapshot, page_id) {
+        Ok(svg) => svg,
+        Err((code, msg)) => return generate_res(code, msg),
+    };
+    let options = PngOptions {
+        scale,
+        crop: params.crop,
+    };
+    match spawn_blocking(move || render::png::render(&svg, options)).await {
+        Ok(Ok(png)) => generate_res_file("image/png", png),
+        Ok(Err(e)) => generate_res(StatusCode::BAD_REQUEST, Some(&e.to_string())),
+        Err(e) => {
+            error!("png rendering has panicked: {}", e);
+            generate_res(StatusCode::INTERNAL_SERVER_ERROR, None)
+        }
+    }
+}
+
+async fn export_pdf(State(state): State<AppState>, Path(public_id): Path<String>) -> Response {
+    let snapshot = match get_snapshot(state, &public_id).await {
+        Ok(snapshot) => snapshot,
+        Err((code, msg)) => return generate_res(code, msg),
+    };
+    // every board's page is a pdf page
+    let svgs: Vec<String> = snapshot
+        .pages
+        .iter()
+        .map(|page| {
+            let shapes = render::shapes(&snapshot.current, &page.id);
+            render::svg::render(&snapshot.size, &shapes)
+        })
+        .collect();
+    match spawn_blocking(move || render::pdf::render(&svgs)).await {
+        Ok(Ok(pdf)) => generate_res_file("application/pdf", pdf),
+        Ok(Err(e)) => generate_res(StatusCode::BAD_REQUEST, Some(&e.to_string())),
+        Err(e) => {
+            error!("pdf rendering has panicked: {}", e);
+            generate_res(StatusCode::INTERNAL_SERVER_ERROR, None)
+        }
+    }
+}
+
+async fn export_excalidraw(
+    State(state): State<AppState>,
+    Path(public_id): Path<String>,
+    Query(params): Query<ExportParams>,
+) -> Response {
+    let snapshot = match get_snapshot(state, &public_id).await {
+        Ok(snapshot) => snapshot,
+        Err((code, msg)) => return generate_res(code, msg),
+    };
+    let page_id = params.page_id.as_deref().unwrap_or(DEFAULT_PAGE_ID);
+    if !snapshot.pages.iter().any(|page| page.id == page_id) {
+        return generate_res(StatusCode::NOT_FOUND, Some("page does not exist"));
+    }
+    let shapes = render::shapes(&snapshot.current, page_id);
+    let (mut scene, report) = excalidraw::export(&shapes);
+    // excalidraw ignores unknown fields, so the losses are reported in the file itself
+    scene["board4you"] = serde_json::json!(report);
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.excalidraw\"", public_id),
+        )
+        .body(Body::from(scene.to_string()))
+        .unwrap()
+}
@@ -0,0 +1,89 @@
+use crate::libs::state::DbClient;
+
+// functions
+
+/// Returns the total size of assets uploaded by the user, every asset is counted once
+pub async fn read_usage(client: &DbClient<'_>, user_id: i32) -> Result<i64, tokio_postgres::Error> {
+    let row = client
+        .query_one(
+            "SELECT COALESCE(SUM(assets.size), 0)::BIGINT AS usage FROM user_assets
+             JOIN assets ON assets.hash = user_assets.hash
+             WHERE user_assets.user_id = ($1)",
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(row.get("usage"))
+}
+
+/// Returns true if the user has already uploaded the asset
+pub async fn is_owned(
+    client: &DbClient<'_>,
+    user_id: i32,
+    hash: &str,
+) -> Result<bool, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            "SELECT 1 FROM user_assets WHERE user_id = ($1) AND hash = ($2)",
+            &[&user_id, &hash],
+        )
+        .await?;
+
+    Ok(row.is_some())
+}
+
+/// Saves the asset and binds it to the user if it fits into the user's quota,
+/// does nothing if it is already saved. Returns false if the quota is exceeded.
+/// The user's row is locked, so concurrent uploads of the user are checked one after another
+pub async fn create(
+    client: &mut DbClient<'_>,
+    user_id: i32,
+    hash: &str,
+    size: i32,
+    quota: i64,
+) -> Result<bool, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+    transaction
+        .execute(
+            "SELECT 1 FROM users WHERE id = ($1) FOR UPDATE",
+            &[&user_id],
+        )
+        .await?;
+    let owned = transaction
+        .query_opt(
+            "SELECT 1 FROM user_assets WHERE user_id = ($1) AND hash = ($2)",
+            &[&user_id, &hash],
+        )
+        .await?
+        .is_some();
+    if owned {
+        return Ok(true);
+    }
+    let usage: i64 = transaction
+        .query_one(
+            "SELECT COALESCE(SUM(assets.size), 0)::BIGINT AS usage FROM user_assets
+             JOIN assets ON assets.hash = user_assets.hash
+             WHERE user_assets.user_id = ($1)",
+            &[&user_id],
+        )
+        .await?
+        .get("usage");
+    if usage + size as i64 > quota {
+        return Ok(false);
+    }
+    transaction
+        .execute(
+            "INSERT INTO assets (hash, size) VALUES (($1), ($2)) ON CONFLICT DO NOTHING",
+            &[&hash, &size],
+        )
+        .await?;
+    transaction
+        .execute(
+            "INSERT INTO user_assets (user_id, hash) VALUES (($1), ($2)) ON CONFLICT DO NOTHING",
+            &[&user_id, &hash],
+        )
+        .await?;
+    transaction.commit().await?;
+
+    Ok(true)
+}
@@ -9,10 +9,10 @@ use crate::{
 use futures::pin_mut;
 use log::{error, warn};
 use postgres_types::Type;
-use protocol::board_protocol::BoardSize;
+use protocol::board_protocol::{BoardSize, Page};
 use serde::{Deserialize, Serialize};
 use std::fmt::Write as _;
-use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::{binary_copy::BinaryCopyInWriter, types::Json};
 use uuid::Uuid;
 
 pub async fn create(
@@ -21,21 +21,38 @@ pub async fn create(
 ) -> Result<u64, tokio_postgres::Error> {
     // populate
     let sink = db_client
-        .copy_in("COPY boards (owner_id, public_id, private_id, title) FROM STDIN BINARY")
+        .copy_in(
+            "COPY boards (owner_id, public_id, private_id, co_editor_private_id, title, pages) FROM STDIN BINARY",
+        )
         .await?;
     let writer = BinaryCopyInWriter::new(
         sink,
-        &[Type::INT4, Type::UUID, Type::VARCHAR, Type::VARCHAR],
+        &[
+            Type::INT4,
+            Type::UUID,
+            Type::VARCHAR,
+            Type::VARCHAR,
+            Type::VARCHAR,
+            Type::JSONB,
+        ],
     );
     pin_mut!(writer);
     let mut ready_list = Vec::new();
 
     for chunk in chunks {
+        let pages = Json(&chunk.pages);
         match chunk.owner_id {
             Some(id) => {
                 writer
                     .as_mut()
-                    .write(&[&id, &chunk.public_id, &chunk.private_id, &chunk.title])
+                    .write(&[
+                        &id,
+                        &chunk.public_id,
+                        &chunk.private_id,
+                        &chunk.co_editor_private_id,
+                        &chunk.title,
+                        &pages,
+                    ])
                     .await
                     .unwrap();
             }
@@ -46,7 +63,9 @@ pub async fn create(
                         &None::<&i32>,
                         &chunk.public_id,
                         &chunk.private_id,
+                        &chunk.co_editor_private_id,
                         &chunk.title,
+                        &pages,
                     ])
                     .await
                     .unwrap();
@@ -81,10 +100,11 @@ pub async fn update(
     for chunk in chunks {
         write!(
             &mut statement,
-            "UPDATE boards SET title = '{}', height = {}, width = {} WHERE public_id = '{}';",
+            "UPDATE boards SET title = '{}', height = {}, width = {}, seq = GREATEST(seq, {}) WHERE public_id = '{}';",
             chunk.title,
             chunk.size.height,
             chunk.size.width,
+            chunk.seq,
             chunk.public_id.to_string()
         )
         .unwrap();
@@ -103,28 +123,85 @@ pub async fn update(
     Ok(())
 }
 
+/// co_editor_private_id - is_none for boards created before co-editor tokens were persisted
+pub struct BoardRecord {
+    pub private_id: Box<str>,
+    pub co_editor_private_id: Option<Box<str>>,
+    pub owner_id: Option<i32>,
+    pub board: Board,
+}
+
 pub async fn get(
     pool: &'static PoolWrapper,
     db_queue: &'static DbQueueSender,
     public_id: Uuid,
-) -> Result<(Box<str>, Board), tokio_postgres::Error> {
+) -> Result<BoardRecord, tokio_postgres::Error> {
     let db_client = pool.get().await;
     let sql_res = db_client
         .query_one("SELECT * FROM boards WHERE public_id = $1", &[&public_id])
         .await?;
 
-    let board = Board::load(
+    let mut board = Board::load(
         db_queue,
         sql_res.get("title"),
         BoardSize {
             height: sql_res.get::<&str, i16>("height").try_into().unwrap_or(900),
             width: sql_res.get::<&str, i16>("width").try_into().unwrap_or(1720),
         },
+        sql_res
+            .get::<&str, Option<Json<Vec<Page>>>>("pages")
+            .map(|pages| pages.0)
+            .unwrap_or_default(),
         public_id,
     );
-    let private_id = sql_res.get("private_id");
+    board.set_per_author_undo(sql_res.get("per_author_undo"));
+    board.set_seq(sql_res.get::<&str, i64>("seq") as u64);
 
-    Ok((private_id, board))
+    Ok(BoardRecord {
+        private_id: sql_res.get("private_id"),
+        co_editor_private_id: sql_res.get("co_editor_private_id"),
+        owner_id: sql_res.get("owner_id"),
+        board,
+    })
+}
+
+pub async fn update_co_editor_private_id(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+    co_editor_private_id: &str,
+) -> Result<u64, tokio_postgres::Error> {
+    client
+        .execute(
+            "UPDATE boards SET co_editor_private_id = ($1) WHERE public_id = ($2)",
+            &[&co_editor_private_id, &public_id],
+        )
+        .await
+}
+
+pub async fn update_undo_mode(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+    per_author: bool,
+) -> Result<u64, tokio_postgres::Error> {
+    client
+        .execute(
+            "UPDATE boards SET per_author_undo = ($1) WHERE public_id = ($2)",
+            &[&per_author, &public_id],
+        )
+        .await
+}
+
+pub async fn update_pages(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+    pages: &[Page],
+) -> Result<u64, tokio_postgres::Error> {
+    client
+        .execute(
+            "UPDATE boards SET pages = ($1) WHERE public_id = ($2)",
+            &[&Json(pages), &public_id],
+        )
+        .await
 }
 
 #[derive(Debug, Serialize)]
@@ -0,0 +1,135 @@
+use protocol::board_protocol::{Edit, Page};
+use serde::Serialize;
+use std::time::SystemTime;
+use tokio_postgres::types::Json;
+use uuid::Uuid;
+
+use super::{
+    edit::{decode_edits_async, encode_edits},
+    invite::serialize_time,
+};
+use crate::libs::state::DbClient;
+
+// constants
+
+pub const MAX_CHECKPOINTS_COUNT: i64 = 100;
+
+// structs
+
+/// author - public_login of the user who has created the checkpoint, None if anonymous
+/// created_at - unix timestamp in seconds
+#[derive(Debug, Serialize)]
+pub struct Checkpoint {
+    pub id: i32,
+    pub name: Box<str>,
+    pub author: Option<String>,
+    #[serde(serialize_with = "serialize_time")]
+    pub created_at: Option<SystemTime>,
+}
+
+/// current - Add edits of the shapes the board had, in order of their addition
+#[derive(Debug, Serialize)]
+pub struct CheckpointState {
+    #[serde(flatten)]
+    pub info: Checkpoint,
+    pub pages: Vec<Page>,
+    pub current: Vec<Edit>,
+}
+
+// functions
+
+/// Saves the board's state, returns None if private_id is invalid
+/// or the board has too many checkpoints
+pub async fn create(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+    private_id: &str,
+    name: &str,
+    author_id: Option<i32>,
+    pages: &[Page],
+    current: Vec<Edit>,
+) -> Result<Option<Checkpoint>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            "INSERT INTO board_checkpoints (board_id, name, author_id, pages, data)
+             SELECT public_id, ($3), ($4), ($5), ($6) FROM boards
+             WHERE public_id = ($1) AND private_id = ($2)
+             AND (SELECT COUNT(*) FROM board_checkpoints WHERE board_id = ($1)) < ($7)
+             RETURNING id, created_at,
+             (SELECT public_login FROM users WHERE id = ($4)) AS author",
+            &[
+                &public_id,
+                &private_id,
+                &name,
+                &author_id,
+                &Json(pages),
+                &encode_edits(current),
+                &MAX_CHECKPOINTS_COUNT,
+            ],
+        )
+        .await?;
+
+    Ok(row.map(|row| Checkpoint {
+        id: row.get("id"),
+        name: name.into(),
+        author: row.get("author"),
+        created_at: row.get("created_at"),
+    }))
+}
+
+/// Returns checkpoints of the board, the latest first
+pub async fn read_list(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+) -> Result<Vec<Checkpoint>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "SELECT board_checkpoints.id, name, users.public_login AS author, created_at
+             FROM board_checkpoints LEFT JOIN users ON users.id = board_checkpoints.author_id
+             WHERE board_id = ($1) ORDER BY board_checkpoints.id DESC",
+            &[&public_id],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| Checkpoint {
+            id: row.get("id"),
+            name: row.get("name"),
+            author: row.get("author"),
+            created_at: row.get("created_at"),
+        })
+        .collect())
+}
+
+pub async fn read(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+    id: i32,
+) -> Result<Option<CheckpointState>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            "SELECT board_checkpoints.id, name, users.public_login AS author, created_at,
+             pages, data
+             FROM board_checkpoints LEFT JOIN users ON users.id = board_checkpoints.author_id
+             WHERE board_id = ($1) AND board_checkpoints.id = ($2)",
+            &[&public_id, &id],
+        )
+        .await?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let pages: Option<Json<Vec<Page>>> = row.get("pages");
+
+    Ok(Some(CheckpointState {
+        info: Checkpoint {
+            id: row.get("id"),
+            name: row.get("name"),
+            author: row.get("author"),
+            created_at: row.get("created_at"),
+        },
+        pages: pages.map(|Json(pages)| pages).unwrap_or_default(),
+        current: decode_edits_async(row.get("data")).await,
+    }))
+}
@@ -19,17 +19,18 @@ use uuid::Uuid;
 
 use crate::libs::{
     db_queue::{DbQueueSender, EditCreateChunk, EditDeleteChunk, EditReadChunk, EditUpdateChunk},
-    state::{ExposeId, QueueOp},
+    state::{DbClient, ExposeId, QueueOp},
 };
 
 // helpers
 
-async fn decode_edit_async(buf: Vec<u8>) -> Edit {
+/// Decodes edits stored as PushData, edit rows hold one edit and snapshot rows hold many
+pub async fn decode_edits_async(buf: Vec<u8>) -> Vec<Edit> {
     let (tx, rx) = oneshot::channel();
     spawn_blocking(move || {
         let msg = decode_server_msg(&buf).unwrap().msg.unwrap();
         match msg {
-            Msg::PushData(mut data) => tx.send(data.data.swap_remove(0)).unwrap(),
+            Msg::PushData(data) => tx.send(data.data).unwrap(),
             _ => panic!("Msg is not PushData"),
         };
     })
@@ -38,6 +39,15 @@ async fn decode_edit_async(buf: Vec<u8>) -> Edit {
     rx.await.unwrap()
 }
 
+pub fn encode_edits(edits: Vec<Edit>) -> Vec<u8> {
+    encode_server_msg(&ServerMessage {
+        msg: Some(Msg::PushData(PushData {
+            data: edits,
+            ..Default::default()
+        })),
+    })
+}
+
 // types
 
 pub type IdAction = (SystemTime, Box<str>);
@@ -45,7 +55,7 @@ pub type EditAction = (SystemTime, Edit);
 
 // enums
 
-#[derive(Debug, ToSql, FromSql, PartialEq)]
+#[derive(Debug, ToSql, FromSql, PartialEq, Clone, Copy)]
 #[postgres(name = "edit_status")]
 pub enum EditStatus {
     #[postgres(name = "current")]
@@ -62,12 +72,15 @@ struct SyncData {
     undone_create: Vec<EditAction>,
     set_status_current: Vec<IdAction>,
     set_status_undone: Vec<IdAction>,
+    delete_pages: Vec<Box<str>>,
 }
 
-#[derive(PartialEq, Debug, Clone)]
+/// compacted - ids of current edits restored from the board's snapshot, they cannot be undone
+#[derive(PartialEq, Debug, Clone, Default)]
 pub struct EditState {
     pub current: Vec<Edit>,
     pub undone: Vec<Edit>,
+    pub compacted: Vec<Box<str>>,
 }
 
 // methods
@@ -77,6 +90,7 @@ fn get_sync_data(queue: Vec<QueueOp>) -> SyncData {
     let mut undone = HashMap::new();
     let mut set_current_ids = HashMap::new();
     let mut set_undone_ids = HashMap::new();
+    let mut delete_pages = Vec::new();
     // remove possible dublicates from out future db queries
     for op in queue {
         match op {
@@ -99,6 +113,12 @@ fn get_sync_data(queue: Vec<QueueOp>) -> SyncData {
                     set_current_ids.insert(id.clone(), (stamp, id));
                 }
             }
+            QueueOp::DeletePage(page_id) => {
+                // edits of the page are not in db yet, so just forget them
+                current.retain(|_, (_, edit): &mut EditAction| edit.page_id != page_id.as_ref());
+                undone.retain(|_, (_, edit): &mut EditAction| edit.page_id != page_id.as_ref());
+                delete_pages.push(page_id);
+            }
         }
     }
     // return data
@@ -113,11 +133,28 @@ fn get_sync_data(queue: Vec<QueueOp>) -> SyncData {
             .into_iter()
             .map(|(id, (stamp, _))| (stamp, id))
             .collect(),
+        delete_pages,
     }
 }
 
 pub async fn sync_with_queue(edit_queue: &DbQueueSender, public_id: Uuid, queue: Vec<QueueOp>) {
     let data = get_sync_data(queue);
+    // delete pages before other ops, because status updates may refer to their edits
+    for page_id in data.delete_pages {
+        let (tx, rx) = oneshot::channel();
+        let sent = edit_queue
+            .delete_edit
+            .send(EditDeleteChunk {
+                public_id,
+                status: None,
+                page_id: Some(page_id),
+                ready: tx,
+            })
+            .await;
+        if sent.is_ok() {
+            let _ = rx.await;
+        }
+    }
     let (tx1, rx1) = oneshot::channel();
     let (tx2, rx2) = oneshot::channel();
     let (tx3, rx3) = oneshot::channel();
@@ -214,7 +251,7 @@ pub async fn create(
 
     match db_client
         .execute(&format!(
-            "COPY edits (board_id, edit_id, status, changed_at, data) FROM '{file_name}' WITH (FORMAT csv);",
+            "COPY edits (board_id, edit_id, status, changed_at, page_id, author, created_at, data) FROM '{file_name}' WITH (FORMAT csv);",
         ), &[])
         .await
     {
@@ -256,15 +293,26 @@ pub fn edit_writer(rx: std::sync::mpsc::Receiver<EditCreateFileChunk>) {
                 };
                 let id = Uuid::try_parse(edit.edit.as_ref().unwrap().id()).unwrap();
                 let stamp: DateTime<Utc> = DateTime::from(stamp);
-                let encoded = encode_server_msg(&ServerMessage {
-                    msg: Some(Msg::PushData(PushData { data: vec![edit] })),
-                });
+                let page_id = edit.page_id.clone();
+                // authors are set by the server, so they contain no quotes
+                let author = edit.author.clone();
+                // an empty value is NULL for edits pushed before creation time was recorded
+                let created_at = match edit.created_at {
+                    0 => String::new(),
+                    ms => DateTime::from_timestamp_millis(ms as i64)
+                        .map(|dt| dt.format("%+").to_string())
+                        .unwrap_or_default(),
+                };
+                let encoded = encode_edits(vec![edit]);
                 let buf = format!(
-                    "{},{},{},{},\\x{}\n",
+                    "{},{},{},{},\"{}\",\"{}\",{},\\x{}\n",
                     chunk.public_id.to_string(),
                     id,
                     status,
                     stamp.format("%+"),
+                    page_id,
+                    author,
+                    created_at,
                     HEXUPPER.encode(&encoded)
                 );
                 writer.write(buf.as_bytes()).unwrap();
@@ -287,60 +335,92 @@ pub async fn read(
     if chunks.len() == 0 {
         return Ok(());
     }
-    // form query
-    let mut statement =
-        String::from("SELECT status, board_id, data FROM edits WHERE board_id in (");
+    // form query, snapshots are read in the same statement to see them consistent with edits
+    let mut ids = String::new();
     for chunk in chunks.iter() {
-        write!(&mut statement, "'{}',", chunk.public_id).unwrap();
+        write!(&mut ids, "'{}',", chunk.public_id).unwrap();
     }
-    statement.pop();
-    statement.push_str(") ORDER BY changed_at ASC");
+    ids.pop();
+    let statement = format!(
+        "SELECT board_id, 'current'::edit_status AS status, data, 0 AS part, created_at AS changed_at
+         FROM board_snapshots WHERE board_id in ({ids})
+         UNION ALL
+         SELECT board_id, status, data, 1 AS part, changed_at
+         FROM edits WHERE board_id in ({ids})
+         ORDER BY part ASC, changed_at ASC"
+    );
     // store results
     for row in db_client.query(&statement, &[]).await? {
         let status = row.get("status");
-        let data = decode_edit_async(row.get::<&str, Vec<u8>>("data")).await;
-        let id = row.get("board_id");
-        match res.get_mut(&id) {
-            Some(entry) => match status {
-                EditStatus::Current => entry.current.push(data),
-                EditStatus::Undone => entry.undone.push(data),
-            },
-            None => match status {
-                EditStatus::Current => {
-                    res.insert(
-                        id,
-                        EditState {
-                            current: vec![data],
-                            undone: vec![],
-                        },
-                    );
-                }
-
-                EditStatus::Undone => {
-                    res.insert(
-                        id,
-                        EditState {
-                            current: vec![],
-                            undone: vec![data],
-                        },
-                    );
-                }
-            },
+        let is_snapshot = row.get::<&str, i32>("part") == 0;
+        let data = decode_edits_async(row.get::<&str, Vec<u8>>("data")).await;
+        let entry = res.entry(row.get("board_id")).or_default();
+        if is_snapshot {
+            entry.compacted.extend(
+                data.iter()
+                    .map(|edit| edit.edit.as_ref().unwrap().id().into()),
+            );
+        }
+        match status {
+            EditStatus::Current => entry.current.extend(data),
+            EditStatus::Undone => entry.undone.extend(data),
         }
     }
     // send results
     for chunk in chunks {
         let _ = chunk
             .ready
-            .send(res.remove(&chunk.public_id).unwrap_or(EditState {
-                current: vec![],
-                undone: vec![],
-            }));
+            .send(res.remove(&chunk.public_id).unwrap_or_default());
     }
 
     Ok(())
 }
 
+/// Returns the board's history, edits restored from snapshots come first without timestamps,
+/// then current edits in order of their application. Undone edits are not included
+pub async fn read_history(
+    db_client: &DbClient<'_>,
+    public_id: Uuid,
+) -> Result<(Vec<Edit>, Vec<EditAction>), tokio_postgres::Error> {
+    let rows = db_client
+        .query(
+            "SELECT data, 0 AS part, created_at AS changed_at FROM board_snapshots
+             WHERE board_id = ($1)
+             UNION ALL
+             SELECT data, 1 AS part, changed_at FROM edits
+             WHERE board_id = ($1) AND status = 'current'
+             ORDER BY part ASC, changed_at ASC",
+            &[&public_id],
+        )
+        .await?;
+    let mut parts = Vec::with_capacity(rows.len());
+    for row in rows {
+        let data = decode_edits_async(row.get::<&str, Vec<u8>>("data")).await;
+        parts.push((
+            row.get::<&str, i32>("part") == 0,
+            row.get::<&str, SystemTime>("changed_at"),
+            data,
+        ));
+    }
+
+    Ok(split_history(parts))
+}
+
+/// Splits ordered parts of the history into edits of snapshots and timed edits,
+/// each part is (is_snapshot, changed_at, edits)
+fn split_history(parts: Vec<(bool, SystemTime, Vec<Edit>)>) -> (Vec<Edit>, Vec<EditAction>) {
+    let mut initial = Vec::new();
+    let mut history = Vec::new();
+    for (is_snapshot, stamp, data) in parts {
+        if is_snapshot {
+            initial.extend(data);
+            continue;
+        }
+        history.extend(data.into_iter().map(|edit| (stamp, edit)));
+    }
+    (initial, history)
+}
+
 pub async fn set_status(
     db_client: &tokio_postgres::Client,
     chunks: Vec<EditUpdateChunk>,
@@ -389,40 +469,57 @@ pub async fn delete_bulk(
     db_client: &tokio_postgres::Client,
     chunks: Vec<EditDeleteChunk>,
 ) -> Result<(), tokio_postgres::Error> {
-    if chunks.len() == 0 {
+    if chunks.is_empty() {
         return Ok(());
     }
-    let mut statement = String::from("BEGIN;");
-    let mut ready_list = Vec::with_capacity(chunks.len());
-
-    // form query
-    for chunk in chunks {
-        let status = match chunk.status {
-            EditStatus::Current => "'current'",
-            EditStatus::Undone => "'undone'",
-        };
-
-        write!(
-            &mut statement,
-            "DELETE FROM edits WHERE status = {} AND board_id = '{}';",
-            status, chunk.public_id
-        )
-        .unwrap();
-        ready_list.push(chunk.ready);
+    db_client.batch_execute("BEGIN;").await?;
+    if let Err(e) = delete_chunks(db_client, &chunks).await {
+        let _ = db_client.batch_execute("ROLLBACK;").await;
+        return Err(e);
     }
-    statement.push_str("COMMIT;");
-
-    // execute
-    db_client.batch_execute(&statement).await?;
+    db_client.batch_execute("COMMIT;").await?;
     // notify listeners
-    ready_list.into_iter().for_each(|c| {
-        if let Err(_) = c.send(()) {
+    chunks.into_iter().for_each(|chunk| {
+        if chunk.ready.send(()).is_err() {
             warn!("Failed to send update result");
         }
     });
     Ok(())
 }
 
+/// Deletes edits of the chunks, filters which are None match any value
+async fn delete_chunks(
+    db_client: &tokio_postgres::Client,
+    chunks: &[EditDeleteChunk],
+) -> Result<(), tokio_postgres::Error> {
+    for chunk in chunks {
+        let status = chunk.status.as_ref().map(|status| match status {
+            EditStatus::Current => "current",
+            EditStatus::Undone => "undone",
+        });
+        let page_id = chunk.page_id.as_deref();
+        db_client
+            .execute(
+                "DELETE FROM edits WHERE board_id = ($1)
+                 AND (($2::text) IS NULL OR status::text = ($2))
+                 AND (($3::varchar) IS NULL OR page_id = ($3))",
+                &[&chunk.public_id, &status, &page_id],
+            )
+            .await?;
+        // snapshots consist of current edits
+        if chunk.status != Some(EditStatus::Undone) {
+            db_client
+                .execute(
+                    "DELETE FROM board_snapshots WHERE board_id = ($1)
+                     AND (($2::varchar) IS NULL OR page_id = ($2))",
+                    &[&chunk.public_id, &page_id],
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
 // tests
 
 #[cfg(test)]
@@ -441,6 +538,15 @@ mod tests {
                     shape: None,
                 },
             )),
+            page_id: String::new(),
+            ..Default::default()
+        }
+    }
+
+    fn get_page_edit_sample(id: &str, page_id: &str) -> Edit {
+        Edit {
+            page_id: page_id.to_owned(),
+            ..get_edit_sample(id)
         }
     }
 
@@ -492,6 +598,7 @@ mod tests {
                 undone_create: vec![],
                 set_status_undone: vec![(t_1, "1".into())],
                 set_status_current: vec![],
+                delete_pages: vec![],
             }
         );
     }
@@ -514,6 +621,7 @@ mod tests {
                 undone_create: vec![],
                 set_status_undone: vec![],
                 set_status_current: vec![(t_1, "1".into())],
+                delete_pages: vec![],
             }
         );
     }
@@ -554,4 +662,105 @@ mod tests {
 
         assert_eq!(data.undone_create, vec![(t_2, get_edit_sample("1"))]);
     }
+
+    #[test]
+    fn get_sync_data_delete_page_drops_its_edits() {
+        let t_0 = SystemTime::now();
+        let t_1 = t_0 + SEC;
+        let t_2 = t_1 + SEC;
+        let queue = vec![
+            QueueOp::Push(t_0, get_page_edit_sample("0", "p")),
+            QueueOp::Push(t_1, get_edit_sample("1")),
+            QueueOp::Undo(t_2, "0".into()),
+            QueueOp::DeletePage("p".into()),
+        ];
+
+        let data = get_sync_data(queue);
+
+        assert_eq!(data.current_create, vec![(t_1, get_edit_sample("1"))]);
+        assert_eq!(data.undone_create, vec![]);
+        assert_eq!(data.delete_pages, vec!["p".into()]);
+    }
+
+    #[test]
+    fn get_sync_data_push_undo_redo_creates_current() {
+        let t_0 = SystemTime::now();
+        let t_1 = t_0 + SEC;
+        let t_2 = t_1 + SEC;
+        let queue = vec![
+            QueueOp::Push(t_0, get_edit_sample("0")),
+            QueueOp::Undo(t_1, "0".into()),
+            QueueOp::Redo(t_2, "0".into()),
+        ];
+
+        let data = get_sync_data(queue);
+
+        assert_eq!(data.current_create, vec![(t_2, get_edit_sample("0"))]);
+        assert_eq!(data.undone_create, vec![]);
+        assert_eq!(data.set_status_current, vec![]);
+        assert_eq!(data.set_status_undone, vec![]);
+    }
+
+    #[test]
+    fn get_sync_data_undo_redo_of_saved_edit_keeps_last_status() {
+        let t_0 = SystemTime::now();
+        let t_1 = t_0 + SEC;
+        let t_2 = t_1 + SEC;
+        let queue = vec![
+            QueueOp::Undo(t_0, "0".into()),
+            QueueOp::Redo(t_1, "0".into()),
+            QueueOp::Undo(t_2, "0".into()),
+        ];
+
+        let data = get_sync_data(queue);
+
+        assert_eq!(data.set_status_undone, vec![(t_2, "0".into())]);
+        assert_eq!(data.set_status_current, vec![]);
+    }
+
+    #[test]
+    fn get_sync_data_redo_after_page_deletion_is_not_filtered() {
+        let t_0 = SystemTime::now();
+        let t_1 = t_0 + SEC;
+        let queue = vec![
+            QueueOp::Push(t_0, get_page_edit_sample("0", "p")),
+            QueueOp::DeletePage("p".into()),
+            QueueOp::Redo(t_1, "0".into()),
+        ];
+
+        let data = get_sync_data(queue);
+
+        // the queue cannot tell the edit is gone, so such commands are rejected by the board's index
+        assert_eq!(data.current_create, vec![]);
+        assert_eq!(data.set_status_current, vec![(t_1, "0".into())]);
+        assert_eq!(data.delete_pages, vec!["p".into()]);
+    }
+
+    #[test]
+    fn history_starts_with_snapshots() {
+        let now = SystemTime::now();
+        let parts = vec![
+            (
+                true,
+                now,
+                vec![get_edit_sample("s1"), get_edit_sample("s2")],
+            ),
+            (false, now + SEC, vec![get_edit_sample("e1")]),
+            (false, now + SEC * 2, vec![get_edit_sample("e2")]),
+        ];
+
+        let (initial, history) = split_history(parts);
+        let ids = |edits: Vec<&Edit>| -> Vec<String> {
+            edits
+                .into_iter()
+                .map(|edit| edit.edit.as_ref().unwrap().id().to_owned())
+                .collect()
+        };
+        assert_eq!(ids(initial.iter().collect()), ["s1", "s2"]);
+        assert_eq!(
+            ids(history.iter().map(|(_, edit)| edit).collect()),
+            ["e1", "e2"]
+        );
+        assert_eq!(history[1].0, now + SEC * 2);
+    }
 }
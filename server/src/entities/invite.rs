@@ -0,0 +1,297 @@
+use super::role::BoardRole;
+use crate::libs::state::DbClient;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+// constants
+
+/// Latest expiration time in unix seconds(the end of 9999 year), db cannot store much later times
+const MAX_EXPIRES_AT: u64 = 253_402_300_799;
+
+// structs
+
+/// token - secret part of the invite link
+/// label - name of the invite shown to the owner
+/// role - role granted to users who have joined via the invite
+/// expires_at - the invite is invalid after this time, never expires if None
+/// max_uses - the invite is invalid after this number of uses, unlimited if None
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Invite {
+    pub id: i32,
+    pub token: Box<str>,
+    pub label: Box<str>,
+    pub role: BoardRole,
+    #[serde(
+        serialize_with = "serialize_time",
+        deserialize_with = "deserialize_time"
+    )]
+    pub expires_at: Option<SystemTime>,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+}
+
+impl Invite {
+    pub fn is_valid(&self, now: SystemTime) -> bool {
+        let expired = match self.expires_at {
+            Some(t) => now > t,
+            None => false,
+        };
+        let exhausted = match self.max_uses {
+            Some(max) => self.uses >= max,
+            None => false,
+        };
+        !expired && !exhausted
+    }
+}
+
+/// expires_at - unix timestamp in seconds
+#[derive(Debug, Deserialize)]
+pub struct InviteInitials {
+    pub label: Box<str>,
+    pub role: BoardRole,
+    pub expires_at: Option<u64>,
+    pub max_uses: Option<i32>,
+}
+
+impl InviteInitials {
+    /// Checks if the invite can be created, returns the reason if it cannot
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.label.len() > 36 {
+            return Err("label is too long");
+        }
+        if self.max_uses.is_some_and(|max_uses| max_uses < 1) {
+            return Err("max_uses must be positive");
+        }
+        // only the owner's token grants the owner role
+        if self.role == BoardRole::Owner {
+            return Err("invite cannot grant the owner role");
+        }
+        if self.expires_at.is_some() && self.expiration().is_none() {
+            return Err("expires_at is out of range");
+        }
+        Ok(())
+    }
+
+    /// Returns time the invite expires at, None if it never expires or expires_at is out of range
+    fn expiration(&self) -> Option<SystemTime> {
+        self.expires_at
+            .filter(|t| *t <= MAX_EXPIRES_AT)
+            .and_then(|t| UNIX_EPOCH.checked_add(Duration::from_secs(t)))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct InviteUse {
+    pub invite_id: i32,
+    pub label: Box<str>,
+    pub public_login: Option<String>,
+    #[serde(serialize_with = "serialize_time")]
+    pub used_at: Option<SystemTime>,
+}
+
+// helpers
+
+/// Serializes time as unix timestamp in seconds
+pub fn serialize_time<S: serde::Serializer>(
+    t: &Option<SystemTime>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    match t {
+        Some(t) => s.serialize_some(
+            &t.duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_secs(),
+        ),
+        None => s.serialize_none(),
+    }
+}
+
+pub fn deserialize_time<'de, D: serde::Deserializer<'de>>(
+    d: D,
+) -> Result<Option<SystemTime>, D::Error> {
+    let secs: Option<u64> = Option::deserialize(d)?;
+    Ok(secs.and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs))))
+}
+
+// functions
+
+pub async fn create(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+    private_id: &str,
+    token: &str,
+    initials: InviteInitials,
+) -> Result<Option<Invite>, tokio_postgres::Error> {
+    let expires_at = initials.expiration();
+    let row = client
+        .query_opt(
+            "INSERT INTO board_invites (board_id, token, label, role, expires_at, max_uses)
+             SELECT public_id, ($3), ($4), ($5), ($6), ($7) FROM boards
+             WHERE public_id = ($1) AND private_id = ($2)
+             RETURNING id",
+            &[
+                &public_id,
+                &private_id,
+                &token,
+                &initials.label,
+                &initials.role,
+                &expires_at,
+                &initials.max_uses,
+            ],
+        )
+        .await?;
+
+    Ok(row.map(|row| Invite {
+        id: row.get("id"),
+        token: token.into(),
+        label: initials.label,
+        role: initials.role,
+        expires_at,
+        max_uses: initials.max_uses,
+        uses: 0,
+    }))
+}
+
+/// Returns invites of the board that are not revoked
+pub async fn read_by_board(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+) -> Result<Vec<Invite>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "SELECT id, token, label, role, expires_at, max_uses, uses FROM board_invites
+             WHERE board_id = ($1) AND revoked = false ORDER BY id",
+            &[&public_id],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| Invite {
+            id: row.get("id"),
+            token: row.get("token"),
+            label: row.get("label"),
+            role: row.get("role"),
+            expires_at: row.get("expires_at"),
+            max_uses: row.get("max_uses"),
+            uses: row.get("uses"),
+        })
+        .collect())
+}
+
+/// Returns true if private_id is valid for the board
+pub async fn is_owned(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+    private_id: &str,
+) -> Result<bool, tokio_postgres::Error> {
+    let row = client
+        .query_one(
+            "SELECT COUNT(*) FROM boards WHERE public_id = ($1) AND private_id = ($2)",
+            &[&public_id, &private_id],
+        )
+        .await?;
+
+    Ok(row.get::<&str, i64>("count") != 0)
+}
+
+/// Marks the invite as revoked, returns false if there is no such invite or private_id is invalid
+pub async fn revoke(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+    private_id: &str,
+    id: i32,
+) -> Result<bool, tokio_postgres::Error> {
+    let count = client
+        .execute(
+            "UPDATE board_invites SET revoked = true FROM boards
+             WHERE board_invites.board_id = boards.public_id AND board_invites.id = ($3)
+             AND boards.public_id = ($1) AND boards.private_id = ($2)",
+            &[&public_id, &private_id, &id],
+        )
+        .await?;
+
+    Ok(count != 0)
+}
+
+/// Increments the use counter and saves who has used the invite
+pub async fn save_use(
+    client: &DbClient<'_>,
+    id: i32,
+    user_id: Option<i32>,
+) -> Result<(), tokio_postgres::Error> {
+    client
+        .execute(
+            "UPDATE board_invites SET uses = uses + 1 WHERE id = ($1)",
+            &[&id],
+        )
+        .await?;
+    client
+        .execute(
+            "INSERT INTO invite_uses (invite_id, user_id) VALUES ($1, $2)",
+            &[&id, &user_id],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Returns uses of the board's invites if private_id is valid
+pub async fn read_uses(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+    private_id: &str,
+) -> Result<Vec<InviteUse>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "SELECT invite_uses.invite_id, board_invites.label, users.public_login, invite_uses.used_at
+             FROM invite_uses
+             JOIN board_invites ON board_invites.id = invite_uses.invite_id
+             JOIN boards ON boards.public_id = board_invites.board_id
+             LEFT JOIN users ON users.id = invite_uses.user_id
+             WHERE boards.public_id = ($1) AND boards.private_id = ($2)
+             ORDER BY invite_uses.id DESC",
+            &[&public_id, &private_id],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| InviteUse {
+            invite_id: row.get("invite_id"),
+            label: row.get("label"),
+            public_login: row.get("public_login"),
+            used_at: row.get("used_at"),
+        })
+        .collect())
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_initials_sample(role: BoardRole, expires_at: Option<u64>) -> InviteInitials {
+        InviteInitials {
+            label: "invite".into(),
+            role,
+            expires_at,
+            max_uses: None,
+        }
+    }
+
+    #[test]
+    fn invalid_invites_are_rejected() {
+        assert!(get_initials_sample(BoardRole::Editor, Some(1_700_000_000))
+            .validate()
+            .is_ok());
+        assert!(get_initials_sample(BoardRole::Owner, None)
+            .validate()
+            .is_err());
+        assert!(get_initials_sample(BoardRole::Editor, Some(u64::MAX))
+            .validate()
+            .is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use crate::libs::state::DbClient;
+use std::time::Duration;
+use uuid::Uuid;
+
+// functions
+
+/// Takes the lease of the board if it is free, expired or already held by the node.
+/// Returns the id of the node holding the lease
+pub async fn acquire(
+    client: &DbClient<'_>,
+    board_id: Uuid,
+    node_id: &str,
+    ttl: Duration,
+) -> Result<String, tokio_postgres::Error> {
+    loop {
+        let row = client
+            .query_opt(
+                "INSERT INTO room_leases (board_id, node_id, expires_at)
+                 VALUES (($1), ($2), LOCALTIMESTAMP + make_interval(secs => ($3)))
+                 ON CONFLICT (board_id) DO UPDATE
+                 SET node_id = EXCLUDED.node_id, expires_at = EXCLUDED.expires_at
+                 WHERE room_leases.node_id = EXCLUDED.node_id
+                 OR room_leases.expires_at < LOCALTIMESTAMP
+                 RETURNING node_id",
+                &[&board_id, &node_id, &ttl.as_secs_f64()],
+            )
+            .await?;
+        if let Some(row) = row {
+            return Ok(row.get("node_id"));
+        }
+        // the lease is held by another node unless it has been released meanwhile
+        if let Some(node_id) = holder(client, board_id).await? {
+            return Ok(node_id);
+        }
+    }
+}
+
+/// Returns the id of the node holding a valid lease of the board
+pub async fn holder(
+    client: &DbClient<'_>,
+    board_id: Uuid,
+) -> Result<Option<String>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            "SELECT node_id FROM room_leases WHERE board_id = ($1) AND expires_at >= LOCALTIMESTAMP",
+            &[&board_id],
+        )
+        .await?;
+    Ok(row.map(|row| row.get("node_id")))
+}
+
+/// Prolongs leases of the boards held by the node.
+/// Returns ids of the boards which leases are lost
+pub async fn renew(
+    client: &DbClient<'_>,
+    board_ids: &[Uuid],
+    node_id: &str,
+    ttl: Duration,
+) -> Result<Vec<Uuid>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "UPDATE room_leases SET expires_at = LOCALTIMESTAMP + make_interval(secs => ($3))
+             WHERE board_id = ANY($1) AND node_id = ($2)
+             RETURNING board_id",
+            &[&board_ids, &node_id, &ttl.as_secs_f64()],
+        )
+        .await?;
+    let renewed: Vec<Uuid> = rows.iter().map(|row| row.get("board_id")).collect();
+    Ok(board_ids
+        .iter()
+        .filter(|id| !renewed.contains(id))
+        .copied()
+        .collect())
+}
+
+pub async fn release(
+    client: &DbClient<'_>,
+    board_ids: &[Uuid],
+    node_id: &str,
+) -> Result<u64, tokio_postgres::Error> {
+    client
+        .execute(
+            "DELETE FROM room_leases WHERE board_id = ANY($1) AND node_id = ($2)",
+            &[&board_ids, &node_id],
+        )
+        .await
+}
@@ -1,9 +1,15 @@
 use serde::{Deserialize, Serialize};
 
+pub mod asset;
 pub mod board;
+pub mod checkpoint;
 pub mod edit;
 pub mod folder;
+pub mod invite;
 pub mod jwt;
+pub mod lease;
+pub mod role;
+pub mod snapshot;
 pub mod user;
 
 pub const PAGE_ELEMENTS_COUNT: i64 = 10;
@@ -0,0 +1,136 @@
+use crate::libs::state::DbClient;
+use postgres_types::{FromSql, ToSql};
+use protocol::board_protocol::Role;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// enums
+
+#[derive(Debug, ToSql, FromSql, Deserialize, Serialize, PartialEq, Clone, Copy)]
+#[postgres(name = "board_role")]
+#[serde(rename_all = "lowercase")]
+pub enum BoardRole {
+    #[postgres(name = "viewer")]
+    Viewer,
+    #[postgres(name = "commenter")]
+    Commenter,
+    #[postgres(name = "editor")]
+    Editor,
+    #[postgres(name = "owner")]
+    Owner,
+}
+
+impl From<BoardRole> for Role {
+    fn from(role: BoardRole) -> Self {
+        match role {
+            BoardRole::Viewer => Role::Viewer,
+            BoardRole::Commenter => Role::Commenter,
+            BoardRole::Editor => Role::Editor,
+            BoardRole::Owner => Role::Owner,
+        }
+    }
+}
+
+// structs
+
+#[derive(Debug, Serialize)]
+pub struct RoleInfo {
+    pub public_login: String,
+    pub role: BoardRole,
+}
+
+// functions
+
+/// Returns roles of the board's members by their user ids
+pub async fn read_by_board(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+) -> Result<HashMap<i32, Role>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "SELECT user_id, role FROM board_roles WHERE board_id = ($1)",
+            &[&public_id],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            (
+                row.get::<&str, i32>("user_id"),
+                row.get::<&str, BoardRole>("role").into(),
+            )
+        })
+        .collect())
+}
+
+/// Returns roles of the board's members if private_id is valid
+pub async fn read_list(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+    private_id: &str,
+) -> Result<Vec<RoleInfo>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "SELECT users.public_login, board_roles.role FROM board_roles
+             JOIN users ON users.id = board_roles.user_id
+             JOIN boards ON boards.public_id = board_roles.board_id
+             WHERE boards.public_id = ($1) AND boards.private_id = ($2)
+             ORDER BY board_roles.id",
+            &[&public_id, &private_id],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| RoleInfo {
+            public_login: row.get("public_login"),
+            role: row.get("role"),
+        })
+        .collect())
+}
+
+/// Grants the role to the user and returns the user's id.
+/// Returns None if private_id or public_login is invalid
+pub async fn set(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+    private_id: &str,
+    public_login: &str,
+    role: BoardRole,
+) -> Result<Option<i32>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            "INSERT INTO board_roles (board_id, user_id, role)
+             SELECT boards.public_id, users.id, ($4) FROM boards, users
+             WHERE boards.public_id = ($1) AND boards.private_id = ($2) AND users.public_login = ($3)
+             ON CONFLICT (board_id, user_id) DO UPDATE SET role = EXCLUDED.role
+             RETURNING user_id",
+            &[&public_id, &private_id, &public_login, &role],
+        )
+        .await?;
+
+    Ok(row.map(|row| row.get("user_id")))
+}
+
+/// Revokes the role from the user and returns the user's id.
+/// Returns None if private_id or public_login is invalid
+pub async fn delete(
+    client: &DbClient<'_>,
+    public_id: Uuid,
+    private_id: &str,
+    public_login: &str,
+) -> Result<Option<i32>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            "DELETE FROM board_roles USING boards, users
+             WHERE board_roles.board_id = boards.public_id AND board_roles.user_id = users.id
+             AND boards.public_id = ($1) AND boards.private_id = ($2) AND users.public_login = ($3)
+             RETURNING board_roles.user_id",
+            &[&public_id, &private_id, &public_login],
+        )
+        .await?;
+
+    Ok(row.map(|row| row.get("user_id")))
+}
@@ -0,0 +1,256 @@
+use protocol::board_protocol::{edit::Edit as EditInner, Add, Edit};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use uuid::Uuid;
+
+use super::edit::{decode_edits_async, encode_edits};
+use crate::libs::{render, state::DbClient};
+
+// constants
+
+/// Holder of leases taken by compaction, other nodes never see it because the lease is released
+/// in the same transaction
+const COMPACTION_HOLDER: &str = "compaction";
+
+// functions
+
+/// Applies edits in order and returns Add edits of the remaining shapes by page ids.
+/// Shapes are added back by edits with their ids, authors and creation time of the shapes
+pub fn fold(edits: &[Edit]) -> BTreeMap<String, Vec<Edit>> {
+    let pages: BTreeSet<&str> = edits.iter().map(|edit| edit.page_id.as_str()).collect();
+    let mut created: HashMap<&str, &Edit> = HashMap::new();
+    for edit in edits {
+        if let Some(EditInner::Add(Add {
+            shape: Some(shape), ..
+        })) = &edit.edit
+        {
+            created.insert(&shape.shape_id, edit);
+        }
+    }
+    pages
+        .into_iter()
+        .map(|page_id| {
+            let shapes = render::shapes(edits, page_id)
+                .into_iter()
+                .map(|shape| {
+                    let (author, created_at) = match created.get(shape.shape_id.as_str()) {
+                        Some(add) => (add.author.clone(), add.created_at),
+                        None => (String::new(), 0),
+                    };
+                    Edit {
+                        edit: Some(EditInner::Add(Add {
+                            id: shape.shape_id.clone(),
+                            shape: Some(shape),
+                        })),
+                        page_id: page_id.to_owned(),
+                        author,
+                        created_at,
+                    }
+                })
+                .collect();
+            (page_id.to_owned(), shapes)
+        })
+        .collect()
+}
+
+/// Returns boards which have more than twice the horizon of current edits,
+/// so every compaction folds at least the horizon's worth of them
+pub async fn read_candidates(
+    client: &DbClient<'_>,
+    horizon: usize,
+    limit: i64,
+) -> Result<Vec<Uuid>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "SELECT board_id FROM edits WHERE status = 'current'
+             GROUP BY board_id HAVING COUNT(*) > ($1) LIMIT ($2)",
+            &[&(horizon as i64 * 2), &limit],
+        )
+        .await?;
+
+    Ok(rows.iter().map(|row| row.get("board_id")).collect())
+}
+
+/// Folds current edits older than the last `horizon` ones into the board's snapshots.
+/// Returns the number of folded edits.
+///
+/// The board's lease is taken for the time of the transaction, so the board is not compacted
+/// while any node hosts it and nodes loading it wait for the compaction to finish
+pub async fn compact(
+    client: &mut DbClient<'_>,
+    public_id: Uuid,
+    horizon: usize,
+) -> Result<usize, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+    let leased = transaction
+        .query_opt(
+            "INSERT INTO room_leases (board_id, node_id, expires_at)
+             VALUES (($1), ($2), LOCALTIMESTAMP)
+             ON CONFLICT (board_id) DO UPDATE
+             SET node_id = EXCLUDED.node_id, expires_at = EXCLUDED.expires_at
+             WHERE room_leases.expires_at < LOCALTIMESTAMP
+             RETURNING node_id",
+            &[&public_id, &COMPACTION_HOLDER],
+        )
+        .await?;
+    if leased.is_none() {
+        return Ok(0);
+    }
+    let snapshot_rows = transaction
+        .query(
+            "SELECT data FROM board_snapshots WHERE board_id = ($1) FOR UPDATE",
+            &[&public_id],
+        )
+        .await?;
+    let edit_rows = transaction
+        .query(
+            "SELECT edit_id, data FROM edits WHERE board_id = ($1) AND status = 'current'
+             ORDER BY changed_at ASC",
+            &[&public_id],
+        )
+        .await?;
+    if edit_rows.len() <= horizon {
+        return Ok(0);
+    }
+    let folded_rows = &edit_rows[..edit_rows.len() - horizon];
+    // restore the state beyond the horizon
+    let mut edits: Vec<Edit> = Vec::new();
+    for row in snapshot_rows.iter().chain(folded_rows.iter()) {
+        edits.extend(decode_edits_async(row.get("data")).await);
+    }
+    let snapshots = fold(&edits);
+    // replace folded edits with snapshots
+    let folded_ids: Vec<Uuid> = folded_rows.iter().map(|row| row.get("edit_id")).collect();
+    transaction
+        .execute("DELETE FROM edits WHERE edit_id = ANY($1)", &[&folded_ids])
+        .await?;
+    for (page_id, shapes) in snapshots {
+        if shapes.is_empty() {
+            transaction
+                .execute(
+                    "DELETE FROM board_snapshots WHERE board_id = ($1) AND page_id = ($2)",
+                    &[&public_id, &page_id],
+                )
+                .await?;
+            continue;
+        }
+        transaction
+            .execute(
+                "INSERT INTO board_snapshots (board_id, page_id, data) VALUES (($1), ($2), ($3))
+                 ON CONFLICT (board_id, page_id)
+                 DO UPDATE SET data = EXCLUDED.data, created_at = CURRENT_TIMESTAMP",
+                &[&public_id, &page_id, &encode_edits(shapes)],
+            )
+            .await?;
+    }
+    transaction
+        .execute(
+            "DELETE FROM room_leases WHERE board_id = ($1)",
+            &[&public_id],
+        )
+        .await?;
+    transaction.commit().await?;
+
+    Ok(folded_rows.len())
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::board_protocol::{Modify, Remove, Shape};
+
+    fn get_shape_sample(id: &str, x: f32) -> Shape {
+        Shape {
+            shape_id: id.to_owned(),
+            x,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fold_keeps_last_state_of_remaining_shapes() {
+        let edits = vec![
+            Edit {
+                edit: Some(EditInner::Add(Add {
+                    id: "1".to_owned(),
+                    shape: Some(get_shape_sample("1", 0.0)),
+                })),
+                page_id: String::new(),
+                ..Default::default()
+            },
+            Edit {
+                edit: Some(EditInner::Add(Add {
+                    id: "2".to_owned(),
+                    shape: Some(get_shape_sample("2", 0.0)),
+                })),
+                page_id: "page".to_owned(),
+                ..Default::default()
+            },
+            Edit {
+                edit: Some(EditInner::Modify(Modify {
+                    id: "3".to_owned(),
+                    initial: vec![get_shape_sample("1", 0.0)],
+                    current: vec![get_shape_sample("1", 10.0)],
+                    ..Default::default()
+                })),
+                page_id: String::new(),
+                ..Default::default()
+            },
+            Edit {
+                edit: Some(EditInner::Remove(Remove {
+                    id: "4".to_owned(),
+                    shapes: vec![get_shape_sample("2", 0.0)],
+                })),
+                page_id: "page".to_owned(),
+                ..Default::default()
+            },
+        ];
+
+        let snapshots = fold(&edits);
+
+        assert!(snapshots["page"].is_empty());
+        assert_eq!(
+            snapshots[""],
+            vec![Edit {
+                edit: Some(EditInner::Add(Add {
+                    id: "1".to_owned(),
+                    shape: Some(get_shape_sample("1", 10.0)),
+                })),
+                page_id: String::new(),
+                ..Default::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn fold_keeps_authors_of_shapes() {
+        let edits = vec![
+            Edit {
+                edit: Some(EditInner::Add(Add {
+                    id: "1".to_owned(),
+                    shape: Some(get_shape_sample("1", 0.0)),
+                })),
+                author: "user:1".to_owned(),
+                created_at: 1,
+                ..Default::default()
+            },
+            Edit {
+                edit: Some(EditInner::Modify(Modify {
+                    id: "2".to_owned(),
+                    initial: vec![get_shape_sample("1", 0.0)],
+                    current: vec![get_shape_sample("1", 10.0)],
+                    ..Default::default()
+                })),
+                author: "user:2".to_owned(),
+                created_at: 2,
+                ..Default::default()
+            },
+        ];
+
+        let snapshots = fold(&edits);
+
+        assert_eq!(snapshots[""][0].author, "user:1");
+        assert_eq!(snapshots[""][0].created_at, 1);
+    }
+}
@@ -4,7 +4,10 @@ use argon2::{
     Argon2,
 };
 use serde::{Deserialize, Serialize};
-use std::fmt::{self, Display};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
 use tokio::sync::oneshot;
 
 // user-related structs
@@ -131,6 +134,27 @@ pub async fn read(client: &DbClient<'_>, owner_id: i32) -> Option<UserInfo> {
     }
 }
 
+/// Returns display names of the users by their ids, missing users are skipped
+pub async fn read_names(
+    client: &DbClient<'_>,
+    ids: &[i32],
+) -> Result<HashMap<i32, String>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "SELECT id, first_name, second_name FROM users WHERE id = ANY($1)",
+            &[&ids],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let first_name: &str = row.get("first_name");
+            let second_name: &str = row.get("second_name");
+            (row.get("id"), format!("{} {}", first_name, second_name))
+        })
+        .collect())
+}
+
 pub async fn read_by_public_login(
     client: &DbClient<'_>,
     public_login: &str,
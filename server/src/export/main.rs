@@ -0,0 +1,100 @@
+use clap::{Parser, ValueEnum};
+use reqwest::Client;
+use std::{fs, process::exit};
+
+// board export via the room api
+
+#[derive(ValueEnum, Clone, Debug)]
+enum Format {
+    Svg,
+    Png,
+    Pdf,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Svg => "svg",
+            Format::Png => "png",
+            Format::Pdf => "pdf",
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Exports a board to svg, png or pdf", long_about = None)]
+struct Args {
+    /// Board's public id
+    public_id: String,
+
+    #[arg(short, long, value_enum, default_value_t = Format::Pdf)]
+    format: Format,
+
+    /// Output file, <public_id>.<format> by default
+    #[arg(short, long)]
+    output: Option<String>,
+
+    #[arg(long, default_value = "http://localhost:3000")]
+    host: String,
+
+    /// Page to export(svg and png only), the default page if not set
+    #[arg(long)]
+    page_id: Option<String>,
+
+    /// Ratio of output pixels to board pixels(png only)
+    #[arg(short, long)]
+    scale: Option<f32>,
+
+    /// Resolution of the output image, overrides scale(png only)
+    #[arg(short, long)]
+    dpi: Option<f32>,
+
+    /// Render only the area covered by shapes(png only)
+    #[arg(short, long, default_value_t = false)]
+    crop: bool,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let url = format!(
+        "{}/api/room/{}/export.{}",
+        args.host.trim_end_matches('/'),
+        args.public_id,
+        args.format.extension()
+    );
+    // form query
+    let mut query = vec![("crop", args.crop.to_string())];
+    if let Some(page_id) = args.page_id {
+        query.push(("page_id", page_id));
+    }
+    if let Some(scale) = args.scale {
+        query.push(("scale", scale.to_string()));
+    }
+    if let Some(dpi) = args.dpi {
+        query.push(("dpi", dpi.to_string()));
+    }
+    // fetch the file
+    let res = match Client::new().get(&url).query(&query).send().await {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("cannot connect to the server: {}", e);
+            exit(1);
+        }
+    };
+    let status = res.status();
+    let body = res.bytes().await.unwrap_or_default();
+    if !status.is_success() {
+        eprintln!("{}: {}", status, String::from_utf8_lossy(&body));
+        exit(1);
+    }
+    // save it
+    let output = args
+        .output
+        .unwrap_or(format!("{}.{}", args.public_id, args.format.extension()));
+    if let Err(e) = fs::write(&output, body) {
+        eprintln!("cannot write {}: {}", output, e);
+        exit(1);
+    }
+    println!("saved {}", output);
+}
@@ -0,0 +1,250 @@
+use protocol::board_protocol::{edit::Edit as EditInner, BoardSize, Edit, Page, Shape};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+// constants
+
+pub const FORMAT: &str = "board4you";
+pub const VERSION: u64 = 2;
+/// Shapes' urls with this prefix refer to Archive::assets
+const ASSET_PREFIX: &str = "b4y-asset:";
+
+// errors
+
+pub enum ArchiveError {
+    UnknownFormat,
+    UnsupportedVersion(u64),
+    Malformed(String),
+    MissingAsset(String),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat => write!(f, "file is not a board4you archive"),
+            Self::UnsupportedVersion(v) => write!(f, "archive version {} is not supported", v),
+            Self::Malformed(e) => write!(f, "archive is malformed: {}", e),
+            Self::MissingAsset(id) => write!(f, "asset {} is missing", id),
+        }
+    }
+}
+
+// structs
+
+/// Board archive stored in .b4y.json files
+///
+/// format - always "board4you"
+/// version - version of the format, older versions are migrated forward on import:
+///   1 - raw room initials without format, version, pages and assets
+///   2 - current version
+/// title - board's title
+/// size - canvas size
+/// pages - ordered list of pages, edits refer to them by page_id
+/// current - applied edits in order of their application
+/// undone - undone edits in order of their undoing
+/// assets - data urls of images by their ids, shapes refer to them as "b4y-asset:<id>"
+#[derive(Serialize, Deserialize)]
+pub struct Archive {
+    pub format: Box<str>,
+    pub version: u64,
+    pub title: Box<str>,
+    pub size: BoardSize,
+    pub pages: Vec<Page>,
+    pub current: Vec<Edit>,
+    pub undone: Vec<Edit>,
+    pub assets: BTreeMap<String, String>,
+}
+
+impl Archive {
+    /// Creates an archive moving embedded images to assets
+    pub fn new(
+        title: Box<str>,
+        size: BoardSize,
+        pages: Vec<Page>,
+        mut current: Vec<Edit>,
+        mut undone: Vec<Edit>,
+    ) -> Self {
+        let mut assets = BTreeMap::new();
+        let mut ids: HashMap<String, String> = HashMap::new();
+        for edit in current.iter_mut().chain(undone.iter_mut()) {
+            for_each_shape_mut(edit, |shape| {
+                if !shape.url.starts_with("data:") {
+                    return;
+                }
+                // the same image is stored once
+                let id = match ids.get(&shape.url) {
+                    Some(id) => id.clone(),
+                    None => {
+                        let id = ids.len().to_string();
+                        ids.insert(shape.url.clone(), id.clone());
+                        assets.insert(id.clone(), shape.url.clone());
+                        id
+                    }
+                };
+                shape.url = format!("{ASSET_PREFIX}{id}");
+            });
+        }
+
+        Archive {
+            format: FORMAT.into(),
+            version: VERSION,
+            title,
+            size,
+            pages,
+            current,
+            undone,
+            assets,
+        }
+    }
+
+    /// Parses an archive of any supported version and migrates it to the current one
+    pub fn parse(data: &[u8]) -> Result<Self, ArchiveError> {
+        let value: Value =
+            serde_json::from_slice(data).map_err(|e| ArchiveError::Malformed(e.to_string()))?;
+        let value = migrate(value)?;
+        serde_json::from_value(value).map_err(|e| ArchiveError::Malformed(e.to_string()))
+    }
+
+    /// Puts assets back to the urls of shapes that refer to them
+    pub fn embed_assets(&mut self) -> Result<(), ArchiveError> {
+        let assets = &self.assets;
+        let mut missing = None;
+        for edit in self.current.iter_mut().chain(self.undone.iter_mut()) {
+            for_each_shape_mut(edit, |shape| {
+                if let Some(id) = shape.url.strip_prefix(ASSET_PREFIX) {
+                    match assets.get(id) {
+                        Some(url) => shape.url = url.clone(),
+                        None => missing = Some(id.to_owned()),
+                    }
+                }
+            });
+        }
+        match missing {
+            Some(id) => Err(ArchiveError::MissingAsset(id)),
+            None => Ok(()),
+        }
+    }
+}
+
+// helpers
+
+fn for_each_shape_mut(edit: &mut Edit, mut f: impl FnMut(&mut Shape)) {
+    match &mut edit.edit {
+        Some(EditInner::Add(add)) => {
+            if let Some(shape) = &mut add.shape {
+                f(shape);
+            }
+        }
+        Some(EditInner::Remove(remove)) => remove.shapes.iter_mut().for_each(f),
+        Some(EditInner::Modify(modify)) => {
+            modify
+                .current
+                .iter_mut()
+                .chain(modify.initial.iter_mut())
+                .for_each(f);
+        }
+        None => (),
+    }
+}
+
+/// Applies migrations one by one until the value has the current version
+fn migrate(mut value: Value) -> Result<Value, ArchiveError> {
+    if !value.is_object() {
+        return Err(ArchiveError::Malformed("root is not an object".to_owned()));
+    }
+    let version = match value.get("version") {
+        Some(v) => v.as_u64().ok_or(ArchiveError::UnknownFormat)?,
+        // room initials do not have a version
+        None => 1,
+    };
+    if version > 1 && value.get("format").and_then(|f| f.as_str()) != Some(FORMAT) {
+        return Err(ArchiveError::UnknownFormat);
+    }
+    if version == 0 || version > VERSION {
+        return Err(ArchiveError::UnsupportedVersion(version));
+    }
+    for v in version..VERSION {
+        value = match v {
+            1 => migrate_v1(value),
+            _ => return Err(ArchiveError::UnsupportedVersion(v)),
+        };
+    }
+    Ok(value)
+}
+
+fn migrate_v1(mut value: Value) -> Value {
+    let obj = value.as_object_mut().unwrap();
+    obj.insert("format".to_owned(), json!(FORMAT));
+    obj.insert("version".to_owned(), json!(2));
+    obj.entry("pages").or_insert(json!([]));
+    obj.insert("assets".to_owned(), json!({}));
+    value
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::board_protocol::Add;
+
+    fn get_image_edit_sample(id: &str, url: &str) -> Edit {
+        Edit {
+            edit: Some(EditInner::Add(Add {
+                id: id.to_owned(),
+                shape: Some(Shape {
+                    shape_id: id.to_owned(),
+                    url: url.to_owned(),
+                    ..Default::default()
+                }),
+            })),
+            page_id: String::new(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_migrates_room_initials() {
+        let data = br#"{
+            "current": [{"edit": {"Add": {"id": "1", "shape": {"shape_id": "1"}}}}],
+            "undone": [],
+            "size": {"height": 900, "width": 1720},
+            "title": "old"
+        }"#;
+
+        let archive = Archive::parse(data).unwrap_or_else(|e| panic!("{}", e));
+
+        assert_eq!(archive.version, VERSION);
+        assert_eq!(archive.current.len(), 1);
+        assert!(archive.pages.is_empty());
+    }
+
+    #[test]
+    fn assets_are_deduplicated_and_embedded_back() {
+        let current = vec![
+            get_image_edit_sample("1", "data:image/png;base64,AA=="),
+            get_image_edit_sample("2", "data:image/png;base64,AA=="),
+        ];
+        let archive = Archive::new(
+            "title".into(),
+            BoardSize {
+                height: 1,
+                width: 1,
+            },
+            vec![],
+            current.clone(),
+            vec![],
+        );
+        assert_eq!(archive.assets.len(), 1);
+
+        let data = serde_json::to_vec(&archive).unwrap();
+        let mut archive = Archive::parse(&data).unwrap_or_else(|e| panic!("{}", e));
+        let _ = archive.embed_assets();
+
+        assert_eq!(archive.current, current);
+    }
+}
@@ -0,0 +1,351 @@
+use axum::{async_trait, body::Bytes};
+use data_encoding::BASE64;
+use protocol::board_protocol::{edit::Edit as EditInner, Edit, Shape};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fmt, io, path::PathBuf};
+use tokio::fs;
+
+use crate::{entities::asset, libs::state::DbClient};
+
+// constants
+
+/// Shapes' urls with this prefix refer to stored assets by their hashes
+pub const ASSET_SCHEME: &str = "asset://";
+const HASH_LENGTH: usize = 64;
+
+// errors
+
+pub enum AssetError {
+    UnsupportedFormat,
+    TooLarge,
+    QuotaExceeded,
+    Unauthorized,
+    Internal(String),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedFormat => write!(f, "asset is not a supported image"),
+            Self::TooLarge => write!(f, "asset is too large"),
+            Self::QuotaExceeded => write!(f, "asset quota is exceeded"),
+            Self::Unauthorized => write!(f, "auth to store large images"),
+            Self::Internal(e) => write!(f, "cannot save the asset: {}", e),
+        }
+    }
+}
+
+// backends
+
+/// Storage of asset blobs by their hashes
+#[async_trait]
+pub trait AssetBackend: Send + Sync {
+    /// Saves the blob, does nothing if it is already saved
+    async fn put(&self, hash: &str, data: Bytes) -> io::Result<()>;
+    /// Returns the blob or None if it does not exist
+    async fn get(&self, hash: &str) -> io::Result<Option<Bytes>>;
+    async fn exists(&self, hash: &str) -> io::Result<bool>;
+}
+
+/// Stores blobs as files named by their hashes
+///
+/// root - directory with the files, first two chars of a hash are used as a subdirectory
+pub struct LocalDisk {
+    root: PathBuf,
+}
+
+impl LocalDisk {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalDisk { root: root.into() }
+    }
+
+    fn path(&self, hash: &str) -> PathBuf {
+        self.root.join(&hash[..2]).join(hash)
+    }
+}
+
+#[async_trait]
+impl AssetBackend for LocalDisk {
+    async fn put(&self, hash: &str, data: Bytes) -> io::Result<()> {
+        let path = self.path(hash);
+        if fs::try_exists(&path).await? {
+            return Ok(());
+        }
+        fs::create_dir_all(path.parent().unwrap()).await?;
+        // write to a temporary file first so readers never see a partial blob
+        let tmp_path = path.with_extension(format!("{}.tmp", uuid::Uuid::now_v7()));
+        fs::write(&tmp_path, &data).await?;
+        fs::rename(&tmp_path, &path).await
+    }
+
+    async fn get(&self, hash: &str) -> io::Result<Option<Bytes>> {
+        match fs::read(self.path(hash)).await {
+            Ok(data) => Ok(Some(data.into())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn exists(&self, hash: &str) -> io::Result<bool> {
+        fs::try_exists(self.path(hash)).await
+    }
+}
+
+// functions
+
+pub fn hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+pub fn is_hash(hash: &str) -> bool {
+    hash.len() == HASH_LENGTH
+        && hash
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+/// Returns the hash an asset url refers to or None if the url is not an asset url
+pub fn parse_url(url: &str) -> Option<&str> {
+    url.strip_prefix(ASSET_SCHEME)
+}
+
+/// Returns the mime type of an image by its signature
+pub fn sniff_mime(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some("image/png");
+    }
+    if data.starts_with(b"\xff\xd8\xff") {
+        return Some("image/jpeg");
+    }
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return Some("image/gif");
+    }
+    if data.len() > 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    None
+}
+
+/// Saves the blob and binds it to the user
+///
+/// # Errors
+///
+/// This function will return an error if the blob is not an image,
+/// is larger than max_size or does not fit into the user's quota.
+/// Blobs the user has already uploaded do not count against the quota again
+pub async fn store(
+    backend: &dyn AssetBackend,
+    client: &mut DbClient<'_>,
+    user_id: i32,
+    data: Bytes,
+    max_size: usize,
+    quota: i64,
+) -> Result<String, AssetError> {
+    if sniff_mime(&data).is_none() {
+        return Err(AssetError::UnsupportedFormat);
+    }
+    if data.len() > max_size {
+        return Err(AssetError::TooLarge);
+    }
+    let hash = hash(&data);
+    let internal = |e: tokio_postgres::Error| AssetError::Internal(e.to_string());
+    if asset::is_owned(client, user_id, &hash)
+        .await
+        .map_err(internal)?
+    {
+        return Ok(hash);
+    }
+    // do not save blobs which obviously exceed the quota
+    let usage = asset::read_usage(client, user_id).await.map_err(internal)?;
+    if usage + data.len() as i64 > quota {
+        return Err(AssetError::QuotaExceeded);
+    }
+    let size = data.len() as i32;
+    backend
+        .put(&hash, data)
+        .await
+        .map_err(|e| AssetError::Internal(e.to_string()))?;
+    // the quota is checked again, because other uploads of the user may have finished meanwhile
+    match asset::create(client, user_id, &hash, size, quota)
+        .await
+        .map_err(internal)?
+    {
+        true => Ok(hash),
+        false => Err(AssetError::QuotaExceeded),
+    }
+}
+
+/// Replaces asset urls of shapes with data urls, so the edits can be used outside the server.
+/// Missing assets are left as is
+pub async fn embed(backend: &dyn AssetBackend, edits: &mut [Edit]) {
+    let mut urls: HashMap<String, Option<String>> = HashMap::new();
+    for edit in edits.iter() {
+        for_each_shape(edit, |shape| {
+            if let Some(hash) = parse_url(&shape.url).filter(|hash| is_hash(hash)) {
+                urls.insert(hash.to_owned(), None);
+            }
+        });
+    }
+    for (hash, url) in urls.iter_mut() {
+        if let Ok(Some(data)) = backend.get(hash).await {
+            let mime = sniff_mime(&data).unwrap_or("application/octet-stream");
+            *url = Some(format!("data:{};base64,{}", mime, BASE64.encode(&data)));
+        }
+    }
+    for edit in edits.iter_mut() {
+        for_each_shape_mut(edit, |shape| {
+            if let Some(Some(url)) = parse_url(&shape.url).and_then(|hash| urls.get(hash)) {
+                shape.url = url.clone();
+            }
+        });
+    }
+}
+
+/// Moves embedded images longer than max_length to the store and replaces them with asset urls
+///
+/// # Errors
+///
+/// This function will return an error if there are such images and the user is not authed
+/// or if any of them cannot be stored
+pub async fn store_embedded(
+    backend: &dyn AssetBackend,
+    client: &mut DbClient<'_>,
+    user_id: Option<i32>,
+    edits: &mut [Edit],
+    max_length: usize,
+    max_size: usize,
+    quota: i64,
+) -> Result<(), AssetError> {
+    let mut urls: HashMap<String, String> = HashMap::new();
+    for edit in edits.iter() {
+        for_each_shape(edit, |shape| {
+            if shape.url.len() > max_length && shape.url.starts_with("data:") {
+                urls.insert(shape.url.clone(), String::new());
+            }
+        });
+    }
+    if urls.is_empty() {
+        return Ok(());
+    }
+    let user_id = user_id.ok_or(AssetError::Unauthorized)?;
+    for (data_url, asset_url) in urls.iter_mut() {
+        let data = data_url
+            .split_once(";base64,")
+            .and_then(|(_, data)| BASE64.decode(data.as_bytes()).ok())
+            .ok_or(AssetError::UnsupportedFormat)?;
+        let hash = store(backend, client, user_id, data.into(), max_size, quota).await?;
+        *asset_url = format!("{ASSET_SCHEME}{hash}");
+    }
+    for edit in edits.iter_mut() {
+        for_each_shape_mut(edit, |shape| {
+            if let Some(url) = urls.get(&shape.url) {
+                shape.url = url.clone();
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Removes embedded images longer than max_length, so the edit can be validated before they are stored
+pub fn strip_embedded(edit: &mut Edit, max_length: usize) {
+    for_each_shape_mut(edit, |shape| {
+        if shape.url.len() > max_length && shape.url.starts_with("data:") {
+            shape.url.clear();
+        }
+    });
+}
+
+/// Returns hashes of assets the edit refers to
+pub fn referenced(edit: &Edit) -> Vec<&str> {
+    let mut hashes = Vec::new();
+    for_each_shape(edit, |shape| {
+        if let Some(hash) = parse_url(&shape.url) {
+            hashes.push(hash);
+        }
+    });
+    hashes
+}
+
+// helpers
+
+fn for_each_shape<'a>(edit: &'a Edit, mut f: impl FnMut(&'a Shape)) {
+    match &edit.edit {
+        Some(EditInner::Add(add)) => {
+            if let Some(shape) = &add.shape {
+                f(shape);
+            }
+        }
+        Some(EditInner::Remove(remove)) => remove.shapes.iter().for_each(f),
+        Some(EditInner::Modify(modify)) => {
+            modify
+                .current
+                .iter()
+                .chain(modify.initial.iter())
+                .for_each(f);
+        }
+        None => (),
+    }
+}
+
+fn for_each_shape_mut(edit: &mut Edit, mut f: impl FnMut(&mut Shape)) {
+    match &mut edit.edit {
+        Some(EditInner::Add(add)) => {
+            if let Some(shape) = &mut add.shape {
+                f(shape);
+            }
+        }
+        Some(EditInner::Remove(remove)) => remove.shapes.iter_mut().for_each(f),
+        Some(EditInner::Modify(modify)) => {
+            modify
+                .current
+                .iter_mut()
+                .chain(modify.initial.iter_mut())
+                .for_each(f);
+        }
+        None => (),
+    }
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::board_protocol::Add;
+
+    #[tokio::test]
+    async fn local_disk_deduplicates_and_embeds() {
+        let root = std::env::temp_dir().join(format!("b4y-assets-{}", uuid::Uuid::now_v7()));
+        let backend = LocalDisk::new(&root);
+        let data = Bytes::from_static(b"GIF89a\x01\x00\x01\x00");
+        let hash = hash(&data);
+        backend.put(&hash, data.clone()).await.unwrap();
+        backend.put(&hash, data.clone()).await.unwrap();
+        assert_eq!(std::fs::read_dir(root.join(&hash[..2])).unwrap().count(), 1);
+
+        let mut edits = vec![Edit {
+            edit: Some(EditInner::Add(Add {
+                id: "1".to_owned(),
+                shape: Some(Shape {
+                    url: format!("{ASSET_SCHEME}{hash}"),
+                    ..Default::default()
+                }),
+            })),
+            page_id: String::new(),
+            ..Default::default()
+        }];
+        embed(&backend, &mut edits).await;
+        let _ = std::fs::remove_dir_all(&root);
+
+        match &edits[0].edit {
+            Some(EditInner::Add(add)) => assert!(add
+                .shape
+                .as_ref()
+                .unwrap()
+                .url
+                .starts_with("data:image/gif;base64,")),
+            _ => unreachable!(),
+        }
+    }
+}
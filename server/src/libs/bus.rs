@@ -0,0 +1,325 @@
+use axum::{async_trait, body::Bytes};
+use data_encoding::BASE64;
+use futures::{stream, StreamExt};
+use log::{error, warn};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    time::sleep,
+};
+use tokio_postgres::{AsyncMessage, Client, NoTls};
+
+// constants
+
+/// Postgres rejects notifications with larger payloads,
+/// larger messages are saved in bus_messages and notifications refer to them
+const MAX_NOTIFICATION_LENGTH: usize = 7900;
+const STORED_MESSAGE_PREFIX: &str = "@";
+/// Delays between attempts to reconnect the bus, doubled after every failed attempt
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+// errors
+
+#[derive(Debug)]
+pub enum BusError {
+    Db(String),
+    Malformed,
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Db(e) => write!(f, "bus database error: {}", e),
+            Self::Malformed => write!(f, "bus message is malformed"),
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for BusError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        Self::Db(e.to_string())
+    }
+}
+
+// bus
+
+/// Delivers messages between server nodes by topics
+#[async_trait]
+pub trait Bus: Send + Sync {
+    /// Sends the payload to subscribers of the topic on every node
+    async fn publish(&self, topic: &str, payload: Bytes) -> Result<(), BusError>;
+    /// Returns a receiver of payloads published to the topic,
+    /// the subscription is cancelled when the receiver is dropped
+    async fn subscribe(&self, topic: &str) -> Result<UnboundedReceiver<Bytes>, BusError>;
+}
+
+/// Subscribers by topics
+#[derive(Default)]
+struct Topics {
+    subscribers: Mutex<HashMap<String, Vec<UnboundedSender<Bytes>>>>,
+}
+
+impl Topics {
+    /// Returns true if the topic is new
+    fn subscribe(&self, topic: &str) -> (UnboundedReceiver<Bytes>, bool) {
+        let (tx, rx) = unbounded_channel();
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let is_new = !subscribers.contains_key(topic);
+        subscribers.entry(topic.to_owned()).or_default().push(tx);
+        (rx, is_new)
+    }
+
+    /// Returns topics that have subscribers
+    fn topics(&self) -> Vec<String> {
+        self.subscribers.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Returns false if the topic has no subscribers anymore
+    fn deliver(&self, topic: &str, payload: Bytes) -> bool {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let Some(senders) = subscribers.get_mut(topic) else {
+            return false;
+        };
+        senders.retain(|sender| sender.send(payload.clone()).is_ok());
+        if senders.is_empty() {
+            subscribers.remove(topic);
+            return false;
+        }
+        true
+    }
+}
+
+/// Bus of a single node, used when the app is not clustered and in tests
+#[derive(Default)]
+pub struct LocalBus {
+    topics: Topics,
+}
+
+#[async_trait]
+impl Bus for LocalBus {
+    async fn publish(&self, topic: &str, payload: Bytes) -> Result<(), BusError> {
+        self.topics.deliver(topic, payload);
+        Ok(())
+    }
+
+    async fn subscribe(&self, topic: &str) -> Result<UnboundedReceiver<Bytes>, BusError> {
+        Ok(self.topics.subscribe(topic).0)
+    }
+}
+
+/// Bus based on LISTEN/NOTIFY of Postgres
+///
+/// client - dedicated connection, pooled ones cannot listen,
+/// it is replaced when the connection is lost and restored
+pub struct PgBus {
+    client: Arc<Mutex<Arc<Client>>>,
+    topics: Arc<Topics>,
+}
+
+impl PgBus {
+    pub async fn connect(config: &str) -> Result<Self, BusError> {
+        let topics = Arc::new(Topics::default());
+        let (client, lost) = PgBus::open(config, topics.clone()).await?;
+        let client = Arc::new(Mutex::new(client));
+        tokio::spawn(PgBus::reconnect(
+            config.to_owned(),
+            Arc::downgrade(&client),
+            topics.clone(),
+            lost,
+        ));
+        Ok(PgBus { client, topics })
+    }
+
+    /// Opens a connection delivering notifications to the topics,
+    /// the receiver gets a message when the connection is lost
+    async fn open(
+        config: &str,
+        topics: Arc<Topics>,
+    ) -> Result<(Arc<Client>, oneshot::Receiver<()>), BusError> {
+        let (client, mut connection) = tokio_postgres::connect(config, NoTls).await?;
+        let client = Arc::new(client);
+        // drive the connection and collect notifications
+        let (tx, mut rx) = unbounded_channel();
+        let (lost_tx, lost) = oneshot::channel();
+        tokio::spawn(async move {
+            let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+            while let Some(message) = messages.next().await {
+                match message {
+                    Ok(AsyncMessage::Notification(n)) => {
+                        let _ = tx.send((n.channel().to_owned(), n.payload().to_owned()));
+                    }
+                    Ok(_) => (),
+                    Err(e) => {
+                        error!("bus connection is lost: {}", e);
+                        break;
+                    }
+                }
+            }
+            let _ = lost_tx.send(());
+        });
+        // deliver notifications
+        let delivery_client = client.clone();
+        tokio::spawn(async move {
+            while let Some((topic, payload)) = rx.recv().await {
+                let payload = match PgBus::decode(&delivery_client, &payload).await {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        warn!("cannot read message of topic {}: {}", topic, e);
+                        continue;
+                    }
+                };
+                if !topics.deliver(&topic, payload) {
+                    let _ = delivery_client
+                        .batch_execute(&format!("UNLISTEN {}", PgBus::quote(&topic)))
+                        .await;
+                }
+            }
+        });
+        Ok((client, lost))
+    }
+
+    /// Opens a new connection every time the current one is lost and listens to the subscribed topics again.
+    /// Stops when the bus is dropped
+    async fn reconnect(
+        config: String,
+        client: Weak<Mutex<Arc<Client>>>,
+        topics: Arc<Topics>,
+        mut lost: oneshot::Receiver<()>,
+    ) {
+        loop {
+            let _ = (&mut lost).await;
+            let mut delay = MIN_RECONNECT_DELAY;
+            lost = loop {
+                if client.strong_count() == 0 {
+                    return;
+                }
+                sleep(delay).await;
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                let (new_client, lost) = match PgBus::open(&config, topics.clone()).await {
+                    Ok(opened) => opened,
+                    Err(e) => {
+                        warn!("cannot reconnect the bus: {}", e);
+                        continue;
+                    }
+                };
+                if let Err(e) = PgBus::listen(&new_client, &topics.topics()).await {
+                    warn!("cannot subscribe the reconnected bus: {}", e);
+                    continue;
+                }
+                match client.upgrade() {
+                    Some(client) => *client.lock().unwrap() = new_client,
+                    None => return,
+                }
+                break lost;
+            };
+        }
+    }
+
+    async fn listen(client: &Client, topics: &[String]) -> Result<(), BusError> {
+        for topic in topics {
+            client
+                .batch_execute(&format!("LISTEN {}", PgBus::quote(topic)))
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn client(&self) -> Arc<Client> {
+        self.client.lock().unwrap().clone()
+    }
+
+    async fn decode(client: &Client, payload: &str) -> Result<Bytes, BusError> {
+        match payload.strip_prefix(STORED_MESSAGE_PREFIX) {
+            Some(id) => {
+                let id: i64 = id.parse().map_err(|_| BusError::Malformed)?;
+                let row = client
+                    .query_one("SELECT payload FROM bus_messages WHERE id = ($1)", &[&id])
+                    .await?;
+                Ok(Bytes::from(row.get::<_, Vec<u8>>("payload")))
+            }
+            None => BASE64
+                .decode(payload.as_bytes())
+                .map(Bytes::from)
+                .map_err(|_| BusError::Malformed),
+        }
+    }
+
+    fn quote(topic: &str) -> String {
+        format!("\"{}\"", topic.replace('"', "\"\""))
+    }
+}
+
+#[async_trait]
+impl Bus for PgBus {
+    async fn publish(&self, topic: &str, payload: Bytes) -> Result<(), BusError> {
+        let client = self.client();
+        let mut encoded = BASE64.encode(&payload);
+        if encoded.len() > MAX_NOTIFICATION_LENGTH {
+            // messages are read right after they are published, so old ones are not needed
+            client
+                .execute(
+                    "DELETE FROM bus_messages WHERE created_at < LOCALTIMESTAMP - interval '1 minute'",
+                    &[],
+                )
+                .await?;
+            let row = client
+                .query_one(
+                    "INSERT INTO bus_messages (payload) VALUES (($1)) RETURNING id",
+                    &[&payload.as_ref()],
+                )
+                .await?;
+            encoded = format!("{}{}", STORED_MESSAGE_PREFIX, row.get::<_, i64>("id"));
+        }
+        client
+            .execute("SELECT pg_notify(($1), ($2))", &[&topic, &encoded])
+            .await?;
+        Ok(())
+    }
+
+    async fn subscribe(&self, topic: &str) -> Result<UnboundedReceiver<Bytes>, BusError> {
+        let (rx, is_new) = self.topics.subscribe(topic);
+        if is_new {
+            PgBus::listen(&self.client(), &[topic.to_owned()]).await?;
+        }
+        Ok(rx)
+    }
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn local_bus_delivers_to_subscribers_of_topic() {
+        let bus = LocalBus::default();
+        let mut first = bus.subscribe("a").await.unwrap();
+        let mut second = bus.subscribe("a").await.unwrap();
+        let mut other = bus.subscribe("b").await.unwrap();
+
+        bus.publish("a", Bytes::from_static(b"msg")).await.unwrap();
+        assert_eq!(first.recv().await, Some(Bytes::from_static(b"msg")));
+        assert_eq!(second.recv().await, Some(Bytes::from_static(b"msg")));
+        assert!(other.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn dropped_receivers_are_unsubscribed() {
+        let bus = LocalBus::default();
+        let first = bus.subscribe("a").await.unwrap();
+        drop(first);
+
+        assert!(!bus.topics.deliver("a", Bytes::new()));
+        assert!(bus.topics.subscribers.lock().unwrap().is_empty());
+    }
+}
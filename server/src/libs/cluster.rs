@@ -0,0 +1,864 @@
+use super::{
+    auth::UserData,
+    bus::{Bus, BusError},
+    room::{RestoreError, RoomChannel, UserChannel, UserMessage},
+    state::{BoardSnapshot, DbClient, EditAuthorship, Rooms},
+};
+use crate::{
+    entities::{invite::Invite, lease, role::BoardRole},
+    websocket::{decode_error_message, handle_message, user_queue, MessageError},
+    LEASE_TTL, NEXT_USER_ID,
+};
+use axum::{body::Bytes, http::StatusCode};
+use data_encoding::BASE64;
+use log::{error, warn};
+use protocol::{
+    board_protocol::{Edit, Page},
+    decode_user_msg,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    select,
+    sync::{mpsc::WeakSender, oneshot},
+    time::{interval, timeout},
+};
+use uuid::Uuid;
+
+// constants
+
+/// Time a node waits for the reply of the room hosted by another node
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// errors
+
+#[derive(Debug)]
+pub enum RequestError {
+    Bus(BusError),
+    Timeout,
+    /// the room has stopped before replying
+    Closed,
+}
+
+impl RequestError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Bus(_) => StatusCode::BAD_GATEWAY,
+            Self::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            Self::Closed => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bus(e) => write!(f, "{}", e),
+            Self::Timeout => write!(f, "the room has not replied in time"),
+            Self::Closed => write!(f, "the room has stopped"),
+        }
+    }
+}
+
+impl From<BusError> for RequestError {
+    fn from(e: BusError) -> Self {
+        Self::Bus(e)
+    }
+}
+
+// requests
+
+/// Requests of REST handlers to a room, the room may be hosted by another node
+#[derive(Serialize, Deserialize)]
+pub enum RoomRequest {
+    GetCoEditorToken {
+        private_id: Box<str>,
+    },
+    GetUpdatedCoEditorToken {
+        private_id: Box<str>,
+    },
+    VerifyCoEditorToken {
+        token: Box<str>,
+    },
+    SetUndoMode {
+        private_id: Box<str>,
+        per_author: bool,
+    },
+    GetAuthorship {
+        shape_id: Box<str>,
+        private_id: Option<Box<str>>,
+        db_id: Option<i32>,
+    },
+    GetSnapshot,
+    RestoreCheckpoint {
+        private_id: Box<str>,
+        pages: Vec<Page>,
+        current: Vec<Edit>,
+    },
+    DeleteRoom {
+        private_id: Box<str>,
+    },
+    SetRole {
+        db_id: i32,
+        role: Option<BoardRole>,
+    },
+    AddInvite(Invite),
+    RevokeInvite {
+        id: i32,
+    },
+}
+
+/// Replies of the room to RoomRequest, Done is sent to requests that only change the room
+#[derive(Serialize, Deserialize)]
+pub enum RoomReply {
+    Token(Result<Box<str>, ()>),
+    Done(bool),
+    Authorship(Option<Vec<EditAuthorship>>),
+    Snapshot(BoardSnapshot),
+    Restored(Result<usize, RestoreError>),
+}
+
+impl RoomRequest {
+    /// Passes the request to the room, returns None if the room has stopped
+    async fn handle(self, room: &RoomChannel) -> Option<RoomReply> {
+        match self {
+            Self::GetCoEditorToken { private_id } => {
+                let (sender, rx) = oneshot::channel();
+                let msg = UserMessage::GetCoEditorToken { private_id, sender };
+                room.send(msg).await.ok()?;
+                rx.await.ok().map(RoomReply::Token)
+            }
+            Self::GetUpdatedCoEditorToken { private_id } => {
+                let (sender, rx) = oneshot::channel();
+                let msg = UserMessage::GetUpdatedCoEditorToken { private_id, sender };
+                room.send(msg).await.ok()?;
+                rx.await.ok().map(RoomReply::Token)
+            }
+            Self::VerifyCoEditorToken { token } => {
+                let (sender, rx) = oneshot::channel();
+                let msg = UserMessage::VerifyCoEditorToken { token, sender };
+                room.send(msg).await.ok()?;
+                rx.await.ok().map(RoomReply::Done)
+            }
+            Self::SetUndoMode {
+                private_id,
+                per_author,
+            } => {
+                let (sender, rx) = oneshot::channel();
+                let msg = UserMessage::SetUndoMode {
+                    private_id,
+                    per_author,
+                    sender,
+                };
+                room.send(msg).await.ok()?;
+                rx.await.ok().map(RoomReply::Done)
+            }
+            Self::GetAuthorship {
+                shape_id,
+                private_id,
+                db_id,
+            } => {
+                let (sender, rx) = oneshot::channel();
+                let msg = UserMessage::GetAuthorship {
+                    shape_id,
+                    private_id,
+                    db_id,
+                    sender,
+                };
+                room.send(msg).await.ok()?;
+                rx.await.ok().map(RoomReply::Authorship)
+            }
+            Self::GetSnapshot => {
+                let (sender, rx) = oneshot::channel();
+                room.send(UserMessage::GetSnapshot(sender)).await.ok()?;
+                rx.await.ok().map(RoomReply::Snapshot)
+            }
+            Self::RestoreCheckpoint {
+                private_id,
+                pages,
+                current,
+            } => {
+                let (sender, rx) = oneshot::channel();
+                let msg = UserMessage::RestoreCheckpoint {
+                    private_id,
+                    pages,
+                    current,
+                    sender,
+                };
+                room.send(msg).await.ok()?;
+                rx.await.ok().map(RoomReply::Restored)
+            }
+            Self::DeleteRoom { private_id } => {
+                let (deleted, rx) = oneshot::channel();
+                let msg = UserMessage::DeleteRoom {
+                    deleted,
+                    private_id,
+                };
+                room.send(msg).await.ok()?;
+                rx.await.ok().map(RoomReply::Done)
+            }
+            Self::SetRole { db_id, role } => {
+                let msg = UserMessage::SetRole {
+                    db_id,
+                    role: role.map(Into::into),
+                };
+                room.send(msg).await.ok()?;
+                Some(RoomReply::Done(true))
+            }
+            Self::AddInvite(invite) => {
+                room.send(UserMessage::AddInvite(invite)).await.ok()?;
+                Some(RoomReply::Done(true))
+            }
+            Self::RevokeInvite { id } => {
+                room.send(UserMessage::RevokeInvite { id }).await.ok()?;
+                Some(RoomReply::Done(true))
+            }
+        }
+    }
+}
+
+// envelopes
+
+/// Messages to the node hosting a room from nodes its users are connected to.
+/// user_id - id of the user on the node the user is connected to
+#[derive(Serialize, Deserialize)]
+enum RoomEnvelope {
+    Join {
+        node: Box<str>,
+        user_id: usize,
+        user_data: Option<UserData>,
+        resume: Option<(Box<str>, u64)>,
+    },
+    Frame {
+        node: Box<str>,
+        user_id: usize,
+        frame: String,
+    },
+    Heartbeat {
+        node: Box<str>,
+        user_id: usize,
+        missed: u32,
+    },
+    Quit {
+        node: Box<str>,
+        user_id: usize,
+    },
+    /// request_id - id of the request on the node waiting for the reply
+    Request {
+        node: Box<str>,
+        request_id: u64,
+        request: RoomRequest,
+    },
+}
+
+/// Messages to users connected to the node from rooms hosted by other nodes
+/// and replies to requests of the node
+#[derive(Serialize, Deserialize)]
+enum NodeEnvelope {
+    Message {
+        user_id: usize,
+        msg: String,
+    },
+    Close {
+        user_id: usize,
+    },
+    Reply {
+        request_id: u64,
+        reply: Option<RoomReply>,
+    },
+}
+
+fn room_topic(public_id: Uuid) -> String {
+    format!("room:{}", public_id)
+}
+
+fn node_topic(node_id: &str) -> String {
+    format!("node:{}", node_id)
+}
+
+// cluster
+
+/// The node's part of the cluster, every room is hosted by the node holding its lease
+/// and users connected to other nodes are relayed to it through the bus
+///
+/// relayed - local users of rooms hosted by other nodes by their ids
+/// requests - senders of replies to requests sent to other nodes by ids of the requests
+pub struct Cluster {
+    node_id: Box<str>,
+    bus: Box<dyn Bus>,
+    relayed: Mutex<HashMap<usize, RelayedUser>>,
+    requests: Mutex<HashMap<u64, oneshot::Sender<Option<RoomReply>>>>,
+    next_request_id: AtomicU64,
+}
+
+/// Local user of a room hosted by another node
+///
+/// host - id of the node hosting the room
+struct RelayedUser {
+    queue: UserChannel,
+    public_id: Uuid,
+    host: Box<str>,
+}
+
+impl Cluster {
+    pub fn new(node_id: Box<str>, bus: Box<dyn Bus>) -> Self {
+        Cluster {
+            node_id,
+            bus,
+            relayed: Mutex::new(HashMap::new()),
+            requests: Mutex::new(HashMap::new()),
+            next_request_id: AtomicU64::new(1),
+        }
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Delivers messages of rooms hosted by other nodes to local users, runs while the app works
+    pub async fn relay_task(&'static self) {
+        let mut rx = match self.bus.subscribe(&node_topic(&self.node_id)).await {
+            Ok(rx) => rx,
+            Err(e) => {
+                error!("cannot subscribe to the node's topic: {}", e);
+                return;
+            }
+        };
+        while let Some(payload) = rx.recv().await {
+            let envelope = match serde_json::from_slice::<NodeEnvelope>(&payload) {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    warn!("malformed node envelope: {}", e);
+                    continue;
+                }
+            };
+            match envelope {
+                NodeEnvelope::Message { user_id, msg } => {
+                    let msg = match BASE64.decode(msg.as_bytes()) {
+                        Ok(msg) => msg,
+                        Err(_) => continue,
+                    };
+                    if let Some(user) = self.relayed.lock().unwrap().get(&user_id) {
+                        user.queue.push(Bytes::from(msg), None);
+                    }
+                }
+                NodeEnvelope::Close { user_id } => {
+                    if let Some(user) = self.relayed.lock().unwrap().get(&user_id) {
+                        user.queue.close();
+                    }
+                }
+                NodeEnvelope::Reply { request_id, reply } => {
+                    if let Some(sender) = self.requests.lock().unwrap().remove(&request_id) {
+                        let _ = sender.send(reply);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Passes messages of users connected to other nodes to the room hosted by the node.
+    /// The task ends with the room, so it does not keep the room loaded.
+    /// rooms - rooms loaded by the node, the room is removed from them if another node deletes it
+    pub async fn host_task(
+        &'static self,
+        public_id: Uuid,
+        room: WeakSender<UserMessage>,
+        rooms: Rooms,
+    ) {
+        let mut rx = match self.bus.subscribe(&room_topic(public_id)).await {
+            Ok(rx) => rx,
+            Err(e) => {
+                error!("cannot subscribe to topic of room {}: {}", public_id, e);
+                return;
+            }
+        };
+        // ids and queues of users in the room by their nodes and ids on them
+        let mut users: HashMap<(Box<str>, usize), (usize, UserChannel)> = HashMap::new();
+        let mut check = interval(*LEASE_TTL);
+        loop {
+            let payload = select! {
+                payload = rx.recv() => match payload {
+                    Some(payload) => payload,
+                    None => break,
+                },
+                _ = check.tick() => {
+                    if room.strong_count() == 0 {
+                        break;
+                    }
+                    continue;
+                },
+            };
+            let Some(room) = room.upgrade() else {
+                break;
+            };
+            let envelope = match serde_json::from_slice::<RoomEnvelope>(&payload) {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    warn!("malformed room envelope: {}", e);
+                    continue;
+                }
+            };
+            match envelope {
+                RoomEnvelope::Join {
+                    node,
+                    user_id,
+                    user_data,
+                    resume,
+                } => {
+                    let local_id = NEXT_USER_ID.fetch_add(1, Ordering::Relaxed);
+                    let queue = user_queue();
+                    tokio::spawn(self.forward(queue.clone(), node.clone(), user_id));
+                    users.insert((node, user_id), (local_id, queue.clone()));
+                    let _ = room
+                        .send(UserMessage::Join {
+                            user_id: local_id,
+                            chan: queue,
+                            user_data,
+                            resume,
+                        })
+                        .await;
+                }
+                RoomEnvelope::Frame {
+                    node,
+                    user_id,
+                    frame,
+                } => {
+                    let Some((local_id, queue)) = users.get(&(node, user_id)) else {
+                        continue;
+                    };
+                    let msg = BASE64
+                        .decode(frame.as_bytes())
+                        .ok()
+                        .and_then(|frame| decode_user_msg(&frame).ok());
+                    match msg {
+                        Some(msg) => match handle_message(&room, *local_id, msg).await {
+                            Ok(()) => (),
+                            Err(MessageError::Decode(request_id)) => {
+                                queue.push(decode_error_message(&request_id), None);
+                            }
+                            Err(MessageError::Closed(_)) => break,
+                        },
+                        None => {
+                            queue.push(decode_error_message(""), None);
+                        }
+                    }
+                }
+                RoomEnvelope::Heartbeat {
+                    node,
+                    user_id,
+                    missed,
+                } => {
+                    if let Some((local_id, _)) = users.get(&(node, user_id)) {
+                        let _ = room
+                            .send(UserMessage::Heartbeat {
+                                user_id: *local_id,
+                                missed,
+                            })
+                            .await;
+                    }
+                }
+                RoomEnvelope::Quit { node, user_id } => {
+                    if let Some((local_id, queue)) = users.remove(&(node, user_id)) {
+                        queue.close();
+                        let _ = room.send(UserMessage::Quit { user_id: local_id }).await;
+                    }
+                }
+                RoomEnvelope::Request {
+                    node,
+                    request_id,
+                    request,
+                } => {
+                    // replies may take time, messages of users are not held meanwhile
+                    let rooms = rooms.clone();
+                    tokio::spawn(async move {
+                        let deletes = matches!(request, RoomRequest::DeleteRoom { .. });
+                        let reply = request.handle(&room).await;
+                        // the deleted room has stopped, so it cannot be joined anymore
+                        if deletes && matches!(reply, Some(RoomReply::Done(true))) {
+                            rooms.remove(&public_id);
+                        }
+                        let envelope = NodeEnvelope::Reply { request_id, reply };
+                        if let Err(e) = self.publish(&node_topic(&node), &envelope).await {
+                            warn!("cannot reply to node {}: {}", node, e);
+                        }
+                    });
+                }
+            }
+        }
+        // the room is unloaded, so its users should reconnect
+        for (_, queue) in users.into_values() {
+            queue.close();
+        }
+    }
+
+    /// Sends messages of the room to the user's node until the queue is closed
+    async fn forward(&'static self, queue: UserChannel, node: Box<str>, user_id: usize) {
+        let topic = node_topic(&node);
+        while let Some(msg) = queue.pop().await {
+            let envelope = NodeEnvelope::Message {
+                user_id,
+                msg: BASE64.encode(&msg),
+            };
+            if let Err(e) = self.publish(&topic, &envelope).await {
+                warn!("cannot send a message to node {}: {}", node, e);
+            }
+        }
+        let _ = self.publish(&topic, &NodeEnvelope::Close { user_id }).await;
+    }
+
+    /// Disconnects local users of rooms which hosts have lost their leases
+    pub async fn check_hosts(&self, client: &DbClient<'_>) {
+        let rooms: HashSet<(Uuid, Box<str>)> = self
+            .relayed
+            .lock()
+            .unwrap()
+            .values()
+            .map(|user| (user.public_id, user.host.clone()))
+            .collect();
+        for (public_id, host) in rooms {
+            let holder = match lease::holder(client, public_id).await {
+                Ok(holder) => holder,
+                Err(e) => {
+                    error!("cannot read lease of room {}: {}", public_id, e);
+                    continue;
+                }
+            };
+            if holder.as_deref() == Some(&host) {
+                continue;
+            }
+            warn!("node {} does not host room {} anymore", host, public_id);
+            for user in self.relayed.lock().unwrap().values() {
+                if user.public_id == public_id {
+                    user.queue.close();
+                }
+            }
+        }
+    }
+
+    async fn publish<T: Serialize>(&self, topic: &str, envelope: &T) -> Result<(), BusError> {
+        let payload = serde_json::to_vec(envelope).map_err(|_| BusError::Malformed)?;
+        self.bus.publish(topic, Bytes::from(payload)).await
+    }
+}
+
+// links
+
+/// Room a connection is bound to
+#[derive(Clone)]
+pub enum RoomLink {
+    Local(RoomChannel),
+    Remote(RemoteRoom),
+}
+
+impl RoomLink {
+    pub async fn join(
+        &self,
+        user_id: usize,
+        chan: UserChannel,
+        user_data: Option<UserData>,
+        resume: Option<(Box<str>, u64)>,
+    ) {
+        match self {
+            Self::Local(room) => {
+                let _ = room
+                    .send(UserMessage::Join {
+                        user_id,
+                        chan,
+                        user_data,
+                        resume,
+                    })
+                    .await;
+            }
+            Self::Remote(room) => room.join(user_id, chan, user_data, resume).await,
+        }
+    }
+
+    pub async fn heartbeat(&self, user_id: usize, missed: u32) {
+        match self {
+            Self::Local(room) => {
+                let _ = room.send(UserMessage::Heartbeat { user_id, missed }).await;
+            }
+            Self::Remote(room) => room
+                .send(RoomEnvelope::Heartbeat {
+                    node: room.cluster.node_id.clone(),
+                    user_id,
+                    missed,
+                })
+                .await
+                .unwrap_or_else(|e| warn!("cannot send a heartbeat: {}", e)),
+        }
+    }
+
+    pub async fn quit(&self, user_id: usize) {
+        match self {
+            Self::Local(room) => {
+                let _ = room.send(UserMessage::Quit { user_id }).await;
+            }
+            Self::Remote(room) => room.quit(user_id).await,
+        }
+    }
+
+    pub async fn request(&self, request: RoomRequest) -> Result<RoomReply, RequestError> {
+        match self {
+            Self::Local(room) => request.handle(room).await.ok_or(RequestError::Closed),
+            Self::Remote(room) => room.request(request).await,
+        }
+    }
+}
+
+/// Room hosted by another node
+///
+/// host - id of the node
+#[derive(Clone)]
+pub struct RemoteRoom {
+    cluster: &'static Cluster,
+    public_id: Uuid,
+    host: Box<str>,
+}
+
+impl RemoteRoom {
+    pub fn new(cluster: &'static Cluster, public_id: Uuid, host: Box<str>) -> Self {
+        RemoteRoom {
+            cluster,
+            public_id,
+            host,
+        }
+    }
+
+    async fn join(
+        &self,
+        user_id: usize,
+        chan: UserChannel,
+        user_data: Option<UserData>,
+        resume: Option<(Box<str>, u64)>,
+    ) {
+        self.cluster.relayed.lock().unwrap().insert(
+            user_id,
+            RelayedUser {
+                queue: chan,
+                public_id: self.public_id,
+                host: self.host.clone(),
+            },
+        );
+        let envelope = RoomEnvelope::Join {
+            node: self.cluster.node_id.clone(),
+            user_id,
+            user_data,
+            resume,
+        };
+        if let Err(e) = self.send(envelope).await {
+            warn!("cannot join room {}: {}", self.public_id, e);
+        }
+    }
+
+    /// Passes the user's message to the host without decoding it
+    pub async fn frame(&self, user_id: usize, frame: &[u8]) -> Result<(), BusError> {
+        self.send(RoomEnvelope::Frame {
+            node: self.cluster.node_id.clone(),
+            user_id,
+            frame: BASE64.encode(frame),
+        })
+        .await
+    }
+
+    async fn quit(&self, user_id: usize) {
+        self.cluster.relayed.lock().unwrap().remove(&user_id);
+        let envelope = RoomEnvelope::Quit {
+            node: self.cluster.node_id.clone(),
+            user_id,
+        };
+        if let Err(e) = self.send(envelope).await {
+            warn!("cannot leave room {}: {}", self.public_id, e);
+        }
+    }
+
+    /// Sends the request to the host and waits for the reply
+    async fn request(&self, request: RoomRequest) -> Result<RoomReply, RequestError> {
+        let cluster = self.cluster;
+        let request_id = cluster.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        cluster.requests.lock().unwrap().insert(request_id, tx);
+        let envelope = RoomEnvelope::Request {
+            node: cluster.node_id.clone(),
+            request_id,
+            request,
+        };
+        let res = match self.send(envelope).await {
+            Ok(()) => match timeout(REQUEST_TIMEOUT, rx).await {
+                Ok(Ok(Some(reply))) => Ok(reply),
+                Ok(Ok(None)) | Ok(Err(_)) => Err(RequestError::Closed),
+                Err(_) => Err(RequestError::Timeout),
+            },
+            Err(e) => Err(e.into()),
+        };
+        cluster.requests.lock().unwrap().remove(&request_id);
+        res
+    }
+
+    async fn send(&self, envelope: RoomEnvelope) -> Result<(), BusError> {
+        self.cluster
+            .publish(&room_topic(self.public_id), &envelope)
+            .await
+    }
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::bus::LocalBus;
+    use protocol::{
+        board_protocol::{user_message, Role, SetCursor, UserMessage as Message},
+        encode_user_msg,
+    };
+    use tokio::sync::mpsc::channel;
+
+    fn get_cluster_sample(node_id: &str, bus: &'static LocalBus) -> &'static Cluster {
+        Box::leak(Box::new(Cluster::new(node_id.into(), Box::new(Relay(bus)))))
+    }
+
+    /// Lets nodes of a test share the same bus
+    struct Relay(&'static LocalBus);
+
+    #[axum::async_trait]
+    impl Bus for Relay {
+        async fn publish(&self, topic: &str, payload: Bytes) -> Result<(), BusError> {
+            self.0.publish(topic, payload).await
+        }
+
+        async fn subscribe(
+            &self,
+            topic: &str,
+        ) -> Result<tokio::sync::mpsc::UnboundedReceiver<Bytes>, BusError> {
+            self.0.subscribe(topic).await
+        }
+    }
+
+    #[tokio::test]
+    async fn users_of_other_nodes_join_hosted_rooms() {
+        let bus: &'static LocalBus = Box::leak(Box::default());
+        let host = get_cluster_sample("a", bus);
+        let node = get_cluster_sample("b", bus);
+        let public_id = Uuid::now_v7();
+        // the host's room
+        let (room_tx, mut room_rx) = channel(8);
+        tokio::spawn(host.host_task(public_id, room_tx.downgrade(), Rooms::default()));
+        tokio::spawn(node.relay_task());
+        tokio::task::yield_now().await;
+        // the user connects to another node
+        let room = RoomLink::Remote(RemoteRoom::new(node, public_id, "a".into()));
+        let queue = user_queue();
+        room.join(7, queue.clone(), None, None).await;
+        let chan = match room_rx.recv().await {
+            Some(UserMessage::Join { chan, resume, .. }) => {
+                assert!(resume.is_none());
+                chan
+            }
+            _ => panic!("user has not joined"),
+        };
+        // the room's messages reach the user
+        chan.push(Bytes::from_static(b"msg"), None);
+        assert_eq!(queue.pop().await, Some(Bytes::from_static(b"msg")));
+        // the user's messages reach the room
+        let msg = encode_user_msg(Message {
+            msg: Some(user_message::Msg::SetCursor(SetCursor { x: 1.0, y: 2.0 })),
+            ..Default::default()
+        });
+        if let RoomLink::Remote(remote) = &room {
+            remote.frame(7, &msg).await.unwrap();
+        }
+        assert!(matches!(
+            room_rx.recv().await,
+            Some(UserMessage::SetCursor { x, y, .. }) if (x, y) == (1.0, 2.0)
+        ));
+        // the user leaves
+        room.quit(7).await;
+        assert!(matches!(
+            room_rx.recv().await,
+            Some(UserMessage::Quit { .. })
+        ));
+        assert!(node.relayed.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn requests_reach_rooms_of_other_nodes() {
+        let bus: &'static LocalBus = Box::leak(Box::default());
+        let host = get_cluster_sample("a", bus);
+        let node = get_cluster_sample("b", bus);
+        let public_id = Uuid::now_v7();
+        // the host's room
+        let (room_tx, mut room_rx) = channel(8);
+        tokio::spawn(host.host_task(public_id, room_tx.downgrade(), Rooms::default()));
+        tokio::spawn(node.relay_task());
+        tokio::task::yield_now().await;
+        let room = RoomLink::Remote(RemoteRoom::new(node, public_id, "a".into()));
+        // control messages are passed to the room
+        let request = RoomRequest::SetRole {
+            db_id: 3,
+            role: Some(BoardRole::Editor),
+        };
+        assert!(matches!(
+            room.request(request).await,
+            Ok(RoomReply::Done(true))
+        ));
+        assert!(matches!(
+            room_rx.recv().await,
+            Some(UserMessage::SetRole {
+                db_id: 3,
+                role: Some(Role::Editor)
+            })
+        ));
+        // replies of the room reach the requesting node
+        let request = RoomRequest::SetUndoMode {
+            private_id: "private".into(),
+            per_author: true,
+        };
+        let reply = tokio::spawn(async move { room.request(request).await });
+        match room_rx.recv().await {
+            Some(UserMessage::SetUndoMode {
+                per_author: true,
+                sender,
+                ..
+            }) => sender.send(true).unwrap(),
+            _ => panic!("the room has not received the request"),
+        }
+        assert!(matches!(reply.await.unwrap(), Ok(RoomReply::Done(true))));
+        assert!(node.requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rooms_deleted_by_other_nodes_are_unregistered() {
+        let bus: &'static LocalBus = Box::leak(Box::default());
+        let host = get_cluster_sample("a", bus);
+        let node = get_cluster_sample("b", bus);
+        let public_id = Uuid::now_v7();
+        // the host's room
+        let rooms = Rooms::default();
+        let (room_tx, mut room_rx) = channel(8);
+        tokio::spawn(host.host_task(public_id, room_tx.downgrade(), rooms.clone()));
+        rooms.insert(public_id, room_tx);
+        tokio::spawn(node.relay_task());
+        tokio::task::yield_now().await;
+        let room = RoomLink::Remote(RemoteRoom::new(node, public_id, "a".into()));
+
+        let request = RoomRequest::DeleteRoom {
+            private_id: "private".into(),
+        };
+        let reply = tokio::spawn(async move { room.request(request).await });
+        match room_rx.recv().await {
+            Some(UserMessage::DeleteRoom { deleted, .. }) => deleted.send(true).unwrap(),
+            _ => panic!("the room has not received the request"),
+        }
+        assert!(matches!(reply.await.unwrap(), Ok(RoomReply::Done(true))));
+        assert!(rooms.get(&public_id).is_none());
+    }
+}
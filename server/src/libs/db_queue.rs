@@ -1,5 +1,5 @@
 use log::{debug, error};
-use protocol::board_protocol::BoardSize;
+use protocol::board_protocol::{BoardSize, Page};
 use std::time::Duration;
 use tokio::{
     select,
@@ -31,9 +31,12 @@ pub struct EditUpdateChunk {
     pub ready: oneshot::Sender<()>,
 }
 
+/// status - delete edits with any status if None
+/// page_id - delete edits of all pages if None
 pub struct EditDeleteChunk {
     pub public_id: Uuid,
-    pub status: EditStatus,
+    pub status: Option<EditStatus>,
+    pub page_id: Option<Box<str>>,
     pub ready: oneshot::Sender<()>,
 }
 
@@ -47,8 +50,10 @@ pub struct EditReadChunk {
 pub struct BoardCreateChunk {
     pub public_id: Uuid,
     pub private_id: Box<str>,
+    pub co_editor_private_id: Box<str>,
     pub owner_id: Option<i32>,
     pub title: Box<str>,
+    pub pages: Vec<Page>,
     pub ready: oneshot::Sender<()>,
 }
 
@@ -56,6 +61,7 @@ pub struct BoardUpdateChunk {
     pub public_id: Uuid,
     pub title: Box<str>,
     pub size: BoardSize,
+    pub seq: u64,
     pub ready: oneshot::Sender<()>,
 }
 
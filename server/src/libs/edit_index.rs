@@ -0,0 +1,315 @@
+use protocol::board_protocol::{edit::Edit as EditInner, Edit, ErrorCode};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use super::{
+    spatial_index::edit_shapes,
+    state::{CommandName, ExposeId},
+};
+use crate::entities::edit::{EditState, EditStatus};
+
+// errors
+
+#[derive(Debug, PartialEq)]
+pub enum CommandError {
+    NotFound,
+    BeyondHorizon,
+    ForeignEdit,
+}
+
+impl CommandError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::NotFound => ErrorCode::EditNotFound,
+            Self::BeyondHorizon => ErrorCode::BeyondUndoHorizon,
+            Self::ForeignEdit => ErrorCode::ForeignEdit,
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "edit does not exist"),
+            Self::BeyondHorizon => write!(f, "edit is beyond the undo horizon"),
+            Self::ForeignEdit => write!(f, "edit belongs to another user"),
+        }
+    }
+}
+
+// index
+
+/// status - whether the edit is applied
+/// page_id - page of the edit, used to forget edits of deleted pages
+/// author - empty for edits pushed before authors were recorded
+/// compacted - true if the edit is restored from a snapshot
+/// shape_ids - shapes the edit changes
+#[derive(Clone)]
+struct Entry {
+    status: EditStatus,
+    page_id: Box<str>,
+    author: Box<str>,
+    compacted: bool,
+    shape_ids: Box<[Box<str>]>,
+}
+
+/// Statuses of the board's edits by their ids, lets undo and redo be validated
+/// without reading the edits themselves
+///
+/// clock - the greatest clock of Modify edits
+#[derive(Clone, Default)]
+pub struct EditIndex {
+    entries: HashMap<Box<str>, Entry>,
+    clock: u64,
+}
+
+impl EditIndex {
+    /// Builds the index from edits of all pages
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is an edit without id property
+    pub fn new(state: &EditState) -> Self {
+        let mut index = EditIndex::default();
+        for edit in state.current.iter() {
+            index.insert(edit, EditStatus::Current);
+        }
+        for edit in state.undone.iter() {
+            index.insert(edit, EditStatus::Undone);
+        }
+        for id in state.compacted.iter() {
+            if let Some(entry) = index.entries.get_mut(id) {
+                entry.compacted = true;
+            }
+        }
+        index
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the edit has no id property
+    pub fn push(&mut self, edit: &Edit) {
+        self.insert(edit, EditStatus::Current);
+    }
+
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    /// Returns ids of shapes the edit changes, empty if the edit does not exist
+    pub fn shape_ids(&self, id: &str) -> &[Box<str>] {
+        self.entries
+            .get(id)
+            .map(|entry| entry.shape_ids.as_ref())
+            .unwrap_or_default()
+    }
+
+    /// Validates the command and applies it to the index.
+    /// Returns false if the edit already has the status the command sets, so nothing should be done
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the edit does not exist,
+    /// if it is restored from a snapshot and the command is undo
+    /// or if author is some and the edit belongs to someone else
+    pub fn apply(
+        &mut self,
+        name: &CommandName,
+        id: &str,
+        author: Option<&str>,
+    ) -> Result<bool, CommandError> {
+        let entry = self.entries.get_mut(id).ok_or(CommandError::NotFound)?;
+        // edits pushed before authors were recorded belong to everyone
+        if let Some(author) = author {
+            if !entry.author.is_empty() && entry.author.as_ref() != author {
+                return Err(CommandError::ForeignEdit);
+            }
+        }
+        let status = match name {
+            CommandName::Undo => {
+                if entry.compacted {
+                    return Err(CommandError::BeyondHorizon);
+                }
+                EditStatus::Undone
+            }
+            CommandName::Redo => EditStatus::Current,
+        };
+        if entry.status == status {
+            return Ok(false);
+        }
+        entry.status = status;
+        Ok(true)
+    }
+
+    pub fn delete_page(&mut self, page_id: &str) {
+        self.entries
+            .retain(|_, entry| entry.page_id.as_ref() != page_id);
+    }
+
+    /// Forgets edits with the status
+    pub fn empty(&mut self, status: EditStatus) {
+        self.entries.retain(|_, entry| entry.status != status);
+    }
+
+    fn insert(&mut self, edit: &Edit, status: EditStatus) {
+        if let Some(EditInner::Modify(modify)) = &edit.edit {
+            self.clock = self.clock.max(modify.clock);
+        }
+        self.entries.insert(
+            edit.edit.as_ref().unwrap().id().into(),
+            Entry {
+                status,
+                page_id: edit.page_id.as_str().into(),
+                author: edit.author.as_str().into(),
+                compacted: false,
+                shape_ids: edit_shapes(edit)
+                    .map(|shape| shape.shape_id.as_str())
+                    .collect::<HashSet<_>>()
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+            },
+        );
+    }
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::board_protocol::{Add, Modify, Shape};
+
+    fn get_edit_sample(id: &str, page_id: &str, author: &str) -> Edit {
+        Edit {
+            edit: Some(EditInner::Add(Add {
+                id: id.to_owned(),
+                shape: None,
+            })),
+            page_id: page_id.to_owned(),
+            author: author.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn get_index_sample() -> EditIndex {
+        EditIndex::new(&EditState {
+            current: vec![
+                get_edit_sample("0", "", ""),
+                get_edit_sample("1", "", "user:1"),
+            ],
+            undone: vec![get_edit_sample("2", "page", "user:1")],
+            compacted: vec!["0".into()],
+        })
+    }
+
+    #[test]
+    fn shape_ids_are_kept_by_edits() {
+        let shape = |id: &str| Shape {
+            shape_id: id.to_owned(),
+            ..Default::default()
+        };
+        let modify = Edit {
+            edit: Some(EditInner::Modify(Modify {
+                id: "modify".to_owned(),
+                current: vec![shape("a"), shape("b")],
+                initial: vec![shape("a"), shape("b")],
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let mut index = get_index_sample();
+        index.push(&modify);
+
+        let mut ids = index.shape_ids("modify").to_vec();
+        ids.sort();
+        assert_eq!(ids, [Box::from("a"), Box::from("b")]);
+        assert!(index.shape_ids("0").is_empty());
+        assert!(index.shape_ids("3").is_empty());
+    }
+
+    #[test]
+    fn apply_rejects_unknown_ids() {
+        let mut index = get_index_sample();
+
+        assert_eq!(
+            index.apply(&CommandName::Undo, "3", None),
+            Err(CommandError::NotFound)
+        );
+        assert_eq!(
+            index.apply(&CommandName::Redo, "3", None),
+            Err(CommandError::NotFound)
+        );
+    }
+
+    #[test]
+    fn apply_is_idempotent() {
+        let mut index = get_index_sample();
+
+        assert_eq!(index.apply(&CommandName::Undo, "1", None), Ok(true));
+        assert_eq!(index.apply(&CommandName::Undo, "1", None), Ok(false));
+        assert_eq!(index.apply(&CommandName::Redo, "1", None), Ok(true));
+        assert_eq!(index.apply(&CommandName::Redo, "1", None), Ok(false));
+        assert_eq!(index.apply(&CommandName::Redo, "2", None), Ok(true));
+    }
+
+    #[test]
+    fn apply_checks_horizon_and_author() {
+        let mut index = get_index_sample();
+
+        assert_eq!(
+            index.apply(&CommandName::Undo, "0", None),
+            Err(CommandError::BeyondHorizon)
+        );
+        assert_eq!(
+            index.apply(&CommandName::Undo, "1", Some("user:2")),
+            Err(CommandError::ForeignEdit)
+        );
+        assert_eq!(
+            index.apply(&CommandName::Undo, "1", Some("user:1")),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn delete_page_and_empty_forget_edits() {
+        let mut index = get_index_sample();
+        index.push(&get_edit_sample("3", "page", "user:1"));
+
+        index.delete_page("page");
+        assert_eq!(
+            index.apply(&CommandName::Undo, "3", None),
+            Err(CommandError::NotFound)
+        );
+        assert_eq!(
+            index.apply(&CommandName::Redo, "2", None),
+            Err(CommandError::NotFound)
+        );
+
+        index.empty(EditStatus::Current);
+        assert_eq!(
+            index.apply(&CommandName::Undo, "1", None),
+            Err(CommandError::NotFound)
+        );
+    }
+
+    #[test]
+    fn clock_is_the_greatest_one() {
+        let mut index = get_index_sample();
+        assert_eq!(index.clock(), 0);
+
+        for clock in [3, 1] {
+            index.push(&Edit {
+                edit: Some(EditInner::Modify(Modify {
+                    id: clock.to_string(),
+                    clock,
+                    ..Default::default()
+                })),
+                ..Default::default()
+            });
+        }
+        assert_eq!(index.clock(), 3);
+    }
+}
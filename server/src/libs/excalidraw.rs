@@ -0,0 +1,779 @@
+use protocol::board_protocol::{
+    edit::Edit as EditInner, Add, BoardSize, Edit, LineType, Shape, ShapeType, Tool,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, mem,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+use super::state::Board;
+
+// constants
+
+const DEFAULT_TITLE: &str = "untitled";
+const DEFAULT_HEIGHT: f32 = 900.0;
+const DEFAULT_WIDTH: f32 = 1720.0;
+const MAX_SIZE: f32 = 10_000.0;
+/// Space between imported content and the board's edges
+const MARGIN: f32 = 50.0;
+/// Excalidraw font families by their ids
+const FONT_FAMILIES: [(u64, &str); 8] = [
+    (1, "Virgil"),
+    (2, "Helvetica"),
+    (3, "Cascadia"),
+    (5, "Excalifont"),
+    (6, "Nunito"),
+    (7, "Lilita One"),
+    (8, "Comic Shanns"),
+    (9, "Liberation Sans"),
+];
+const LINE_HEIGHT: f32 = 1.25;
+
+// errors
+
+pub enum ExcalidrawError {
+    UnknownFormat,
+    Malformed(String),
+}
+
+impl fmt::Display for ExcalidrawError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat => write!(f, "file is not an excalidraw scene"),
+            Self::Malformed(e) => write!(f, "scene is malformed: {}", e),
+        }
+    }
+}
+
+// structs
+
+/// unsupported - ids of elements(or shapes) that lost a property by the property's name in snake case
+/// skipped - elements(or shapes) that cannot be converted at all
+#[derive(Serialize, Default, Debug)]
+pub struct Report {
+    pub unsupported: BTreeMap<String, Vec<String>>,
+    pub skipped: Vec<Skipped>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Skipped {
+    pub id: String,
+    pub reason: String,
+}
+
+impl Report {
+    fn unsupported(&mut self, property: &str, id: &str) {
+        self.unsupported
+            .entry(property.to_owned())
+            .or_default()
+            .push(id.to_owned());
+    }
+
+    fn skip(&mut self, id: &str, reason: &str) {
+        self.skipped.push(Skipped {
+            id: id.to_owned(),
+            reason: reason.to_owned(),
+        });
+    }
+}
+
+/// title - name of the scene if it fits into board's title
+/// current - Add edits of the default page
+pub struct Imported {
+    pub title: Box<str>,
+    pub size: BoardSize,
+    pub current: Vec<Edit>,
+    pub report: Report,
+}
+
+#[derive(Deserialize)]
+struct Scene {
+    #[serde(rename = "type")]
+    scene_type: String,
+    #[serde(default)]
+    elements: Vec<Element>,
+    #[serde(default, rename = "appState")]
+    app_state: Map<String, Value>,
+    #[serde(default)]
+    files: HashMap<String, File>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct File {
+    #[serde(rename = "dataURL")]
+    data_url: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct Element {
+    id: String,
+    #[serde(rename = "type")]
+    element_type: String,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    angle: f32,
+    stroke_color: String,
+    background_color: String,
+    stroke_width: f32,
+    stroke_style: String,
+    roughness: f32,
+    opacity: Option<f32>,
+    group_ids: Vec<String>,
+    frame_id: Option<String>,
+    roundness: Option<Value>,
+    is_deleted: bool,
+    bound_elements: Option<Vec<Value>>,
+    link: Option<String>,
+    locked: bool,
+    // linear elements
+    points: Vec<[f32; 2]>,
+    start_arrowhead: Option<String>,
+    end_arrowhead: Option<String>,
+    start_binding: Option<Value>,
+    end_binding: Option<Value>,
+    pressures: Vec<f32>,
+    simulate_pressure: bool,
+    // text
+    text: String,
+    font_size: f32,
+    font_family: Option<u64>,
+    text_align: Option<String>,
+    container_id: Option<String>,
+    // image
+    file_id: Option<String>,
+    scale: Option<[f32; 2]>,
+}
+
+// import
+
+/// Converts an excalidraw scene to edits of a new board
+pub fn import(data: &[u8]) -> Result<Imported, ExcalidrawError> {
+    let scene: Scene =
+        serde_json::from_slice(data).map_err(|e| ExcalidrawError::Malformed(e.to_string()))?;
+    if scene.scene_type != "excalidraw" {
+        return Err(ExcalidrawError::UnknownFormat);
+    }
+    let mut report = Report::default();
+    let mut shapes = Vec::new();
+    for element in scene.elements.iter().filter(|e| !e.is_deleted) {
+        if let Some(mut shape) = element_to_shape(element, &scene.files, &mut report) {
+            // large images are moved to the asset store on room creation
+            let url = mem::take(&mut shape.url);
+            if let Err(e) = Board::validate_shape(&shape) {
+                report.skip(&element.id, &e.to_string());
+                continue;
+            }
+            shape.url = url;
+            report_common(element, &mut report);
+            shapes.push(shape);
+        }
+    }
+    // move content to the board's area, excalidraw's canvas is infinite
+    let (min_x, min_y, max_x, max_y) = shapes.iter().map(shape_bounds).fold(
+        (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+        |(a, b, c, d), (x0, y0, x1, y1)| (a.min(x0), b.min(y0), c.max(x1), d.max(y1)),
+    );
+    let (dx, dy) = match shapes.is_empty() {
+        true => (0.0, 0.0),
+        false => (MARGIN - min_x, MARGIN - min_y),
+    };
+    for shape in shapes.iter_mut() {
+        shape.x += dx;
+        shape.y += dy;
+    }
+    let size = BoardSize {
+        height: (max_y + dy + MARGIN).clamp(DEFAULT_HEIGHT, MAX_SIZE) as u32,
+        width: (max_x + dx + MARGIN).clamp(DEFAULT_WIDTH, MAX_SIZE) as u32,
+    };
+    let title = match scene.app_state.get("name").and_then(|n| n.as_str()) {
+        Some(name) if !name.is_empty() && name.len() <= 36 => name.into(),
+        _ => DEFAULT_TITLE.into(),
+    };
+    let current = shapes
+        .into_iter()
+        .map(|shape| Edit {
+            edit: Some(EditInner::Add(Add {
+                id: shape.shape_id.clone(),
+                shape: Some(shape),
+            })),
+            page_id: String::new(),
+            ..Default::default()
+        })
+        .collect();
+
+    Ok(Imported {
+        title,
+        size,
+        current,
+        report,
+    })
+}
+
+fn report_common(element: &Element, report: &mut Report) {
+    let id = &element.id;
+    if element.opacity.is_some_and(|o| o != 100.0) {
+        report.unsupported("opacity", id);
+    }
+    if element.roughness != 0.0 {
+        report.unsupported("roughness", id);
+    }
+    if !matches!(element.background_color.as_str(), "" | "transparent") {
+        report.unsupported("background_color", id);
+    }
+    if element.roundness.as_ref().is_some_and(|r| !r.is_null()) {
+        report.unsupported("roundness", id);
+    }
+    if !element.group_ids.is_empty() {
+        report.unsupported("group_ids", id);
+    }
+    if element.frame_id.is_some() {
+        report.unsupported("frame_id", id);
+    }
+    if element
+        .bound_elements
+        .as_ref()
+        .is_some_and(|b| !b.is_empty())
+    {
+        report.unsupported("bound_elements", id);
+    }
+    if element.link.is_some() {
+        report.unsupported("link", id);
+    }
+    if element.locked {
+        report.unsupported("locked", id);
+    }
+    if element.stroke_style == "dotted" {
+        report.unsupported("stroke_style", id);
+    }
+}
+
+fn element_to_shape(
+    element: &Element,
+    files: &HashMap<String, File>,
+    report: &mut Report,
+) -> Option<Shape> {
+    let id = Uuid::now_v7().to_string();
+    let mut shape = Shape {
+        shape_id: id,
+        color: element.stroke_color.clone(),
+        line_size: element.stroke_width,
+        line_type: match element.stroke_style.as_str() {
+            "dashed" | "dotted" => LineType::Dashed as i32,
+            _ => LineType::General as i32,
+        },
+        scale_x: 1.0,
+        scale_y: 1.0,
+        ..Default::default()
+    };
+    match element.element_type.as_str() {
+        "rectangle" => {
+            shape.tool = Tool::RectTool as i32;
+            shape.shape_type = ShapeType::Rect as i32;
+            set_box(&mut shape, element);
+        }
+        "ellipse" => {
+            shape.tool = Tool::EllipseTool as i32;
+            shape.shape_type = ShapeType::Ellipse as i32;
+            shape.x = element.x + element.width / 2.0;
+            shape.y = element.y + element.height / 2.0;
+            shape.radius_x = element.width / 2.0;
+            shape.radius_y = element.height / 2.0;
+            shape.rotation = element.angle.to_degrees();
+        }
+        "line" | "arrow" | "freedraw" => {
+            let has_pointer = element.end_arrowhead.is_some();
+            let (tool, shape_type) = match element.element_type.as_str() {
+                "freedraw" => (Tool::PenTool, ShapeType::Line),
+                "arrow" if has_pointer => (Tool::ArrowTool, ShapeType::Arrow),
+                _ => (Tool::LineTool, ShapeType::Line),
+            };
+            shape.tool = tool as i32;
+            shape.shape_type = shape_type as i32;
+            if element.start_arrowhead.is_some() {
+                report.unsupported("start_arrowhead", &element.id);
+            }
+            if element
+                .end_arrowhead
+                .as_ref()
+                .is_some_and(|head| head != "arrow")
+            {
+                report.unsupported("end_arrowhead", &element.id);
+            }
+            if element.start_binding.is_some() || element.end_binding.is_some() {
+                report.unsupported("binding", &element.id);
+            }
+            if !element.pressures.is_empty() && !element.simulate_pressure {
+                report.unsupported("pressures", &element.id);
+            }
+            if element.points.is_empty() {
+                report.skip(&element.id, "line has no points");
+                return None;
+            }
+            set_points(&mut shape, element);
+        }
+        "text" => {
+            shape.tool = Tool::TextTool as i32;
+            shape.shape_type = ShapeType::Text as i32;
+            shape.text = element.text.clone();
+            shape.font_size = element.font_size;
+            shape.font_family = match FONT_FAMILIES
+                .iter()
+                .find(|(id, _)| Some(*id) == element.font_family)
+            {
+                Some((_, name)) => (*name).to_owned(),
+                None => {
+                    report.unsupported("font_family", &element.id);
+                    String::new()
+                }
+            };
+            if element.text_align.as_ref().is_some_and(|a| a != "left") {
+                report.unsupported("text_align", &element.id);
+            }
+            if element.container_id.is_some() {
+                report.unsupported("container_id", &element.id);
+            }
+            set_box(&mut shape, element);
+        }
+        "image" => {
+            let file = element.file_id.as_ref().and_then(|id| files.get(id));
+            let file = match file {
+                Some(file) => file,
+                None => {
+                    report.skip(&element.id, "image file is missing");
+                    return None;
+                }
+            };
+            shape.tool = Tool::ImgTool as i32;
+            shape.shape_type = ShapeType::Img as i32;
+            shape.url = file.data_url.clone();
+            if element.scale.is_some_and(|s| s != [1.0, 1.0]) {
+                report.unsupported("scale", &element.id);
+            }
+            set_box(&mut shape, element);
+        }
+        other => {
+            report.skip(
+                &element.id,
+                &format!("{} elements are not supported", other),
+            );
+            return None;
+        }
+    }
+    Some(shape)
+}
+
+/// Sets position, size and rotation of a box-like shape.
+/// Excalidraw rotates elements around their centers, konva around their origins
+fn set_box(shape: &mut Shape, element: &Element) {
+    let (w, h) = (element.width, element.height);
+    let (cx, cy) = (element.x + w / 2.0, element.y + h / 2.0);
+    let (ox, oy) = rotate((-w / 2.0, -h / 2.0), element.angle);
+    shape.x = cx + ox;
+    shape.y = cy + oy;
+    shape.width = w;
+    shape.height = h;
+    shape.rotation = element.angle.to_degrees();
+}
+
+/// Sets points of a line, rotation is applied to the points
+/// because board's points cannot be negative
+fn set_points(shape: &mut Shape, element: &Element) {
+    let (min_x, min_y, max_x, max_y) = bounds(&element.points);
+    let (cx, cy) = (
+        element.x + (min_x + max_x) / 2.0,
+        element.y + (min_y + max_y) / 2.0,
+    );
+    let points: Vec<(f32, f32)> = element
+        .points
+        .iter()
+        .map(|[px, py]| {
+            let (x, y) = rotate((element.x + px - cx, element.y + py - cy), element.angle);
+            (x + cx, y + cy)
+        })
+        .collect();
+    let origin_x = points.iter().map(|p| p.0).fold(f32::MAX, f32::min);
+    let origin_y = points.iter().map(|p| p.1).fold(f32::MAX, f32::min);
+    shape.x = origin_x;
+    shape.y = origin_y;
+    shape.points = points
+        .iter()
+        .flat_map(|(x, y)| [(x - origin_x).round() as u32, (y - origin_y).round() as u32])
+        .collect();
+}
+
+// export
+
+/// Converts shapes of a board's page to an excalidraw scene
+pub fn export(shapes: &[Shape]) -> (Value, Report) {
+    let mut report = Report::default();
+    let mut elements = Vec::new();
+    let mut files = Map::new();
+    let updated = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+
+    for shape in shapes {
+        let id = &shape.shape_id;
+        if shape.skew_x != 0.0 || shape.skew_y != 0.0 {
+            report.unsupported("skew", id);
+        }
+        if !shape.connected.is_empty() {
+            report.unsupported("connected", id);
+        }
+        let mut element = base_element(shape, elements.len() as u64 + 1, updated);
+        let shape_type = ShapeType::try_from(shape.shape_type);
+        match shape_type {
+            Ok(ShapeType::Line) | Ok(ShapeType::Arrow) => {
+                if shape.tool == Tool::EraserTool as i32 {
+                    report.skip(id, "eraser lines cannot be represented");
+                    continue;
+                }
+                let points = absolute_points(shape);
+                if points.is_empty() {
+                    report.skip(id, "line has no points");
+                    continue;
+                }
+                let (x0, y0) = points[0];
+                let relative: Vec<[f32; 2]> =
+                    points.iter().map(|(x, y)| [x - x0, y - y0]).collect();
+                let (min_x, min_y, max_x, max_y) = bounds(&relative);
+                element["x"] = json!(x0);
+                element["y"] = json!(y0);
+                element["width"] = json!(max_x - min_x);
+                element["height"] = json!(max_y - min_y);
+                element["angle"] = json!(0);
+                element["points"] = json!(relative);
+                element["lastCommittedPoint"] = Value::Null;
+                if shape.tool == Tool::PenTool as i32 && shape_type == Ok(ShapeType::Line) {
+                    element["type"] = json!("freedraw");
+                    element["pressures"] = json!([]);
+                    element["simulatePressure"] = json!(true);
+                } else {
+                    let is_arrow = shape_type == Ok(ShapeType::Arrow);
+                    element["type"] = json!(if is_arrow { "arrow" } else { "line" });
+                    element["startBinding"] = Value::Null;
+                    element["endBinding"] = Value::Null;
+                    element["startArrowhead"] = Value::Null;
+                    element["endArrowhead"] = match is_arrow {
+                        true => json!("arrow"),
+                        false => Value::Null,
+                    };
+                }
+            }
+            Ok(ShapeType::Rect) => {
+                element["type"] = json!("rectangle");
+                set_element_box(&mut element, shape, shape_width(shape), shape_height(shape));
+            }
+            Ok(ShapeType::Ellipse) => {
+                let (w, h) = (
+                    shape.radius_x.abs() * 2.0 * scale(shape.scale_x),
+                    shape.radius_y.abs() * 2.0 * scale(shape.scale_y),
+                );
+                element["type"] = json!("ellipse");
+                element["x"] = json!(shape.x - w / 2.0);
+                element["y"] = json!(shape.y - h / 2.0);
+                element["width"] = json!(w);
+                element["height"] = json!(h);
+            }
+            Ok(ShapeType::Img) => {
+                let mime_type = shape
+                    .url
+                    .strip_prefix("data:")
+                    .and_then(|url| url.split(';').next());
+                let mime_type = match mime_type {
+                    Some(mime_type) => mime_type,
+                    None => {
+                        report.skip(id, "only embedded images can be exported");
+                        continue;
+                    }
+                };
+                element["type"] = json!("image");
+                element["fileId"] = json!(id);
+                element["status"] = json!("saved");
+                element["scale"] = json!([1, 1]);
+                set_element_box(&mut element, shape, shape_width(shape), shape_height(shape));
+                files.insert(
+                    id.clone(),
+                    json!({
+                        "id": id,
+                        "mimeType": mime_type,
+                        "dataURL": shape.url,
+                        "created": updated,
+                    }),
+                );
+            }
+            Ok(ShapeType::Text) => {
+                set_text(&mut element, shape, &mut report);
+            }
+            Ok(ShapeType::StickyNote) => {
+                // a sticky note is a filled rectangle with a text
+                let mut note = element.clone();
+                note["type"] = json!("rectangle");
+                note["backgroundColor"] = json!(match shape.fill.is_empty() {
+                    true => "#fff475",
+                    false => &shape.fill,
+                });
+                note["strokeColor"] = json!("transparent");
+                set_element_box(&mut note, shape, shape_width(shape), shape_height(shape));
+                elements.push(note);
+                element = base_element(shape, elements.len() as u64 + 1, updated);
+                element["id"] = json!(format!("{}-text", id));
+                set_text(&mut element, shape, &mut report);
+            }
+            Err(_) => {
+                report.skip(id, "unknown shape type");
+                continue;
+            }
+        }
+        elements.push(element);
+    }
+
+    let scene = json!({
+        "type": "excalidraw",
+        "version": 2,
+        "source": "board4you",
+        "elements": elements,
+        "appState": {
+            "viewBackgroundColor": "#ffffff",
+            "gridSize": null,
+        },
+        "files": files,
+    });
+    (scene, report)
+}
+
+fn base_element(shape: &Shape, seed: u64, updated: u64) -> Value {
+    json!({
+        "id": shape.shape_id,
+        "type": "",
+        "x": shape.x,
+        "y": shape.y,
+        "width": 0,
+        "height": 0,
+        "angle": shape.rotation.to_radians(),
+        "strokeColor": shape.color,
+        "backgroundColor": "transparent",
+        "fillStyle": "solid",
+        "strokeWidth": shape.line_size,
+        "strokeStyle": if shape.line_type == LineType::Dashed as i32 { "dashed" } else { "solid" },
+        "roughness": 0,
+        "opacity": 100,
+        "groupIds": [],
+        "frameId": null,
+        "roundness": null,
+        "seed": seed,
+        "version": 1,
+        "versionNonce": 0,
+        "isDeleted": false,
+        "boundElements": null,
+        "updated": updated,
+        "link": null,
+        "locked": false,
+    })
+}
+
+/// Sets position and size of a box-like element.
+/// Konva rotates shapes around their origins, excalidraw around their centers
+fn set_element_box(element: &mut Value, shape: &Shape, w: f32, h: f32) {
+    let angle = shape.rotation.to_radians();
+    let (ox, oy) = rotate((w / 2.0, h / 2.0), angle);
+    let (cx, cy) = (shape.x + ox, shape.y + oy);
+    element["x"] = json!(cx - w.abs() / 2.0);
+    element["y"] = json!(cy - h.abs() / 2.0);
+    element["width"] = json!(w.abs());
+    element["height"] = json!(h.abs());
+}
+
+fn set_text(element: &mut Value, shape: &Shape, report: &mut Report) {
+    let font_size = match shape.font_size > 0.0 {
+        true => shape.font_size,
+        false => 12.0,
+    };
+    let font_family = match FONT_FAMILIES
+        .iter()
+        .find(|(_, name)| *name == shape.font_family)
+    {
+        Some((id, _)) => *id,
+        None => {
+            if !shape.font_family.is_empty() {
+                report.unsupported("font_family", &shape.shape_id);
+            }
+            2
+        }
+    };
+    let lines = shape.text.lines().count().max(1) as f32;
+    let longest = shape
+        .text
+        .lines()
+        .map(|l| l.chars().count())
+        .max()
+        .unwrap_or(0) as f32;
+    let (w, h) = (longest * font_size * 0.6, lines * font_size * LINE_HEIGHT);
+    element["type"] = json!("text");
+    element["text"] = json!(shape.text);
+    element["originalText"] = json!(shape.text);
+    element["fontSize"] = json!(font_size);
+    element["fontFamily"] = json!(font_family);
+    element["textAlign"] = json!("left");
+    element["verticalAlign"] = json!("top");
+    element["containerId"] = Value::Null;
+    element["lineHeight"] = json!(LINE_HEIGHT);
+    element["autoResize"] = json!(true);
+    set_element_box(element, shape, w, h);
+}
+
+// helpers
+
+fn rotate((x, y): (f32, f32), angle: f32) -> (f32, f32) {
+    let (sin, cos) = angle.sin_cos();
+    (x * cos - y * sin, x * sin + y * cos)
+}
+
+fn bounds(points: &[[f32; 2]]) -> (f32, f32, f32, f32) {
+    points.iter().fold(
+        (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+        |(a, b, c, d), [x, y]| (a.min(*x), b.min(*y), c.max(*x), d.max(*y)),
+    )
+}
+
+/// zero scale means that the field is unset
+fn scale(value: f32) -> f32 {
+    if value == 0.0 {
+        1.0
+    } else {
+        value
+    }
+}
+
+fn shape_width(shape: &Shape) -> f32 {
+    match ShapeType::try_from(shape.shape_type) {
+        Ok(ShapeType::Line) | Ok(ShapeType::Arrow) => shape
+            .points
+            .chunks_exact(2)
+            .map(|p| p[0] as f32)
+            .fold(0.0, f32::max),
+        Ok(ShapeType::Ellipse) => shape.radius_x.abs(),
+        _ => shape.width * scale(shape.scale_x),
+    }
+}
+
+fn shape_height(shape: &Shape) -> f32 {
+    match ShapeType::try_from(shape.shape_type) {
+        Ok(ShapeType::Line) | Ok(ShapeType::Arrow) => shape
+            .points
+            .chunks_exact(2)
+            .map(|p| p[1] as f32)
+            .fold(0.0, f32::max),
+        Ok(ShapeType::Ellipse) => shape.radius_y.abs(),
+        _ => shape.height * scale(shape.scale_y),
+    }
+}
+
+/// Returns the area the shape takes, ellipses are positioned by their centers
+fn shape_bounds(shape: &Shape) -> (f32, f32, f32, f32) {
+    let (width, height) = (shape_width(shape), shape_height(shape));
+    match ShapeType::try_from(shape.shape_type) {
+        Ok(ShapeType::Ellipse) => (
+            shape.x - width,
+            shape.y - height,
+            shape.x + width,
+            shape.y + height,
+        ),
+        _ => (shape.x, shape.y, shape.x + width, shape.y + height),
+    }
+}
+
+/// Returns points of a line in board's coordinates
+fn absolute_points(shape: &Shape) -> Vec<(f32, f32)> {
+    let angle = shape.rotation.to_radians();
+    shape
+        .points
+        .chunks_exact(2)
+        .map(|p| {
+            let (x, y) = rotate(
+                (
+                    p[0] as f32 * scale(shape.scale_x),
+                    p[1] as f32 * scale(shape.scale_y),
+                ),
+                angle,
+            );
+            (shape.x + x, shape.y + y)
+        })
+        .collect()
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_reports_unsupported_properties() {
+        let data = br##"{
+            "type": "excalidraw",
+            "elements": [
+                {"id": "a", "type": "rectangle", "x": -100, "y": -100, "width": 10, "height": 10,
+                 "roughness": 1, "strokeColor": "#000", "strokeWidth": 2},
+                {"id": "b", "type": "diamond", "x": 0, "y": 0, "width": 10, "height": 10},
+                {"id": "c", "type": "line", "x": 0, "y": 0, "points": [[0, 0], [-10, 20]],
+                 "roughness": 0, "startArrowhead": "dot"}
+            ]
+        }"##;
+
+        let imported = import(data).unwrap_or_else(|e| panic!("{}", e));
+
+        assert_eq!(imported.current.len(), 2);
+        assert_eq!(imported.report.unsupported["roughness"], vec!["a"]);
+        assert_eq!(imported.report.unsupported["start_arrowhead"], vec!["c"]);
+        assert_eq!(imported.report.skipped[0].id, "b");
+    }
+
+    #[test]
+    fn imported_ellipse_fits_the_board() {
+        let data = br##"{
+            "type": "excalidraw",
+            "elements": [
+                {"id": "a", "type": "ellipse", "x": -100, "y": -100, "width": 20, "height": 10}
+            ]
+        }"##;
+
+        let imported = import(data).unwrap_or_else(|e| panic!("{}", e));
+        let shape = match &imported.current[0].edit {
+            Some(EditInner::Add(add)) => add.shape.clone().unwrap(),
+            _ => panic!("shape is not added"),
+        };
+
+        // the left and top edges are at the margin, not the center
+        assert_eq!((shape.x, shape.y), (MARGIN + 10.0, MARGIN + 5.0));
+    }
+
+    #[test]
+    fn rotated_rect_keeps_its_center() {
+        let shape = Shape {
+            shape_type: ShapeType::Rect as i32,
+            x: 100.0,
+            y: 100.0,
+            width: 40.0,
+            height: 20.0,
+            rotation: 90.0,
+            ..Default::default()
+        };
+
+        let (scene, _) = export(&[shape]);
+        let element = &scene["elements"][0];
+
+        // konva's origin is the top left corner, so the center is at (90, 120)
+        assert!((element["x"].as_f64().unwrap() - 70.0).abs() < 1e-3);
+        assert!((element["y"].as_f64().unwrap() - 110.0).abs() < 1e-3);
+    }
+}
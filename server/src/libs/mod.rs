@@ -1,4 +1,17 @@
+pub mod archive;
+pub mod assets;
 pub mod auth;
+pub mod bus;
+pub mod cluster;
 pub mod db_queue;
+pub mod edit_index;
+pub mod excalidraw;
+pub mod operation_log;
+pub mod registry;
+pub mod render;
 pub mod room;
+pub mod sessions;
+pub mod shape_merge;
+pub mod spatial_index;
 pub mod state;
+pub mod user_queue;
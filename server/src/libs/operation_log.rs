@@ -0,0 +1,99 @@
+use protocol::board_protocol::ServerMessage;
+use std::collections::VecDeque;
+
+/// Messages of the latest accepted operations with their sequence numbers,
+/// the oldest ones are dropped when the capacity is reached
+///
+/// entries - messages ordered by their sequence numbers
+pub struct OperationLog {
+    entries: VecDeque<(u64, ServerMessage)>,
+    capacity: usize,
+}
+
+impl OperationLog {
+    pub fn new(capacity: usize) -> Self {
+        OperationLog {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, seq: u64, msg: ServerMessage) {
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((seq, msg));
+    }
+
+    /// Returns messages of operations after seq up to last_seq
+    /// or None if some of them are not in the log anymore
+    pub fn after(&self, seq: u64, last_seq: u64) -> Option<Vec<ServerMessage>> {
+        if seq > last_seq {
+            return None;
+        }
+        if seq == last_seq {
+            return Some(vec![]);
+        }
+        match self.entries.front() {
+            Some((first, _)) if *first <= seq + 1 => Some(
+                self.entries
+                    .iter()
+                    .filter(|(op_seq, _)| *op_seq > seq)
+                    .map(|(_, msg)| msg.clone())
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::board_protocol::{server_message::Msg, PushData};
+
+    fn get_msg_sample(seq: u64) -> ServerMessage {
+        ServerMessage {
+            msg: Some(Msg::PushData(PushData { data: vec![], seq })),
+        }
+    }
+
+    fn get_log_sample(capacity: usize, last_seq: u64) -> OperationLog {
+        let mut log = OperationLog::new(capacity);
+        for seq in 1..=last_seq {
+            log.push(seq, get_msg_sample(seq));
+        }
+        log
+    }
+
+    #[test]
+    fn after_returns_operations_in_order() {
+        let log = get_log_sample(10, 5);
+
+        assert_eq!(
+            log.after(2, 5),
+            Some(vec![
+                get_msg_sample(3),
+                get_msg_sample(4),
+                get_msg_sample(5)
+            ])
+        );
+        assert_eq!(log.after(0, 5).map(|ops| ops.len()), Some(5));
+        assert_eq!(log.after(5, 5), Some(vec![]));
+    }
+
+    #[test]
+    fn after_requires_full_state_if_operations_are_missing() {
+        let log = get_log_sample(3, 5);
+
+        // operations 1 and 2 are dropped
+        assert_eq!(log.after(1, 5), None);
+        assert_eq!(log.after(2, 5).map(|ops| ops.len()), Some(3));
+        // seq from another life of the board
+        assert_eq!(log.after(6, 5), None);
+        // the board was loaded after seq 3, so its operations are not logged
+        assert_eq!(OperationLog::new(3).after(3, 5), None);
+    }
+}
@@ -0,0 +1,219 @@
+use super::room::RoomChannel;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+// constants
+
+/// Number of independently locked parts of the registry
+const SHARDS_COUNT: usize = 16;
+
+// registry
+
+/// Room is loaded once the cell is initialized,
+/// callers waiting for the same room share the cell
+type Slot = Arc<OnceCell<RoomChannel>>;
+
+/// Rooms loaded by the node split in shards by ids,
+/// so operations on different rooms do not wait for each other.
+/// Locks are never held across awaits
+pub struct RoomRegistry {
+    shards: Box<[Mutex<HashMap<Uuid, Slot>>]>,
+}
+
+impl Default for RoomRegistry {
+    fn default() -> Self {
+        RoomRegistry {
+            shards: (0..SHARDS_COUNT)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+        }
+    }
+}
+
+impl RoomRegistry {
+    fn shard(&self, public_id: &Uuid) -> &Mutex<HashMap<Uuid, Slot>> {
+        &self.shards[public_id.as_u128() as usize % self.shards.len()]
+    }
+
+    pub fn get(&self, public_id: &Uuid) -> Option<RoomChannel> {
+        let shard = self.shard(public_id).lock().unwrap();
+        shard.get(public_id).and_then(|slot| slot.get().cloned())
+    }
+
+    /// Returns the room or spawns it with the function.
+    /// Concurrent callers of the same room wait for the first one,
+    /// so the room is spawned once. If spawning fails, the next waiting caller tries again
+    pub async fn get_or_spawn<F, Fut, E>(&self, public_id: Uuid, spawn: F) -> Result<RoomChannel, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<RoomChannel, E>>,
+    {
+        let slot = self
+            .shard(&public_id)
+            .lock()
+            .unwrap()
+            .entry(public_id)
+            .or_default()
+            .clone();
+        match slot.get_or_try_init(spawn).await {
+            Ok(room) => Ok(room.clone()),
+            Err(e) => {
+                // do not keep slots of rooms that cannot be spawned
+                let mut shard = self.shard(&public_id).lock().unwrap();
+                if let Some(current) = shard.get(&public_id) {
+                    if Arc::ptr_eq(current, &slot) && !current.initialized() {
+                        shard.remove(&public_id);
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Adds the room replacing the previous one with the same id
+    pub fn insert(&self, public_id: Uuid, room: RoomChannel) {
+        let slot = Arc::new(OnceCell::new_with(Some(room)));
+        self.shard(&public_id)
+            .lock()
+            .unwrap()
+            .insert(public_id, slot);
+    }
+
+    pub fn remove(&self, public_id: &Uuid) -> Option<RoomChannel> {
+        let slot = self.shard(public_id).lock().unwrap().remove(public_id)?;
+        slot.get().cloned()
+    }
+
+    /// Removes the room if nothing but the registry refers to it,
+    /// i.e. there are no connections to the room or requests being handled by it.
+    /// Returns the removed room, nobody can get it from the registry anymore
+    pub fn remove_unused(&self, public_id: &Uuid) -> Option<RoomChannel> {
+        let mut shard = self.shard(public_id).lock().unwrap();
+        let room = shard.get(public_id)?.get()?;
+        if room.strong_count() != 1 {
+            return None;
+        }
+        shard.remove(public_id)?.get().cloned()
+    }
+
+    /// Returns loaded rooms without locking the registry while they are used
+    pub fn rooms(&self) -> Vec<(Uuid, RoomChannel)> {
+        let mut rooms = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = shard.lock().unwrap();
+            rooms.extend(
+                shard
+                    .iter()
+                    .filter_map(|(id, slot)| slot.get().map(|room| (*id, room.clone()))),
+            );
+        }
+        rooms
+    }
+
+    /// Returns ids of loaded rooms
+    pub fn ids(&self) -> Vec<Uuid> {
+        let mut ids = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = shard.lock().unwrap();
+            ids.extend(
+                shard
+                    .iter()
+                    .filter(|(_, slot)| slot.initialized())
+                    .map(|(id, _)| *id),
+            );
+        }
+        ids
+    }
+
+    /// Shrinks capacity of the shards
+    pub fn shrink(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().shrink_to(20 / SHARDS_COUNT + 1);
+        }
+    }
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::room::UserMessage;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::mpsc::{channel, Receiver};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_joins_spawn_room_once() {
+        let registry = Arc::new(RoomRegistry::default());
+        let spawned = Arc::new(AtomicUsize::new(0));
+        let receivers = Arc::new(Mutex::new(Vec::<Receiver<UserMessage>>::new()));
+        let public_id = Uuid::now_v7();
+
+        let mut joins = Vec::new();
+        for _ in 0..64 {
+            let registry = registry.clone();
+            let spawned = spawned.clone();
+            let receivers = receivers.clone();
+            joins.push(tokio::spawn(async move {
+                registry
+                    .get_or_spawn(public_id, || async move {
+                        // loading takes time, so others try to join meanwhile
+                        tokio::task::yield_now().await;
+                        spawned.fetch_add(1, Ordering::SeqCst);
+                        let (tx, rx) = channel(1);
+                        receivers.lock().unwrap().push(rx);
+                        Ok::<_, ()>(tx)
+                    })
+                    .await
+                    .unwrap()
+            }));
+        }
+        let mut rooms = Vec::new();
+        for join in joins {
+            rooms.push(join.await.unwrap());
+        }
+
+        assert_eq!(spawned.load(Ordering::SeqCst), 1);
+        assert!(rooms.iter().all(|room| room.same_channel(&rooms[0])));
+        assert_eq!(registry.ids(), vec![public_id]);
+    }
+
+    #[tokio::test]
+    async fn failed_spawn_is_retried() {
+        let registry = RoomRegistry::default();
+        let public_id = Uuid::now_v7();
+
+        let res = registry
+            .get_or_spawn(public_id, || async { Err::<RoomChannel, _>("not found") })
+            .await;
+        assert!(res.is_err());
+        // the slot is not kept
+        assert!(registry.shard(&public_id).lock().unwrap().is_empty());
+
+        let (tx, _rx) = channel(1);
+        let room = registry
+            .get_or_spawn(public_id, || async { Ok::<_, ()>(tx) })
+            .await
+            .unwrap();
+        assert!(registry.get(&public_id).unwrap().same_channel(&room));
+    }
+
+    #[test]
+    fn used_rooms_are_not_removed() {
+        let registry = RoomRegistry::default();
+        let public_id = Uuid::now_v7();
+        let (tx, _rx) = channel(1);
+        registry.insert(public_id, tx);
+
+        let connection = registry.get(&public_id).unwrap();
+        assert!(registry.remove_unused(&public_id).is_none());
+        drop(connection);
+        assert!(registry.remove_unused(&public_id).is_some());
+        assert!(registry.get(&public_id).is_none());
+    }
+}
@@ -0,0 +1,81 @@
+use lazy_static::lazy_static;
+use protocol::board_protocol::{edit::Edit as EditInner, Edit, Shape};
+use resvg::usvg::{fontdb, PostProcessingSteps, Tree, TreeParsing, TreePostProc};
+use std::fmt;
+
+use super::shape_merge::MergedShape;
+
+pub mod pdf;
+pub mod png;
+pub mod svg;
+
+lazy_static! {
+    static ref FONTS: fontdb::Database = {
+        let mut fonts = fontdb::Database::new();
+        fonts.load_system_fonts();
+        fonts
+    };
+}
+
+// errors
+
+pub enum RenderError {
+    Parse(String),
+    Encode(String),
+    TooLarge,
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(e) => write!(f, "cannot parse svg: {}", e),
+            Self::Encode(e) => write!(f, "cannot encode image: {}", e),
+            Self::TooLarge => write!(f, "output image is too large"),
+        }
+    }
+}
+
+// functions
+
+/// Parses svg and converts its text into paths using system fonts
+fn parse(svg: &str) -> Result<Tree, RenderError> {
+    let mut tree = Tree::from_str(svg, &resvg::usvg::Options::default())
+        .map_err(|e| RenderError::Parse(e.to_string()))?;
+    tree.postprocess(PostProcessingSteps::default(), &FONTS);
+    Ok(tree)
+}
+
+/// Applies edits in order and returns the page's shapes the way the client draws them.
+/// Modify edits are merged by groups of properties(see shape_merge)
+pub fn shapes(current: &[Edit], page_id: &str) -> Vec<Shape> {
+    let mut shapes: Vec<MergedShape> = Vec::new();
+    for edit in current.iter().filter(|edit| edit.page_id == page_id) {
+        match &edit.edit {
+            Some(EditInner::Add(add)) => {
+                if let Some(shape) = &add.shape {
+                    shapes.push(MergedShape::new(shape.clone()));
+                }
+            }
+            Some(EditInner::Remove(remove)) => {
+                shapes.retain(|merged| {
+                    !remove
+                        .shapes
+                        .iter()
+                        .any(|removed| removed.shape_id == merged.shape.shape_id)
+                });
+            }
+            Some(EditInner::Modify(modify)) => {
+                for modified in modify.current.iter() {
+                    if let Some(merged) = shapes
+                        .iter_mut()
+                        .find(|merged| merged.shape.shape_id == modified.shape_id)
+                    {
+                        merged.merge(modified, modify);
+                    }
+                }
+            }
+            None => (),
+        }
+    }
+    shapes.into_iter().map(|merged| merged.shape).collect()
+}
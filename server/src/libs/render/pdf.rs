@@ -0,0 +1,69 @@
+use super::{parse, RenderError};
+use pdf_writer::{Chunk, Content, Finish, Name, Pdf, Rect, Ref};
+
+/// Renders every svg into a separate pdf page of the same size
+pub fn render(svgs: &[String]) -> Result<Vec<u8>, RenderError> {
+    let mut pdf = Pdf::new();
+    let mut alloc = Ref::new(1);
+    let catalog_id = alloc.bump();
+    let page_tree_id = alloc.bump();
+    let mut page_ids = Vec::with_capacity(svgs.len());
+    let svg_name = Name(b"S1");
+
+    for svg in svgs {
+        let tree = parse(svg)?;
+        let (width, height) = (tree.size.width(), tree.size.height());
+        let page_id = alloc.bump();
+        let content_id = alloc.bump();
+        let svg_id = alloc.bump();
+        // svg is embedded as xobject which is scaled to the page size
+        let mut chunk = Chunk::new();
+        alloc = svg2pdf::convert_tree_into(&tree, svg2pdf::Options::default(), &mut chunk, svg_id);
+        pdf.extend(&chunk);
+
+        let mut page = pdf.page(page_id);
+        page.media_box(Rect::new(0.0, 0.0, width, height));
+        page.parent(page_tree_id);
+        page.contents(content_id);
+        page.resources().x_objects().pair(svg_name, svg_id);
+        page.finish();
+
+        let mut content = Content::new();
+        content
+            .transform([width, 0.0, 0.0, height, 0.0, 0.0])
+            .x_object(svg_name);
+        pdf.stream(content_id, &content.finish());
+        page_ids.push(page_id);
+    }
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id)
+        .kids(page_ids.iter().copied())
+        .count(page_ids.len() as i32);
+
+    Ok(pdf.finish())
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::render::svg;
+    use protocol::board_protocol::BoardSize;
+
+    #[test]
+    fn render_page_per_svg() {
+        let size = BoardSize {
+            height: 100,
+            width: 200,
+        };
+        let svgs = vec![svg::render(&size, &[]), svg::render(&size, &[])];
+
+        let pdf = render(&svgs).unwrap_or_default();
+        let pdf = String::from_utf8_lossy(&pdf);
+
+        assert!(pdf.starts_with("%PDF"));
+        assert_eq!(pdf.matches("/Type /Page\n").count(), 2);
+        assert!(pdf.contains("/Count 2"));
+    }
+}
@@ -0,0 +1,85 @@
+use super::{parse, svg::CONTENT_ID, RenderError};
+use resvg::tiny_skia::{Pixmap, Transform};
+
+/// Output images bigger than this are rejected to avoid huge allocations
+const MAX_PIXELS: f32 = 64_000_000.0;
+/// Margin around the content when the image is cropped
+const CROP_MARGIN: f32 = 10.0;
+
+/// scale - ratio of output pixels to board pixels, use dpi / 96 to render with desired DPI
+/// crop - render only the area covered by shapes instead of the whole board
+pub struct PngOptions {
+    pub scale: f32,
+    pub crop: bool,
+}
+
+pub fn render(svg: &str, options: PngOptions) -> Result<Vec<u8>, RenderError> {
+    let tree = parse(svg)?;
+    // determine the area to render
+    let (mut x, mut y) = (0.0, 0.0);
+    let (mut width, mut height) = (tree.size.width(), tree.size.height());
+    if options.crop {
+        if let Some(bbox) = tree
+            .node_by_id(CONTENT_ID)
+            .and_then(|node| node.abs_stroke_bounding_box())
+        {
+            x = (bbox.x() - CROP_MARGIN).max(0.0);
+            y = (bbox.y() - CROP_MARGIN).max(0.0);
+            width = (bbox.right() + CROP_MARGIN).min(width) - x;
+            height = (bbox.bottom() + CROP_MARGIN).min(height) - y;
+        }
+    }
+    let (width, height) = (width * options.scale, height * options.scale);
+    if width * height > MAX_PIXELS {
+        return Err(RenderError::TooLarge);
+    }
+    let mut pixmap =
+        Pixmap::new(width.ceil() as u32, height.ceil() as u32).ok_or(RenderError::TooLarge)?;
+    let transform = Transform::from_scale(options.scale, options.scale).pre_translate(-x, -y);
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+
+    pixmap
+        .encode_png()
+        .map_err(|e| RenderError::Encode(e.to_string()))
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::render::svg;
+    use protocol::board_protocol::{BoardSize, Shape, ShapeType};
+
+    #[test]
+    fn render_crops_to_content() {
+        let size = BoardSize {
+            height: 500,
+            width: 500,
+        };
+        let shape = Shape {
+            shape_type: ShapeType::Rect as i32,
+            x: 100.0,
+            y: 100.0,
+            width: 50.0,
+            height: 50.0,
+            line_size: 2.0,
+            color: "black".to_owned(),
+            ..Default::default()
+        };
+        let svg = svg::render(&size, &[shape]);
+
+        let png = render(
+            &svg,
+            PngOptions {
+                scale: 2.0,
+                crop: true,
+            },
+        )
+        .unwrap_or_default();
+        let pixmap = Pixmap::decode_png(&png).unwrap();
+
+        // (50 + 2 stroke + 2 * 10 margin) * 2 scale
+        assert_eq!((pixmap.width(), pixmap.height()), (144, 144));
+    }
+}
@@ -0,0 +1,402 @@
+use protocol::board_protocol::{BoardSize, LineType, Shape, ShapeType, Tool};
+use std::fmt::Write as _;
+
+// constants that mirror the client's konva settings
+
+const DASH: &str = "10 10";
+const TENSION: f32 = 0.5;
+pub const POINTER_LENGTH: f32 = 10.0;
+pub const POINTER_WIDTH: f32 = 10.0;
+pub const DEFAULT_FONT_SIZE: f32 = 12.0;
+const DEFAULT_FONT_FAMILY: &str = "Arial";
+const DEFAULT_STICKY_NOTE_FILL: &str = "#fff475";
+const STICKY_NOTE_PADDING: f32 = 10.0;
+const BACKGROUND: &str = "white";
+/// id of the group that contains all shapes
+pub const CONTENT_ID: &str = "content";
+
+/// Renders shapes into an svg document of the board's size.
+/// Shapes are expected to be in the order they were added(see render::shapes)
+pub fn render(size: &BoardSize, shapes: &[Shape]) -> String {
+    let (width, height) = (size.width, size.height);
+    let mut defs = String::new();
+    let mut content = String::new();
+
+    for (i, shape) in shapes.iter().enumerate() {
+        let shape_type = ShapeType::try_from(shape.shape_type);
+        // eraser lines remove everything drawn before them
+        if shape.tool == Tool::EraserTool as i32 && shape_type == Ok(ShapeType::Line) {
+            write!(
+                &mut defs,
+                "<mask id=\"eraser-{i}\" maskUnits=\"userSpaceOnUse\" x=\"0\" y=\"0\" width=\"{width}\" height=\"{height}\">\
+                <rect x=\"0\" y=\"0\" width=\"{width}\" height=\"{height}\" fill=\"white\"/>{}</mask>",
+                line(shape, "black")
+            )
+            .unwrap();
+            content = format!("<g mask=\"url(#eraser-{i})\">{content}</g>");
+            continue;
+        }
+        match shape_type {
+            // images are drawn under other shapes
+            Ok(ShapeType::Img) => content.insert_str(0, &image(shape)),
+            Ok(ShapeType::Line) => content.push_str(&line(shape, &shape.color)),
+            Ok(ShapeType::Arrow) => content.push_str(&arrow(shape)),
+            Ok(ShapeType::Rect) => content.push_str(&rect(shape)),
+            Ok(ShapeType::Ellipse) => content.push_str(&ellipse(shape)),
+            Ok(ShapeType::Text) => content.push_str(&text(shape)),
+            Ok(ShapeType::StickyNote) => content.push_str(&sticky_note(shape)),
+            Err(_) => (),
+        }
+    }
+
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+        width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">\
+        <defs>{defs}</defs>\
+        <rect x=\"0\" y=\"0\" width=\"{width}\" height=\"{height}\" fill=\"{BACKGROUND}\"/>\
+        <g id=\"{CONTENT_ID}\">{content}</g></svg>"
+    )
+}
+
+// shapes
+
+fn line(shape: &Shape, color: &str) -> String {
+    format!(
+        "<path transform=\"{}\" d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" \
+        stroke-linecap=\"round\" stroke-linejoin=\"round\"{}/>",
+        transform(shape),
+        line_path(&shape.points),
+        escape(color),
+        shape.line_size,
+        dash(shape)
+    )
+}
+
+fn arrow(shape: &Shape) -> String {
+    let points: Vec<f32> = shape.points.iter().map(|p| *p as f32).collect();
+    let n = points.len();
+    if n < 4 {
+        return line(shape, &shape.color);
+    }
+    // the pointer is directed along the last segment of the curve
+    let tension_points = tension_points(&points);
+    let (dx, dy) = if n > 4 && tension_points.len() >= 6 {
+        let len = tension_points.len();
+        (
+            points[n - 2] - tension_points[len - 2],
+            points[n - 1] - tension_points[len - 1],
+        )
+    } else {
+        (points[n - 2] - points[n - 4], points[n - 1] - points[n - 3])
+    };
+    let angle = dy.atan2(dx).to_degrees();
+    format!(
+        "<g transform=\"{}\">{}<path transform=\"translate({} {}) rotate({})\" \
+        d=\"M0 0 L{} {} L{} {} Z\" fill=\"{color}\" stroke=\"{color}\" stroke-width=\"{}\"/></g>",
+        transform(shape),
+        line(
+            &Shape {
+                x: 0.0,
+                y: 0.0,
+                rotation: 0.0,
+                scale_x: 1.0,
+                scale_y: 1.0,
+                skew_x: 0.0,
+                skew_y: 0.0,
+                ..shape.clone()
+            },
+            &shape.color
+        ),
+        points[n - 2],
+        points[n - 1],
+        angle,
+        -POINTER_LENGTH,
+        POINTER_WIDTH / 2.0,
+        -POINTER_LENGTH,
+        -POINTER_WIDTH / 2.0,
+        shape.line_size,
+        color = escape(&shape.color),
+    )
+}
+
+fn rect(shape: &Shape) -> String {
+    // path is used because svg rect cannot have negative size
+    format!(
+        "<path transform=\"{}\" d=\"M0 0 H{} V{} H0 Z\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"{}/>",
+        transform(shape),
+        shape.width,
+        shape.height,
+        escape(&shape.color),
+        shape.line_size,
+        dash(shape)
+    )
+}
+
+fn ellipse(shape: &Shape) -> String {
+    format!(
+        "<ellipse transform=\"{}\" cx=\"0\" cy=\"0\" rx=\"{}\" ry=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"{}/>",
+        transform(shape),
+        shape.radius_x.abs(),
+        shape.radius_y.abs(),
+        escape(&shape.color),
+        shape.line_size,
+        dash(shape)
+    )
+}
+
+fn image(shape: &Shape) -> String {
+    format!(
+        "<image transform=\"{}\" x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" preserveAspectRatio=\"none\" xlink:href=\"{}\"/>",
+        transform(shape),
+        shape.width,
+        shape.height,
+        escape(&shape.url)
+    )
+}
+
+fn text(shape: &Shape) -> String {
+    format!(
+        "<g transform=\"{}\">{}</g>",
+        transform(shape),
+        text_lines(shape, 0.0)
+    )
+}
+
+fn sticky_note(shape: &Shape) -> String {
+    let fill = match shape.fill.is_empty() {
+        true => DEFAULT_STICKY_NOTE_FILL,
+        false => &shape.fill,
+    };
+    format!(
+        "<g transform=\"{}\"><path d=\"M0 0 H{} V{} H0 Z\" fill=\"{}\"/>{}</g>",
+        transform(shape),
+        shape.width,
+        shape.height,
+        escape(fill),
+        text_lines(shape, STICKY_NOTE_PADDING)
+    )
+}
+
+// helpers
+
+fn text_lines(shape: &Shape, padding: f32) -> String {
+    let font_size = match shape.font_size > 0.0 {
+        true => shape.font_size,
+        false => DEFAULT_FONT_SIZE,
+    };
+    let font_family = match shape.font_family.is_empty() {
+        true => DEFAULT_FONT_FAMILY,
+        false => &shape.font_family,
+    };
+    let mut lines = String::new();
+    for (i, text) in shape.text.lines().enumerate() {
+        write!(
+            &mut lines,
+            "<tspan x=\"{}\" y=\"{}\">{}</tspan>",
+            padding,
+            padding + font_size * (i as f32 + 0.5),
+            escape(text)
+        )
+        .unwrap();
+    }
+    format!(
+        "<text font-size=\"{}\" font-family=\"{}\" fill=\"{}\" dominant-baseline=\"central\" xml:space=\"preserve\">{}</text>",
+        font_size,
+        escape(font_family),
+        escape(&shape.color),
+        lines
+    )
+}
+
+/// Returns svg transform in the same order konva applies it
+fn transform(shape: &Shape) -> String {
+    let mut transform = format!("translate({} {})", shape.x, shape.y);
+    if shape.rotation != 0.0 {
+        write!(&mut transform, " rotate({})", shape.rotation).unwrap();
+    }
+    if shape.skew_x != 0.0 || shape.skew_y != 0.0 {
+        write!(
+            &mut transform,
+            " matrix(1 {} {} 1 0 0)",
+            shape.skew_y, shape.skew_x
+        )
+        .unwrap();
+    }
+    // zero scale means that the field is unset
+    let scale_x = if shape.scale_x == 0.0 {
+        1.0
+    } else {
+        shape.scale_x
+    };
+    let scale_y = if shape.scale_y == 0.0 {
+        1.0
+    } else {
+        shape.scale_y
+    };
+    if scale_x != 1.0 || scale_y != 1.0 {
+        write!(&mut transform, " scale({} {})", scale_x, scale_y).unwrap();
+    }
+    transform
+}
+
+fn dash(shape: &Shape) -> String {
+    match shape.line_type == LineType::Dashed as i32 {
+        true => format!(" stroke-dasharray=\"{DASH}\""),
+        false => String::new(),
+    }
+}
+
+/// Returns path of a smooth line through the points like konva.Line with tension does
+fn line_path(points: &[u32]) -> String {
+    let p: Vec<f32> = points.iter().map(|p| *p as f32).collect();
+    let n = p.len();
+    if n < 2 {
+        return String::new();
+    }
+    let mut path = format!("M{} {}", p[0], p[1]);
+    let tp = tension_points(&p);
+    let len = tp.len();
+    if n > 4 && len >= 6 {
+        write!(&mut path, " Q{} {} {} {}", tp[0], tp[1], tp[2], tp[3]).unwrap();
+        let mut i = 4;
+        while i < len - 2 {
+            write!(
+                &mut path,
+                " C{} {} {} {} {} {}",
+                tp[i],
+                tp[i + 1],
+                tp[i + 2],
+                tp[i + 3],
+                tp[i + 4],
+                tp[i + 5]
+            )
+            .unwrap();
+            i += 6;
+        }
+        write!(
+            &mut path,
+            " Q{} {} {} {}",
+            tp[len - 2],
+            tp[len - 1],
+            p[n - 2],
+            p[n - 1]
+        )
+        .unwrap();
+    } else {
+        for point in p[2..].chunks_exact(2) {
+            write!(&mut path, " L{} {}", point[0], point[1]).unwrap();
+        }
+    }
+    path
+}
+
+/// Returns control points of the curve, the same as konva's Util._expandPoints
+fn tension_points(p: &[f32]) -> Vec<f32> {
+    let mut res = Vec::new();
+    let mut n = 2;
+    while n + 3 < p.len() {
+        let (x0, y0, x1, y1, x2, y2) = (p[n - 2], p[n - 1], p[n], p[n + 1], p[n + 2], p[n + 3]);
+        let d01 = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+        let d12 = ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt();
+        let fa = TENSION * d01 / (d01 + d12);
+        let fb = TENSION * d12 / (d01 + d12);
+        n += 2;
+        if fa.is_nan() || fb.is_nan() {
+            continue;
+        }
+        res.extend_from_slice(&[
+            x1 - fa * (x2 - x0),
+            y1 - fa * (y2 - y0),
+            x1,
+            y1,
+            x1 + fb * (x2 - x0),
+            y1 + fb * (y2 - y0),
+        ]);
+    }
+    res
+}
+
+fn escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&apos;"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_line_sample(tool: Tool, points: Vec<u32>) -> Shape {
+        Shape {
+            tool: tool as i32,
+            shape_type: ShapeType::Line as i32,
+            color: "black".to_owned(),
+            line_size: 2.0,
+            scale_x: 1.0,
+            scale_y: 1.0,
+            points,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn line_path_straight_segment() {
+        assert_eq!(line_path(&[0, 0, 10, 20]), "M0 0 L10 20");
+    }
+
+    #[test]
+    fn line_path_with_tension_ends_in_last_point() {
+        let path = line_path(&[0, 0, 10, 10, 20, 0, 30, 10]);
+
+        assert!(path.starts_with("M0 0 Q"));
+        assert!(path.contains(" C"));
+        assert!(path.ends_with("30 10"));
+    }
+
+    #[test]
+    fn render_eraser_masks_previous_shapes() {
+        let size = BoardSize {
+            height: 100,
+            width: 100,
+        };
+        let shapes = vec![
+            get_line_sample(Tool::PenTool, vec![0, 0, 10, 10]),
+            get_line_sample(Tool::EraserTool, vec![0, 10, 10, 0]),
+            get_line_sample(Tool::PenTool, vec![5, 5, 10, 10]),
+        ];
+
+        let svg = render(&size, &shapes);
+
+        assert!(svg.contains("<mask id=\"eraser-1\""));
+        assert!(svg.contains("<g mask=\"url(#eraser-1)\"><path"));
+        assert!(svg.ends_with("</g><path transform=\"translate(0 0)\" d=\"M5 5 L10 10\" fill=\"none\" stroke=\"black\" stroke-width=\"2\" stroke-linecap=\"round\" stroke-linejoin=\"round\"/></g></svg>"));
+    }
+
+    #[test]
+    fn render_escapes_text() {
+        let size = BoardSize {
+            height: 100,
+            width: 100,
+        };
+        let shape = Shape {
+            shape_type: ShapeType::Text as i32,
+            text: "<b>&</b>".to_owned(),
+            ..Default::default()
+        };
+
+        let svg = render(&size, &[shape]);
+
+        assert!(svg.contains("&lt;b&gt;&amp;&lt;/b&gt;"));
+    }
+}
@@ -1,74 +1,158 @@
 use crate::{
-    entities::{board::delete, edit::sync_with_queue},
+    entities::{
+        board::{delete, update_co_editor_private_id, update_pages, update_undo_mode},
+        edit::sync_with_queue,
+        invite::{save_use, Invite},
+    },
     libs::db_queue::BoardUpdateChunk,
     PoolWrapper,
 };
 
 use super::{
+    assets::{self, AssetBackend},
+    auth::UserData,
     db_queue::DbQueueSender,
-    state::{Command, CommandName, Room},
+    spatial_index::{edit_shapes, Bounds, View},
+    state::{
+        Board, BoardSnapshot, Command, CommandName, EditAuthorship, ExposeId, PushError, Room,
+        RoomHealth, User, OWNER_AUTHOR,
+    },
+    user_queue::{CoalesceKey, UserQueue},
 };
 use axum::body::Bytes;
-use log::debug;
+use log::{error, info};
 use protocol::{
     board_protocol::{
-        server_message::Msg, ActionType, Authed, BoardSize, Edit, EmptyActionType, EmptyData, Info,
-        PushData, QuitData, ServerMessage, SizeData, TitleData, UndoRedoData, UpdateCoEditorData,
+        edit::Edit as EditInner, server_message::Msg, Ack, ActionType, Authed, BoardSize,
+        CursorData, Edit, EmptyActionType, EmptyData, ErrorCode, Info, JoinData, LeaveData, Page,
+        PagesData, PresenceData, PullData, PushData, QuitData, Role, ServerMessage, SessionData,
+        SizeData, TitleData, UndoRedoData, UpdateCoEditorData, Viewport, ViewportData,
     },
     encode_server_msg,
 };
-use std::mem;
-use tokio::sync::{
-    mpsc::{Receiver, Sender, UnboundedSender},
-    oneshot,
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::{
+    select,
+    sync::{
+        mpsc::{Receiver, Sender},
+        oneshot,
+    },
+    time::sleep,
 };
 use uuid::Uuid;
 
-pub type UserChannel = UnboundedSender<Bytes>;
+pub type UserChannel = Arc<UserQueue>;
 pub type RoomChannel = Sender<UserMessage>;
 
+#[derive(Serialize, Deserialize)]
+pub enum RestoreError {
+    Unauthorized,
+    Invalid(String),
+}
+
 pub enum UserMessage {
     // Messages that don't require any auth
+    /// resume - session token and the last sequence number the user has received
     Join {
         user_id: usize,
         chan: UserChannel,
+        user_data: Option<UserData>,
+        resume: Option<(Box<str>, u64)>,
     },
     Quit {
         user_id: usize,
     },
+    /// missed - number of heartbeats the user has not answered in a row
+    Heartbeat {
+        user_id: usize,
+        missed: u32,
+    },
+    SetCursor {
+        user_id: usize,
+        x: f32,
+        y: f32,
+    },
+    SetViewport {
+        user_id: usize,
+        data: Option<Viewport>,
+    },
+    /// viewport - area the user pulls and subscribes to, the whole board if is_none
     Pull {
         user_id: usize,
         current: Vec<Box<str>>,
         undone: Vec<Box<str>>,
+        page_id: Option<Box<str>>,
+        after_seq: Option<u64>,
+        viewport: Option<Viewport>,
     },
-    // Messages that assume user is authed
+    // Messages that require a role(see UserMessage::required_role)
     SetTitle {
         user_id: usize,
         title: Box<str>,
+        request_id: Box<str>,
     },
     UndoRedo {
         user_id: usize,
         action_type: ActionType,
         action_id: Box<str>,
+        request_id: Box<str>,
     },
     Empty {
         user_id: usize,
         action_type: EmptyActionType,
+        request_id: Box<str>,
     },
     Push {
         user_id: usize,
         data: Vec<Edit>,
         silent: bool,
+        request_id: Box<str>,
     },
     SetSize {
         user_id: usize,
         data: Option<BoardSize>,
+        request_id: Box<str>,
+    },
+    CreatePage {
+        user_id: usize,
+        data: Option<Page>,
+        request_id: Box<str>,
+    },
+    DeletePage {
+        user_id: usize,
+        id: Box<str>,
+        request_id: Box<str>,
+    },
+    MovePage {
+        user_id: usize,
+        id: Box<str>,
+        position: usize,
+        request_id: Box<str>,
+    },
+    RenamePage {
+        user_id: usize,
+        id: Box<str>,
+        title: Box<str>,
+        request_id: Box<str>,
     },
     // Messages that implement auth
     Auth {
         user_id: usize,
         token: Box<str>,
-        sender: oneshot::Sender<bool>,
+    },
+    SetRole {
+        db_id: i32,
+        role: Option<Role>,
+    },
+    AddInvite(Invite),
+    RevokeInvite {
+        id: i32,
     },
     GetCoEditorToken {
         private_id: Box<str>,
@@ -86,12 +170,135 @@ pub enum UserMessage {
         deleted: oneshot::Sender<bool>,
         private_id: Box<str>,
     },
+    RestoreCheckpoint {
+        private_id: Box<str>,
+        pages: Vec<Page>,
+        current: Vec<Edit>,
+        sender: oneshot::Sender<Result<usize, RestoreError>>,
+    },
+    SetUndoMode {
+        private_id: Box<str>,
+        per_author: bool,
+        sender: oneshot::Sender<bool>,
+    },
     // Messages that used only by app(user cannot send them)
     HasUsers(oneshot::Sender<bool>),
+    GetHealth(oneshot::Sender<RoomHealth>),
+    GetSnapshot(oneshot::Sender<BoardSnapshot>),
+    /// sender - receives None if neither the token nor the registered user has access to the board
+    GetAuthorship {
+        shape_id: Box<str>,
+        private_id: Option<Box<str>>,
+        db_id: Option<i32>,
+        sender: oneshot::Sender<Option<Vec<EditAuthorship>>>,
+    },
     Expire(oneshot::Sender<()>),
     TryExpireCache,
 }
 
+impl UserMessage {
+    /// Returns the sender's id, the minimal role and the name of the action
+    /// if the message requires a role
+    fn required_role(&self) -> Option<(usize, Role, &'static str)> {
+        match self {
+            UserMessage::Push { user_id, data, .. } => {
+                // commenters can only add new shapes
+                let only_adds = data
+                    .iter()
+                    .all(|edit| matches!(edit.edit, Some(EditInner::Add(_))));
+                let role = if only_adds {
+                    Role::Commenter
+                } else {
+                    Role::Editor
+                };
+                Some((*user_id, role, "Push"))
+            }
+            UserMessage::UndoRedo { user_id, .. } => Some((*user_id, Role::Editor, "UndoRedo")),
+            UserMessage::SetSize { user_id, .. } => Some((*user_id, Role::Editor, "SetSize")),
+            UserMessage::CreatePage { user_id, .. } => Some((*user_id, Role::Editor, "CreatePage")),
+            UserMessage::MovePage { user_id, .. } => Some((*user_id, Role::Editor, "MovePage")),
+            UserMessage::RenamePage { user_id, .. } => Some((*user_id, Role::Editor, "RenamePage")),
+            UserMessage::DeletePage { user_id, .. } => Some((*user_id, Role::Owner, "DeletePage")),
+            UserMessage::Empty { user_id, .. } => Some((*user_id, Role::Owner, "Empty")),
+            UserMessage::SetTitle { user_id, .. } => Some((*user_id, Role::Owner, "SetTitle")),
+            _ => None,
+        }
+    }
+
+    /// Returns the id the sender has given to the operation
+    fn request_id(&self) -> &str {
+        match self {
+            UserMessage::Push { request_id, .. }
+            | UserMessage::UndoRedo { request_id, .. }
+            | UserMessage::Empty { request_id, .. }
+            | UserMessage::SetTitle { request_id, .. }
+            | UserMessage::SetSize { request_id, .. }
+            | UserMessage::CreatePage { request_id, .. }
+            | UserMessage::DeletePage { request_id, .. }
+            | UserMessage::MovePage { request_id, .. }
+            | UserMessage::RenamePage { request_id, .. } => request_id,
+            _ => "",
+        }
+    }
+}
+
+/// Returns the hash of the first asset the edits refer to that is not stored
+async fn missing_asset<'a>(backend: &dyn AssetBackend, edits: &'a [Edit]) -> Option<&'a str> {
+    // invalid hashes are rejected by the board's validation
+    let hashes = edits
+        .iter()
+        .flat_map(assets::referenced)
+        .filter(|hash| assets::is_hash(hash));
+    for hash in hashes {
+        if !backend.exists(hash).await.unwrap_or(false) {
+            return Some(hash);
+        }
+    }
+    None
+}
+
+/// Sends Info with the reason to the sender of a rejected operation,
+/// payload is the human readable detail of the code
+fn reject(
+    room: &Room,
+    user_id: usize,
+    action: &str,
+    request_id: &str,
+    code: ErrorCode,
+    payload: String,
+) {
+    send_by_id(
+        room,
+        user_id,
+        ServerMessage {
+            msg: Some(Msg::Info(Info {
+                status: "bad".to_owned(),
+                action: action.to_owned(),
+                payload,
+                request_id: request_id.to_owned(),
+                code: code.into(),
+            })),
+        },
+    );
+}
+
+/// Confirms the operation to its sender if they have given it an id
+fn ack(room: &Room, user_id: usize, request_id: &str, seq: u64) {
+    if request_id.is_empty() {
+        return;
+    }
+    send_by_id(
+        room,
+        user_id,
+        ServerMessage {
+            msg: Some(Msg::Ack(Ack {
+                request_id: request_id.to_owned(),
+                seq,
+            })),
+        },
+    );
+}
+
 trait ToBytes {
     fn as_bytes(&self) -> Bytes;
 }
@@ -108,81 +315,254 @@ pub async fn task(
     mut room: Room,
     client_pool: &PoolWrapper,
     db_queue: &DbQueueSender,
+    asset_backend: &dyn AssetBackend,
     mut message_receiver: Receiver<UserMessage>,
 ) {
     // handle room events
-    'MessageLoop: while let Some(msg) = message_receiver.recv().await {
+    'MessageLoop: loop {
+        // throttled presence is broadcasted after the throttle, so peers get the latest values
+        let msg = match room.presence_deadline() {
+            Some(deadline) => {
+                let wait = deadline
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default();
+                select! {
+                    msg = message_receiver.recv() => msg,
+                    _ = sleep(wait) => {
+                        flush_presence(&mut room);
+                        continue;
+                    }
+                }
+            }
+            None => message_receiver.recv().await,
+        };
+        let msg = match msg {
+            Some(msg) => msg,
+            None => break,
+        };
+        // check if the sender has enough rights
+        if let Some((user_id, required_role, action)) = msg.required_role() {
+            if room.role(&user_id) < required_role {
+                reject(
+                    &room,
+                    user_id,
+                    action,
+                    msg.request_id(),
+                    ErrorCode::InsufficientRights,
+                    "insufficient rights".to_owned(),
+                );
+                continue;
+            }
+        }
         match msg {
-            UserMessage::Auth {
-                token,
-                user_id,
-                sender,
-            } => {
-                if token.as_ref() == room.private_id()
-                    || token.as_ref() == room.co_editor_private_id()
-                {
-                    if let Ok(_) = sender.send(true) {
-                        send_by_id(
-                            &room,
-                            user_id,
-                            ServerMessage {
-                                msg: Some(Msg::Authed(Authed {})),
-                            },
-                        );
+            UserMessage::Auth { token, user_id } => {
+                let token_role = room.token_role(&token);
+                let invite_id = token_role.and_then(|(_, invite_id)| invite_id);
+                let mut is_new_invite_use = false;
+                if let Some(user) = room.user_mut(&user_id) {
+                    is_new_invite_use = invite_id.is_some() && user.invite_id() != invite_id;
+                    user.set_token_role(token_role.map(|(role, _)| role));
+                    user.set_invite_id(invite_id);
+                    user.set_auth_token(token_role.map(|_| token.clone()));
+                }
+                // save who has joined via the invite
+                if let (true, Some(id)) = (is_new_invite_use, invite_id) {
+                    room.use_invite(id);
+                    let db_id = room.users().get(&user_id).and_then(|u| u.db_id());
+                    info!(
+                        "user {} has authed in room {} via invite {}",
+                        user_id, public_id, id
+                    );
+                    if let Err(e) = save_use(&client_pool.get().await, id, db_id).await {
+                        error!("cannot save invite use: {}", e);
                     }
+                }
+                if token_role.is_some() {
+                    send_by_id(
+                        &room,
+                        user_id,
+                        ServerMessage {
+                            msg: Some(Msg::Authed(Authed {
+                                role: room.role(&user_id).into(),
+                            })),
+                        },
+                    );
                 } else {
-                    let _ = sender.send(false);
+                    reject(
+                        &room,
+                        user_id,
+                        "Auth",
+                        "",
+                        ErrorCode::InvalidToken,
+                        "token is invalid".to_owned(),
+                    );
+                }
+            }
+            UserMessage::AddInvite(invite) => {
+                room.add_invite(invite);
+            }
+            UserMessage::RevokeInvite { id } => {
+                for user_id in room.revoke_invite(id) {
                     send_by_id(
                         &room,
                         user_id,
                         ServerMessage {
-                            msg: Some(Msg::Info(Info {
-                                status: "bad".to_owned(),
-                                action: "Auth".to_owned(),
-                                payload: "token is invalid".to_owned(),
+                            msg: Some(Msg::Authed(Authed {
+                                role: room.role(&user_id).into(),
                             })),
                         },
                     );
                 }
             }
-            UserMessage::Join { user_id, chan } => {
-                room.add_user(user_id.clone(), chan);
-                send_by_id(
+            UserMessage::SetRole { db_id, role } => {
+                for user_id in room.set_granted_role(db_id, role) {
+                    send_by_id(
+                        &room,
+                        user_id,
+                        ServerMessage {
+                            msg: Some(Msg::Authed(Authed {
+                                role: room.role(&user_id).into(),
+                            })),
+                        },
+                    );
+                }
+            }
+            UserMessage::Join {
+                user_id,
+                chan,
+                user_data,
+                resume,
+            } => {
+                let db_id = user_data.as_ref().map(|data| data.id);
+                let resumed = resume.as_ref().and_then(|(token, last_seq)| {
+                    room.resume_user(token, db_id).map(|user| (user, *last_seq))
+                });
+                let (user, last_seq) = match resumed {
+                    Some((mut user, last_seq)) => {
+                        user.resume(chan);
+                        (user, Some(last_seq))
+                    }
+                    None => {
+                        // an expired session keeps its token, so anonymous users keep their author
+                        let session = match resume {
+                            Some((token, _))
+                                if Room::is_session_token(&token) && !room.has_session(&token) =>
+                            {
+                                token
+                            }
+                            _ => Room::generate_session_token().await,
+                        };
+                        (User::new(chan, user_data, session), None)
+                    }
+                };
+                let session = user.session().to_owned();
+                room.add_user(user_id, user);
+                // send presence snapshot to the new user and notify others
+                send_board_state(&room, user_id);
+                // send role if it is granted by db
+                let role = room.role(&user_id);
+                if role != Role::Viewer {
+                    send_by_id(
+                        &room,
+                        user_id,
+                        ServerMessage {
+                            msg: Some(Msg::Authed(Authed { role: role.into() })),
+                        },
+                    );
+                }
+                let member = room.users().get(&user_id).map(|u| u.member(user_id));
+                send_to_everyone(
                     &room,
-                    user_id,
+                    Some(user_id),
                     ServerMessage {
-                        msg: Some(Msg::SizeData(SizeData {
-                            data: Some(room.size().clone()),
-                        })),
+                        msg: Some(Msg::JoinData(JoinData { member })),
                     },
                 );
+                // let the user resume the session after reconnecting
                 send_by_id(
                     &room,
                     user_id,
                     ServerMessage {
-                        msg: Some(Msg::TitleData(TitleData {
-                            title: room.title().to_owned(),
+                        msg: Some(Msg::SessionData(SessionData {
+                            token: session,
+                            seq: room.board.seq(),
+                            resumed: last_seq.is_some(),
                         })),
                     },
                 );
+                // send operations the user has missed while being disconnected
+                if let Some(last_seq) = last_seq {
+                    match room.operations_after(last_seq) {
+                        Some(operations) => {
+                            for msg in operations {
+                                send_by_id(&room, user_id, msg);
+                            }
+                        }
+                        None => {
+                            let pull_data = room.board.pull_all(None).await;
+                            send_by_id(
+                                &room,
+                                user_id,
+                                ServerMessage {
+                                    msg: Some(Msg::PullData(pull_data)),
+                                },
+                            );
+                        }
+                    }
+                }
             }
             UserMessage::Quit { user_id } => {
-                room.remove_user(&user_id);
+                room.suspend_user(&user_id);
+                send_to_everyone(
+                    &room,
+                    None,
+                    ServerMessage {
+                        msg: Some(Msg::LeaveData(LeaveData { id: user_id as u64 })),
+                    },
+                );
+            }
+            UserMessage::Heartbeat { user_id, missed } => {
+                if let Some(user) = room.user_mut(&user_id) {
+                    user.set_missed_heartbeats(missed);
+                }
+            }
+            UserMessage::SetCursor { user_id, x, y } => {
+                let should_send = match room.user_mut(&user_id) {
+                    Some(user) => user.set_cursor(x, y),
+                    None => false,
+                };
+                if should_send {
+                    send_cursor(&room, user_id, x, y);
+                }
+            }
+            UserMessage::SetViewport { user_id, data } => {
+                let data = match data {
+                    Some(d) => d,
+                    None => continue,
+                };
+                let should_send = match room.user_mut(&user_id) {
+                    Some(user) => user.set_viewport(data.clone()),
+                    None => false,
+                };
+                if should_send {
+                    send_viewport(&room, user_id, data);
+                }
             }
 
-            UserMessage::SetTitle { user_id, title } => {
+            UserMessage::SetTitle {
+                user_id,
+                title,
+                request_id,
+            } => {
                 // validate title
                 if let Err(e) = room.board.set_title(title.clone()) {
-                    send_by_id(
+                    reject(
                         &room,
                         user_id,
-                        ServerMessage {
-                            msg: Some(Msg::Info(Info {
-                                status: "bad".to_owned(),
-                                action: "SetTitle".to_owned(),
-                                payload: e.to_string(),
-                            })),
-                        },
+                        "SetTitle",
+                        &request_id,
+                        e.code(),
+                        e.to_string(),
                     );
                     continue;
                 }
@@ -193,6 +573,7 @@ pub async fn task(
                     .send(BoardUpdateChunk {
                         title: room.title().into(),
                         size: room.size().clone(),
+                        seq: room.board.seq_limit(),
                         public_id: room.public_id(),
                         ready: tx,
                     })
@@ -209,68 +590,77 @@ pub async fn task(
                         })),
                     },
                 );
+                ack(&room, user_id, &request_id, 0);
             }
             UserMessage::Push {
                 user_id,
                 mut data,
                 silent,
+                request_id,
             } => {
-                // save changes and validate data
-                if silent {
-                    for edit in data.into_iter() {
-                        match room.board.push(edit).await {
-                            Ok(()) => (),
-                            Err(e) => {
-                                send_by_id(
-                                    &room,
-                                    user_id,
-                                    ServerMessage {
-                                        msg: Some(Msg::Info(Info {
-                                            status: "bad".to_owned(),
-                                            action: "Push".to_owned(),
-                                            payload: e.to_string(),
-                                        })),
-                                    },
-                                );
-                                continue 'MessageLoop;
-                            }
+                // shapes can only refer to uploaded assets
+                if let Some(hash) = missing_asset(asset_backend, &data).await {
+                    reject(
+                        &room,
+                        user_id,
+                        "Push",
+                        &request_id,
+                        ErrorCode::AssetNotFound,
+                        format!("asset {} does not exist", hash),
+                    );
+                    continue;
+                }
+                // record who has made the changes
+                let author = match room.users().get(&user_id) {
+                    Some(user) => user.author().to_owned(),
+                    None => continue,
+                };
+                for edit in data.iter_mut() {
+                    Board::sign(edit, &author);
+                }
+                // validate all edits first, so the operation is accepted or rejected as a whole
+                for edit in data.iter() {
+                    let valid = Board::validate_edit(edit).and_then(|_| {
+                        match room.board.has_page(&edit.page_id) {
+                            true => Ok(()),
+                            false => Err(PushError::PageNotFound),
                         }
+                    });
+                    if let Err(e) = valid {
+                        reject(&room, user_id, "Push", &request_id, e.code(), e.to_string());
+                        continue 'MessageLoop;
                     }
-                } else {
-                    for edit in data.to_owned().into_iter() {
-                        match room.board.push(edit).await {
-                            Ok(()) => (),
-                            Err(e) => {
-                                debug!("failed");
-                                send_by_id(
-                                    &room,
-                                    user_id,
-                                    ServerMessage {
-                                        msg: Some(Msg::Info(Info {
-                                            status: "bad".to_owned(),
-                                            action: "Push".to_owned(),
-                                            payload: e.to_string(),
-                                        })),
-                                    },
-                                );
-                                continue 'MessageLoop;
-                            }
-                        }
+                }
+                // save changes
+                for edit in data.iter_mut() {
+                    room.board.stamp(edit).await;
+                    if let Err(e) = room.board.push(edit.clone()).await {
+                        reject(&room, user_id, "Push", &request_id, e.code(), e.to_string());
+                        continue 'MessageLoop;
                     }
-                    // form data
-                    let push_data = ServerMessage {
-                        msg: Some(Msg::PushData(PushData {
-                            data: mem::take(&mut data),
-                        })),
-                    };
-                    // send
-                    send_to_everyone(&room, Some(user_id), push_data);
                 }
+                let seq = room.board.next_seq().await;
+                let push_data = PushData {
+                    data: mem::take(&mut data),
+                    seq,
+                };
+                // silent pushes are not broadcasted, but users catching up receive them
+                room.log_operation(
+                    seq,
+                    ServerMessage {
+                        msg: Some(Msg::PushData(push_data.clone())),
+                    },
+                );
+                if !silent {
+                    send_push_data(&mut room, Some(user_id), push_data).await;
+                }
+                ack(&room, user_id, &request_id, seq);
             }
             UserMessage::UndoRedo {
                 user_id,
                 action_type,
                 action_id,
+                request_id,
             } => {
                 // determine command name
                 let command_name = if action_type == ActionType::Undo {
@@ -278,72 +668,93 @@ pub async fn task(
                 } else {
                     CommandName::Redo
                 };
+                // owners can undo edits of everyone
+                let author = match room.board.per_author_undo() && room.role(&user_id) < Role::Owner
+                {
+                    true => room.users().get(&user_id).map(|user| user.author().into()),
+                    false => None,
+                };
                 // save changes
                 let exec_command = room
                     .board
                     .exec_command(Command {
                         name: command_name,
                         id: action_id.clone(),
+                        author,
                     })
                     .await;
                 // handle command result
                 match exec_command {
-                    Ok(()) => send_to_everyone(
-                        &room,
-                        Some(user_id),
-                        ServerMessage {
+                    // the edit already has the status, peers have nothing to apply
+                    Ok(false) => ack(&room, user_id, &request_id, room.board.seq()),
+                    Ok(true) => {
+                        let seq = room.board.next_seq().await;
+                        let shape_ids = room.board.edit_shape_ids(&action_id).await;
+                        let undo_redo_data = ServerMessage {
                             msg: Some(Msg::UndoRedoData(UndoRedoData {
                                 action_type: action_type.into(),
                                 action_id: action_id.into(),
+                                seq,
                             })),
-                        },
-                    ),
-                    Err(e) => send_by_id(
+                        };
+                        room.log_operation(seq, undo_redo_data.clone());
+                        send_shapes_operation(
+                            &mut room,
+                            Some(user_id),
+                            &shape_ids,
+                            undo_redo_data,
+                            seq,
+                        )
+                        .await;
+                        ack(&room, user_id, &request_id, seq);
+                    }
+                    Err(e) => reject(
                         &room,
                         user_id,
-                        ServerMessage {
-                            msg: Some(Msg::Info(Info {
-                                status: "bad".to_owned(),
-                                action: "UndoRedo".to_owned(),
-                                payload: e.to_owned(),
-                            })),
-                        },
+                        "UndoRedo",
+                        &request_id,
+                        e.code(),
+                        e.to_string(),
                     ),
                 }
             }
             UserMessage::Empty {
                 user_id,
                 action_type,
+                request_id,
             } => {
                 // save changes
                 match action_type {
                     EmptyActionType::Current => room.board.empty_current().await,
                     EmptyActionType::Undone => room.board.empty_undone().await,
                 }
+                let seq = room.board.next_seq().await;
+                let empty_data = ServerMessage {
+                    msg: Some(Msg::EmptyData(EmptyData {
+                        action_type: action_type.into(),
+                        seq,
+                    })),
+                };
+                room.log_operation(seq, empty_data.clone());
                 // send
-                send_to_everyone(
-                    &room,
-                    Some(user_id),
-                    ServerMessage {
-                        msg: Some(Msg::EmptyData(EmptyData {
-                            action_type: action_type.into(),
-                        })),
-                    },
-                );
+                send_to_everyone(&room, Some(user_id), empty_data);
+                ack(&room, user_id, &request_id, seq);
             }
-            UserMessage::SetSize { user_id, data } => {
+            UserMessage::SetSize {
+                user_id,
+                data,
+                request_id,
+            } => {
                 // update board state
                 if data.is_none() {
-                    send_by_id(
+                    let e = PushError::Missing("size is None");
+                    reject(
                         &room,
                         user_id,
-                        ServerMessage {
-                            msg: Some(Msg::Info(Info {
-                                status: "bad".to_owned(),
-                                action: "SetSize".to_owned(),
-                                payload: "size is None".to_owned(),
-                            })),
-                        },
+                        "SetSize",
+                        &request_id,
+                        e.code(),
+                        e.to_string(),
                     );
                     continue;
                 }
@@ -352,17 +763,13 @@ pub async fn task(
                     .set_size(data.as_ref().unwrap().height, data.as_ref().unwrap().width)
                 {
                     // if size is invalid do nothing
-
-                    send_by_id(
+                    reject(
                         &room,
                         user_id,
-                        ServerMessage {
-                            msg: Some(Msg::Info(Info {
-                                status: "bad".to_owned(),
-                                action: "SetSize".to_owned(),
-                                payload: e.to_string(),
-                            })),
-                        },
+                        "SetSize",
+                        &request_id,
+                        e.code(),
+                        e.to_string(),
                     );
                     continue;
                 }
@@ -373,6 +780,48 @@ pub async fn task(
                         msg: Some(Msg::SizeData(SizeData { data })),
                     },
                 );
+                ack(&room, user_id, &request_id, 0);
+            }
+            UserMessage::CreatePage {
+                user_id,
+                data,
+                request_id,
+            } => {
+                let res = match data {
+                    Some(page) => room.board.create_page(page),
+                    None => Err(PushError::Missing("page is None")),
+                };
+                let action = ("CreatePage", request_id.as_ref());
+                on_pages_change(&room, client_pool, user_id, action, res).await;
+            }
+            UserMessage::DeletePage {
+                user_id,
+                id,
+                request_id,
+            } => {
+                let res = room.board.delete_page(id).await;
+                let action = ("DeletePage", request_id.as_ref());
+                on_pages_change(&room, client_pool, user_id, action, res).await;
+            }
+            UserMessage::MovePage {
+                user_id,
+                id,
+                position,
+                request_id,
+            } => {
+                let res = room.board.move_page(&id, position);
+                let action = ("MovePage", request_id.as_ref());
+                on_pages_change(&room, client_pool, user_id, action, res).await;
+            }
+            UserMessage::RenamePage {
+                user_id,
+                id,
+                title,
+                request_id,
+            } => {
+                let res = room.board.rename_page(&id, title);
+                let action = ("RenamePage", request_id.as_ref());
+                on_pages_change(&room, client_pool, user_id, action, res).await;
             }
             UserMessage::GetUpdatedCoEditorToken { private_id, sender } => {
                 if room.private_id() != private_id.as_ref()
@@ -382,7 +831,13 @@ pub async fn task(
                     continue;
                 }
                 // update co-editor token
-                let _ = sender.send(Ok(room.update_editor_private_id().await));
+                let token = room.update_editor_private_id().await;
+                if let Err(e) =
+                    update_co_editor_private_id(&client_pool.get().await, public_id, &token).await
+                {
+                    error!("cannot save co-editor token: {}", e);
+                }
+                let _ = sender.send(Ok(token));
                 // send message
                 send_to_everyone(
                     &room,
@@ -407,35 +862,129 @@ pub async fn task(
                 user_id,
                 current,
                 undone,
+                page_id,
+                after_seq,
+                viewport,
             } => {
-                let r = room.board.pull(current, undone).await;
+                // pulling a view subscribes the user to changes of its shapes
+                let mut view = viewport
+                    .map(|viewport| View::new(page_id.clone(), Bounds::of_viewport(&viewport)));
+                let visible = match &view {
+                    Some(view) => Some(room.board.visible_shapes(view).await),
+                    None => None,
+                };
+                let previous = room
+                    .users()
+                    .get(&user_id)
+                    .and_then(|user| user.view().cloned());
+                // messages dropped on overflow may have changed the board's state or the user's role
+                let resynced = room
+                    .users()
+                    .get(&user_id)
+                    .is_some_and(|user| user.chan().take_resync());
+                if resynced {
+                    send_board_state(&room, user_id);
+                    send_by_id(
+                        &room,
+                        user_id,
+                        ServerMessage {
+                            msg: Some(Msg::Authed(Authed {
+                                role: room.role(&user_id).into(),
+                            })),
+                        },
+                    );
+                }
+                let r = match after_seq.map(|seq| room.operations_after(seq)) {
+                    // resend the operations, PullData without edits marks the end of them
+                    Some(Some(operations)) => {
+                        if let (Some(view), Some(visible)) = (&mut view, &visible) {
+                            // the user has the shapes of the previous view,
+                            // shapes which have entered the view since then are sent with their edits
+                            let mut sent = HashSet::new();
+                            if let Some(previous) = &previous {
+                                view.receive_from(previous);
+                                let entering = view.entering(
+                                    visible.iter().map(|id| id.as_ref()),
+                                    room.board.spatial_index().await,
+                                );
+                                if !entering.is_empty() {
+                                    let data = room.board.shape_edits(&entering).await;
+                                    sent.extend(data.iter().map(|edit| edit_id(edit).to_owned()));
+                                    let push_data = PushData {
+                                        data,
+                                        seq: after_seq.unwrap_or_default(),
+                                    };
+                                    send_by_id(
+                                        &room,
+                                        user_id,
+                                        ServerMessage {
+                                            msg: Some(Msg::PushData(push_data)),
+                                        },
+                                    );
+                                }
+                            }
+                            view.receive(visible.iter().cloned());
+                            for msg in operations {
+                                if let Some(msg) =
+                                    filter_operation(&mut room.board, view, &sent, msg).await
+                                {
+                                    send_by_id(&room, user_id, msg);
+                                }
+                            }
+                        } else {
+                            for msg in operations {
+                                send_by_id(&room, user_id, msg);
+                            }
+                        }
+                        PullData {
+                            seq: room.board.seq(),
+                            ..Default::default()
+                        }
+                    }
+                    Some(None) => match (&mut view, &visible) {
+                        // the user replaces their state with the view's edits
+                        (Some(view), Some(visible)) => {
+                            view.receive(visible.iter().cloned());
+                            PullData {
+                                reset: true,
+                                ..room
+                                    .board
+                                    .pull(vec![], vec![], page_id.as_deref(), Some(visible))
+                                    .await
+                            }
+                        }
+                        _ => room.board.pull_all(page_id.as_deref()).await,
+                    },
+                    None => {
+                        // shapes the user has are kept, so they are still updated
+                        if let (Some(view), Some(visible)) = (&mut view, &visible) {
+                            if let Some(previous) = &previous {
+                                view.receive_from(previous);
+                            }
+                            view.receive(visible.iter().cloned());
+                        }
+                        room.board
+                            .pull(current, undone, page_id.as_deref(), visible.as_ref())
+                            .await
+                    }
+                };
+                if let Some(user) = room.user_mut(&user_id) {
+                    user.set_view(view);
+                }
                 let pull_data = ServerMessage {
                     msg: Some(Msg::PullData(r)),
                 };
                 send_by_id(&room, user_id, pull_data);
             }
+            UserMessage::GetHealth(sender) => {
+                let _ = sender.send(room.health());
+            }
             UserMessage::HasUsers(sender) => {
-                // if room has no users, stop task execution
-                let users_count = room.users().len();
-                if users_count == 0 {
-                    let (tx, rx) = oneshot::channel();
-                    db_queue
-                        .update_board
-                        .send(BoardUpdateChunk {
-                            title: room.title().into(),
-                            size: room.size().clone(),
-                            public_id: room.public_id(),
-                            ready: tx,
-                        })
-                        .await
-                        .unwrap();
-                    let _ = rx.await;
-                    let _ =
-                        sync_with_queue(db_queue, room.public_id(), room.board.op_queue()).await;
-                    let _ = sender.send(users_count > 0);
-                    break;
-                }
-                let _ = sender.send(users_count > 0);
+                // suspended users keep the room loaded so they can resume their sessions,
+                // the room is stopped by Expire after it is removed from the registry,
+                // so users joining meanwhile are not lost
+                let has_users = !room.users().is_empty() || room.has_suspended_users();
+                let _ = sender.send(has_users);
             }
             UserMessage::DeleteRoom {
                 private_id,
@@ -453,6 +1002,8 @@ pub async fn task(
 
                     let _ = delete(&client_pool.get().await, public_id, &private_id).await;
                     let _ = deleted.send(true);
+                    // the board is gone, so the room stops on any node hosting it
+                    break;
                 } else {
                     let _ = deleted.send(false);
                 }
@@ -464,6 +1015,7 @@ pub async fn task(
                     .send(BoardUpdateChunk {
                         title: room.title().into(),
                         size: room.size().clone(),
+                        seq: room.board.seq_limit(),
                         public_id: room.public_id(),
                         ready: tx,
                     })
@@ -474,6 +1026,75 @@ pub async fn task(
                 let _ = completed.send(());
                 break;
             }
+            UserMessage::RestoreCheckpoint {
+                private_id,
+                pages,
+                current,
+                sender,
+            } => {
+                if room.private_id() != private_id.as_ref() {
+                    let _ = sender.send(Err(RestoreError::Unauthorized));
+                    continue;
+                }
+                // compensating edits are broadcasted like the ones pushed by users
+                let pages_count = room.board.pages().len();
+                match room.board.restore(pages, &current, OWNER_AUTHOR).await {
+                    Ok(edits) => {
+                        // pages deleted after the checkpoint are brought back
+                        if room.board.pages().len() != pages_count {
+                            save_pages(&room, client_pool, None).await;
+                        }
+                        let count = edits.len();
+                        if count > 0 {
+                            let seq = room.board.next_seq().await;
+                            let push_data = PushData { data: edits, seq };
+                            room.log_operation(
+                                seq,
+                                ServerMessage {
+                                    msg: Some(Msg::PushData(push_data.clone())),
+                                },
+                            );
+                            send_push_data(&mut room, None, push_data).await;
+                        }
+                        let _ = sender.send(Ok(count));
+                    }
+                    Err(e) => {
+                        let _ = sender.send(Err(RestoreError::Invalid(e.to_string())));
+                    }
+                }
+            }
+            UserMessage::SetUndoMode {
+                private_id,
+                per_author,
+                sender,
+            } => {
+                if room.private_id() != private_id.as_ref() {
+                    let _ = sender.send(false);
+                    continue;
+                }
+                room.board.set_per_author_undo(per_author);
+                if let Err(e) =
+                    update_undo_mode(&client_pool.get().await, public_id, per_author).await
+                {
+                    error!("cannot save undo mode of room {}: {}", public_id, e);
+                }
+                let _ = sender.send(true);
+            }
+            UserMessage::GetSnapshot(sender) => {
+                let _ = sender.send(room.board.snapshot().await);
+            }
+            UserMessage::GetAuthorship {
+                shape_id,
+                private_id,
+                db_id,
+                sender,
+            } => {
+                if !room.has_access(private_id.as_deref(), db_id) {
+                    let _ = sender.send(None);
+                    continue;
+                }
+                let _ = sender.send(Some(room.board.authorship(&shape_id).await));
+            }
             UserMessage::TryExpireCache => {
                 room.board.clear_db_cache();
             }
@@ -481,17 +1102,315 @@ pub async fn task(
     }
 }
 
+/// Saves pages and sends them to other users if the change is successful,
+/// otherwise reports the error to the sender
+///
+/// action - name of the action and the id the sender has given to it
+async fn on_pages_change(
+    room: &Room,
+    client_pool: &PoolWrapper,
+    user_id: usize,
+    (action, request_id): (&str, &str),
+    res: Result<(), PushError>,
+) {
+    if let Err(e) = res {
+        reject(room, user_id, action, request_id, e.code(), e.to_string());
+        return;
+    }
+    save_pages(room, client_pool, Some(user_id)).await;
+    ack(room, user_id, request_id, 0);
+}
+
+/// Saves pages and sends them to everyone except the specified user
+async fn save_pages(room: &Room, client_pool: &PoolWrapper, except: Option<usize>) {
+    if let Err(e) = update_pages(
+        &client_pool.get().await,
+        room.public_id(),
+        room.board.pages(),
+    )
+    .await
+    {
+        error!("cannot save pages: {}", e);
+    }
+    send_to_everyone(
+        room,
+        except,
+        ServerMessage {
+            msg: Some(Msg::PagesData(PagesData {
+                pages: room.board.pages().clone(),
+            })),
+        },
+    );
+}
+
+/// Sends the board's size, title, pages and the presence snapshot
+fn send_board_state(room: &Room, user_id: usize) {
+    send_by_id(
+        room,
+        user_id,
+        ServerMessage {
+            msg: Some(Msg::SizeData(SizeData {
+                data: Some(room.size().clone()),
+            })),
+        },
+    );
+    send_by_id(
+        room,
+        user_id,
+        ServerMessage {
+            msg: Some(Msg::TitleData(TitleData {
+                title: room.title().to_owned(),
+            })),
+        },
+    );
+    send_by_id(
+        room,
+        user_id,
+        ServerMessage {
+            msg: Some(Msg::PagesData(PagesData {
+                pages: room.board.pages().clone(),
+            })),
+        },
+    );
+    send_by_id(
+        room,
+        user_id,
+        ServerMessage {
+            msg: Some(Msg::PresenceData(PresenceData {
+                self_id: user_id as u64,
+                members: room.members(),
+            })),
+        },
+    );
+}
+
 pub fn send_to_everyone(room: &Room, except: Option<usize>, msg: ServerMessage) {
+    broadcast(room, except, None, msg);
+}
+
+/// Sends pushed edits to everyone. Users with a view receive only edits of shapes they have received,
+/// shapes entering the view are sent with their current edits.
+/// The seq is sent even if no edits are left, so users know they have missed nothing
+async fn send_push_data(room: &mut Room, except: Option<usize>, push_data: PushData) {
+    let shape_ids: HashSet<&str> = push_data
+        .data
+        .iter()
+        .flat_map(edit_shapes)
+        .map(|shape| shape.shape_id.as_str())
+        .collect();
+    let mut entering = entering_edits(room, except, &shape_ids).await;
+    let seq = push_data.seq;
+    let msg = ServerMessage {
+        msg: Some(Msg::PushData(push_data.clone())),
+    }
+    .as_bytes();
+    for (id, user) in room.users().iter() {
+        if except == Some(*id) {
+            continue;
+        }
+        match user.view() {
+            Some(view) => {
+                let mut data = entering.remove(id).unwrap_or_default();
+                // pushed edits of entering shapes are among their current edits
+                let sent: HashSet<String> = data.iter().map(|e| edit_id(e).to_owned()).collect();
+                data.extend(
+                    push_data
+                        .data
+                        .iter()
+                        .filter(|edit| view.contains(edit) && !sent.contains(edit_id(edit)))
+                        .cloned(),
+                );
+                let msg = ServerMessage {
+                    msg: Some(Msg::PushData(PushData { data, seq })),
+                };
+                user.chan().push(msg.as_bytes(), None);
+            }
+            None => {
+                user.chan().push(msg.clone(), None);
+            }
+        }
+    }
+}
+
+/// Sends the operation changing the shapes to everyone.
+/// Users with a view receive it if they have received one of the shapes,
+/// shapes entering the view are sent with their current edits, other users receive only the seq
+async fn send_shapes_operation(
+    room: &mut Room,
+    except: Option<usize>,
+    shape_ids: &HashSet<Box<str>>,
+    msg: ServerMessage,
+    seq: u64,
+) {
+    let ids: HashSet<&str> = shape_ids.iter().map(|id| id.as_ref()).collect();
+    let mut entering = entering_edits(room, except, &ids).await;
+    for (id, user) in room.users().iter() {
+        if except == Some(*id) {
+            continue;
+        }
+        let Some(view) = user.view() else {
+            user.chan().push(msg.as_bytes(), None);
+            continue;
+        };
+        let data = entering.remove(id).unwrap_or_default();
+        let has_shapes = view.has_any(shape_ids);
+        if !data.is_empty() || !has_shapes {
+            let push_data = ServerMessage {
+                msg: Some(Msg::PushData(PushData { data, seq })),
+            };
+            user.chan().push(push_data.as_bytes(), None);
+        }
+        if has_shapes {
+            user.chan().push(msg.as_bytes(), None);
+        }
+    }
+}
+
+/// Returns current edits of the shapes entering views of users by ids of the users,
+/// the shapes are marked as received by the users
+async fn entering_edits(
+    room: &mut Room,
+    except: Option<usize>,
+    shape_ids: &HashSet<&str>,
+) -> HashMap<usize, Vec<Edit>> {
+    // the index exists since the first pull of a view
+    let Some(index) = room.board.spatial() else {
+        return HashMap::new();
+    };
+    let entering: Vec<(usize, HashSet<Box<str>>)> = room
+        .users()
+        .iter()
+        .filter(|(id, _)| except != Some(**id))
+        .filter_map(|(id, user)| {
+            Some((*id, user.view()?.entering(shape_ids.iter().copied(), index)))
+        })
+        .filter(|(_, shapes)| !shapes.is_empty())
+        .collect();
+    if entering.is_empty() {
+        return HashMap::new();
+    }
+    let all: HashSet<Box<str>> = entering
+        .iter()
+        .flat_map(|(_, shapes)| shapes.iter().cloned())
+        .collect();
+    let edits = room.board.shape_edits(&all).await;
+    let mut res = HashMap::new();
+    for (user_id, shapes) in entering {
+        let data = edits
+            .iter()
+            .filter(|edit| edit_shapes(edit).any(|shape| shapes.contains(shape.shape_id.as_str())))
+            .cloned()
+            .collect();
+        if let Some(view) = room.user_mut(&user_id).and_then(|user| user.view_mut()) {
+            view.receive(shapes);
+        }
+        res.insert(user_id, data);
+    }
+    res
+}
+
+/// Returns the logged operation as the user with the view should receive it,
+/// None if the operation does not change shapes the user has.
+/// sent - ids of edits the user has received already
+async fn filter_operation(
+    board: &mut Board,
+    view: &View,
+    sent: &HashSet<String>,
+    msg: ServerMessage,
+) -> Option<ServerMessage> {
+    match msg.msg {
+        Some(Msg::PushData(mut push_data)) => {
+            push_data
+                .data
+                .retain(|edit| view.contains(edit) && !sent.contains(edit_id(edit)));
+            Some(ServerMessage {
+                msg: Some(Msg::PushData(push_data)),
+            })
+        }
+        Some(Msg::UndoRedoData(data)) => {
+            let shape_ids = board.edit_shape_ids(&data.action_id).await;
+            match view.has_any(&shape_ids) {
+                true => Some(ServerMessage {
+                    msg: Some(Msg::UndoRedoData(data)),
+                }),
+                false => None,
+            }
+        }
+        _ => Some(msg),
+    }
+}
+
+fn edit_id(edit: &Edit) -> &str {
+    edit.edit.as_ref().map(|edit| edit.id()).unwrap_or_default()
+}
+
+fn send_cursor(room: &Room, user_id: usize, x: f32, y: f32) {
+    send_to_everyone_coalesced(
+        room,
+        Some(user_id),
+        ("cursor", user_id),
+        ServerMessage {
+            msg: Some(Msg::CursorData(CursorData {
+                id: user_id as u64,
+                x,
+                y,
+            })),
+        },
+    );
+}
+
+fn send_viewport(room: &Room, user_id: usize, data: Viewport) {
+    send_to_everyone_coalesced(
+        room,
+        Some(user_id),
+        ("viewport", user_id),
+        ServerMessage {
+            msg: Some(Msg::ViewportData(ViewportData {
+                id: user_id as u64,
+                data: Some(data),
+            })),
+        },
+    );
+}
+
+/// Broadcasts the latest cursors and viewports which have been throttled
+fn flush_presence(room: &mut Room) {
+    let ids: Vec<usize> = room.users().keys().copied().collect();
+    for user_id in ids {
+        let (cursor, viewport) = match room.user_mut(&user_id) {
+            Some(user) => user.flush_presence(),
+            None => continue,
+        };
+        if let Some((x, y)) = cursor {
+            send_cursor(room, user_id, x, y);
+        }
+        if let Some(viewport) = viewport {
+            send_viewport(room, user_id, viewport);
+        }
+    }
+}
+
+/// Sends the message that replaces queued ones with the same key
+pub fn send_to_everyone_coalesced(
+    room: &Room,
+    except: Option<usize>,
+    key: CoalesceKey,
+    msg: ServerMessage,
+) {
+    broadcast(room, except, Some(key), msg);
+}
+
+fn broadcast(room: &Room, except: Option<usize>, key: Option<CoalesceKey>, msg: ServerMessage) {
     let msg = msg.as_bytes();
 
-    room.users().iter().for_each(|(id, chan)| match except {
+    room.users().iter().for_each(|(id, user)| match except {
         Some(except) => {
             if except != *id {
-                let _ = chan.send(msg.clone());
+                user.chan().push(msg.clone(), key);
             }
         }
         None => {
-            let _ = chan.send(msg.clone());
+            user.chan().push(msg.clone(), key);
         }
     });
 }
@@ -499,7 +1418,846 @@ pub fn send_to_everyone(room: &Room, except: Option<usize>, msg: ServerMessage)
 pub fn send_by_id(room: &Room, id: usize, msg: ServerMessage) {
     let msg = msg.as_bytes();
 
-    if let Some(chan) = room.users().get(&id) {
-        let _ = chan.send(msg);
+    if let Some(user) = room.users().get(&id) {
+        user.chan().push(msg, None);
+    }
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::edit::EditState,
+        libs::{
+            assets::LocalDisk,
+            db_queue::{new_db_queue, DbQueueReceiver, DbQueueSender},
+            state::{ExposeId, DEFAULT_PAGE_ID},
+            user_queue::QueuePolicy,
+        },
+    };
+    use bb8::Pool;
+    use bb8_postgres::PostgresConnectionManager;
+    use protocol::board_protocol::{Add, Modify, Shape, ShapeField};
+    use protocol::decode_server_msg;
+    use std::time::Duration;
+    use tokio::{sync::mpsc::channel, time::timeout};
+    use tokio_postgres::NoTls;
+
+    /// Answers db requests of the room as if the board had no saved edits
+    fn serve_db(mut db: DbQueueReceiver) {
+        tokio::spawn(async move {
+            loop {
+                select! {
+                    Some(chunk) = db.read_edit.recv() => {
+                        let _ = chunk.ready.send(EditState::default());
+                    }
+                    Some(chunk) = db.create_edit.recv() => { let _ = chunk.ready.send(()); }
+                    Some(chunk) = db.update_edit.recv() => { let _ = chunk.ready.send(()); }
+                    Some(chunk) = db.delete_edit.recv() => { let _ = chunk.ready.send(()); }
+                    Some(chunk) = db.create_board.recv() => { let _ = chunk.ready.send(()); }
+                    Some(chunk) = db.update_board.recv() => { let _ = chunk.ready.send(()); }
+                    else => break,
+                }
+            }
+        });
+    }
+
+    /// Spawns a room with an empty board and returns its channel and owner's token.
+    /// The pool is never connected, so messages which use it directly are not tested here
+    async fn get_room_sample() -> (RoomChannel, Box<str>) {
+        get_room_sample_with_seq(0).await
+    }
+
+    /// Spawns a room with an empty board which has saved the seq
+    async fn get_room_sample_with_seq(seq: u64) -> (RoomChannel, Box<str>) {
+        let db_queue = get_db_queue_sample();
+        let mut board = Board::new(db_queue, "title".into(), BoardSize::default(), vec![]);
+        board.set_seq(seq);
+        let room = Room::new(board, None).await;
+        let token = room.private_id().into();
+        (spawn_room(room, db_queue), token)
+    }
+
+    fn get_db_queue_sample() -> &'static DbQueueSender {
+        let (db_queue, db) = new_db_queue();
+        serve_db(db);
+        db_queue
+    }
+
+    fn spawn_room(room: Room, db_queue: &'static DbQueueSender) -> RoomChannel {
+        let manager =
+            PostgresConnectionManager::new_from_stringlike("host=localhost", NoTls).unwrap();
+        let pool: &'static PoolWrapper = Box::leak(Box::new(PoolWrapper {
+            inner: Box::leak(Box::new(Pool::builder().build_unchecked(manager))),
+        }));
+        let assets: &'static LocalDisk = Box::leak(Box::new(LocalDisk::new(
+            std::env::temp_dir().join(format!("b4y-room-{}", Uuid::now_v7())),
+        )));
+        let (tx, rx) = channel(8);
+        tokio::spawn(async move {
+            task(room.public_id(), room, pool, db_queue, assets, rx).await;
+        });
+        tx
+    }
+
+    /// Joins the user and skips messages sent on joining
+    async fn join(room: &RoomChannel, user_id: usize) -> UserChannel {
+        join_session(room, user_id, None).await.0
+    }
+
+    /// Joins the user with the session to resume and returns the session sent to the user
+    async fn join_session(
+        room: &RoomChannel,
+        user_id: usize,
+        resume: Option<(Box<str>, u64)>,
+    ) -> (UserChannel, SessionData) {
+        let chan = UserQueue::new(64, QueuePolicy::Resync, Bytes::new());
+        room.send(UserMessage::Join {
+            user_id,
+            chan: chan.clone(),
+            user_data: None,
+            resume,
+        })
+        .await
+        .unwrap();
+        let session = expect(&chan, |msg| match msg {
+            Msg::SessionData(data) => Some(data),
+            _ => None,
+        })
+        .await;
+        (chan, session)
+    }
+
+    /// Joins the user as the owner
+    async fn join_owner(room: &RoomChannel, user_id: usize, token: &str) -> UserChannel {
+        let chan = join(room, user_id).await;
+        room.send(UserMessage::Auth {
+            user_id,
+            token: token.into(),
+        })
+        .await
+        .unwrap();
+        expect(&chan, |msg| matches!(msg, Msg::Authed(_)).then_some(())).await;
+        chan
+    }
+
+    /// Skips messages until the function returns some
+    async fn expect<T>(chan: &UserChannel, f: impl Fn(Msg) -> Option<T>) -> T {
+        loop {
+            let bytes = timeout(Duration::from_secs(1), chan.pop())
+                .await
+                .expect("no expected message")
+                .unwrap();
+            if let Some(res) = decode_server_msg(&bytes).unwrap().msg.and_then(&f) {
+                return res;
+            }
+        }
+    }
+
+    fn cursor(msg: Msg) -> Option<(f32, f32)> {
+        match msg {
+            Msg::CursorData(data) => Some((data.x, data.y)),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn throttled_cursor_is_sent_after_throttle() {
+        let (room, _) = get_room_sample().await;
+        let peer = join(&room, 1).await;
+        join(&room, 2).await;
+
+        for i in 1..=3 {
+            room.send(UserMessage::SetCursor {
+                user_id: 2,
+                x: i as f32,
+                y: i as f32,
+            })
+            .await
+            .unwrap();
+        }
+        assert_eq!(expect(&peer, cursor).await, (1.0, 1.0));
+        // the last position is sent without waiting for the next update
+        assert_eq!(expect(&peer, cursor).await, (3.0, 3.0));
+    }
+
+    fn get_add_sample(page_id: &str, width: f32) -> Edit {
+        Edit {
+            edit: Some(EditInner::Add(Add {
+                id: Uuid::now_v7().to_string(),
+                shape: Some(Shape {
+                    shape_id: Uuid::now_v7().to_string(),
+                    width,
+                    ..Default::default()
+                }),
+            })),
+            page_id: page_id.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    async fn restore(
+        room: &RoomChannel,
+        private_id: &str,
+        pages: Vec<Page>,
+        current: Vec<Edit>,
+    ) -> Result<usize, RestoreError> {
+        let (tx, rx) = oneshot::channel();
+        room.send(UserMessage::RestoreCheckpoint {
+            private_id: private_id.into(),
+            pages,
+            current,
+            sender: tx,
+        })
+        .await
+        .unwrap();
+        rx.await.unwrap()
+    }
+
+    async fn snapshot(room: &RoomChannel) -> BoardSnapshot {
+        let (tx, rx) = oneshot::channel();
+        room.send(UserMessage::GetSnapshot(tx)).await.unwrap();
+        rx.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn restore_pushes_compensating_edits() {
+        let (room, token) = get_room_sample().await;
+        let peer = join(&room, 1).await;
+
+        let res = restore(
+            &room,
+            &token,
+            vec![],
+            vec![get_add_sample(DEFAULT_PAGE_ID, 10.0)],
+        )
+        .await;
+        assert!(matches!(res, Ok(1)));
+        let pushed = expect(&peer, |msg| match msg {
+            Msg::PushData(data) => Some(data.data),
+            _ => None,
+        })
+        .await;
+        assert_eq!(pushed.len(), 1);
+        assert_eq!(pushed[0].author, OWNER_AUTHOR);
+        assert_eq!(snapshot(&room).await.current.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn restore_requires_private_id() {
+        let (room, _) = get_room_sample().await;
+
+        let res = restore(
+            &room,
+            "wrong",
+            vec![],
+            vec![get_add_sample(DEFAULT_PAGE_ID, 10.0)],
+        )
+        .await;
+        assert!(matches!(res, Err(RestoreError::Unauthorized)));
+        assert!(snapshot(&room).await.current.is_empty());
+    }
+
+    /// Joins the registered user and grants them the role
+    async fn join_with_role(room: &RoomChannel, user_id: usize, role: Role) -> UserChannel {
+        let chan = UserQueue::new(64, QueuePolicy::Resync, Bytes::new());
+        let db_id = user_id as i32;
+        room.send(UserMessage::Join {
+            user_id,
+            chan: chan.clone(),
+            user_data: Some(UserData {
+                id: db_id,
+                login: format!("user{}", user_id),
+                public_login: format!("user{}", user_id),
+                first_name: String::new(),
+                second_name: String::new(),
+            }),
+            resume: None,
+        })
+        .await
+        .unwrap();
+        room.send(UserMessage::SetRole {
+            db_id,
+            role: Some(role),
+        })
+        .await
+        .unwrap();
+        expect(&chan, |msg| matches!(msg, Msg::Authed(_)).then_some(())).await;
+        chan
+    }
+
+    fn get_modify_sample() -> Edit {
+        Edit {
+            edit: Some(EditInner::Modify(Modify {
+                id: Uuid::now_v7().to_string(),
+                current: vec![Shape::default()],
+                initial: vec![Shape::default()],
+                ..Default::default()
+            })),
+            page_id: DEFAULT_PAGE_ID.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    async fn set_title(room: &RoomChannel, user_id: usize, request_id: &str) {
+        room.send(UserMessage::SetTitle {
+            user_id,
+            title: "new title".into(),
+            request_id: request_id.into(),
+        })
+        .await
+        .unwrap();
+    }
+
+    fn insufficient(request_id: &str) -> (String, ErrorCode) {
+        (request_id.to_owned(), ErrorCode::InsufficientRights)
+    }
+
+    #[tokio::test]
+    async fn viewer_cannot_push() {
+        let (room, _) = get_room_sample().await;
+        let viewer = join(&room, 1).await;
+
+        push(&room, 1, get_add_sample(DEFAULT_PAGE_ID, 10.0), "add").await;
+        assert_eq!(expect(&viewer, info).await, insufficient("add"));
+        assert!(snapshot(&room).await.current.is_empty());
+    }
+
+    #[tokio::test]
+    async fn commenter_can_only_add() {
+        let (room, _) = get_room_sample().await;
+        let commenter = join_with_role(&room, 1, Role::Commenter).await;
+        let edit = get_add_sample(DEFAULT_PAGE_ID, 10.0);
+        let action_id = edit.edit.as_ref().unwrap().id().to_owned();
+
+        push(&room, 1, edit, "add").await;
+        assert_eq!(expect(&commenter, ack).await, ("add".to_owned(), 1));
+        push(&room, 1, get_modify_sample(), "modify").await;
+        assert_eq!(expect(&commenter, info).await, insufficient("modify"));
+        room.send(UserMessage::UndoRedo {
+            user_id: 1,
+            action_type: ActionType::Undo,
+            action_id: action_id.into(),
+            request_id: "undo".into(),
+        })
+        .await
+        .unwrap();
+        assert_eq!(expect(&commenter, info).await, insufficient("undo"));
+        set_title(&room, 1, "title").await;
+        assert_eq!(expect(&commenter, info).await, insufficient("title"));
+        assert_eq!(snapshot(&room).await.current.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn only_owner_sets_title() {
+        let (room, token) = get_room_sample().await;
+        let editor = join_with_role(&room, 1, Role::Editor).await;
+        join_owner(&room, 2, &token).await;
+
+        set_title(&room, 1, "title").await;
+        assert_eq!(expect(&editor, info).await, insufficient("title"));
+        set_title(&room, 2, "").await;
+        assert_eq!(&*snapshot(&room).await.title, "new title");
+    }
+
+    #[tokio::test]
+    async fn co_editor_token_survives_reload() {
+        let db_queue = get_db_queue_sample();
+        let board = Board::new(db_queue, "title".into(), BoardSize::default(), vec![]);
+        let room = Room::new(board, None).await;
+        // the tokens are saved with the board when it is created
+        let public_id = room.public_id();
+        let private_id: Box<str> = room.private_id().into();
+        let co_editor_token: Box<str> = room.co_editor_private_id().into();
+        let channel = spawn_room(room, db_queue);
+        let (tx, rx) = oneshot::channel();
+        channel.send(UserMessage::Expire(tx)).await.unwrap();
+        rx.await.unwrap();
+
+        let board = Board::load(
+            db_queue,
+            "title".into(),
+            BoardSize::default(),
+            vec![],
+            public_id,
+        );
+        let room = Room::load(
+            board,
+            private_id,
+            Some(co_editor_token.clone()),
+            None,
+            HashMap::new(),
+            vec![],
+        )
+        .await;
+        let channel = spawn_room(room, db_queue);
+        let chan = join(&channel, 1).await;
+        channel
+            .send(UserMessage::Auth {
+                user_id: 1,
+                token: co_editor_token,
+            })
+            .await
+            .unwrap();
+        let role = expect(&chan, |msg| match msg {
+            Msg::Authed(authed) => Some(authed.role()),
+            _ => None,
+        })
+        .await;
+        assert_eq!(role, Role::Editor);
+    }
+
+    #[tokio::test]
+    async fn invalid_restore_changes_nothing() {
+        let (room, token) = get_room_sample().await;
+        let page = Page {
+            id: Uuid::now_v7().to_string(),
+            title: "page".into(),
+        };
+        // the deleted page is valid, but one of its shapes is not
+        let current = vec![
+            get_add_sample(DEFAULT_PAGE_ID, 10.0),
+            get_add_sample(&page.id, 10.0),
+            get_add_sample(&page.id, f32::MAX),
+        ];
+
+        let res = restore(&room, &token, vec![page], current).await;
+        assert!(matches!(res, Err(RestoreError::Invalid(_))));
+        let board = snapshot(&room).await;
+        assert_eq!(board.pages.len(), 1);
+        assert!(board.current.is_empty());
+    }
+
+    #[tokio::test]
+    async fn expired_session_keeps_its_token() {
+        let (room, _) = get_room_sample().await;
+        let token = Room::generate_session_token().await;
+
+        let (_chan, session) = join_session(&room, 1, Some((token.clone(), 0))).await;
+        assert_eq!(session.token, token.as_ref());
+        assert!(!session.resumed);
+        // the token is in use, so another connection cannot take it
+        let (_, session) = join_session(&room, 2, Some((token.clone(), 0))).await;
+        assert_ne!(session.token, token.as_ref());
+        let (_, session) = join_session(&room, 3, Some(("token".into(), 0))).await;
+        assert_ne!(session.token, "token");
+    }
+
+    #[tokio::test]
+    async fn shape_authors_require_access() {
+        let (room, token) = get_room_sample().await;
+        let authorship = |private_id: Option<&str>| {
+            let room = room.clone();
+            let private_id = private_id.map(Box::from);
+            async move {
+                let (tx, rx) = oneshot::channel();
+                room.send(UserMessage::GetAuthorship {
+                    shape_id: "shape".into(),
+                    private_id,
+                    db_id: Some(1),
+                    sender: tx,
+                })
+                .await
+                .unwrap();
+                rx.await.unwrap()
+            }
+        };
+
+        assert!(authorship(None).await.is_none());
+        assert!(authorship(Some("wrong")).await.is_none());
+        assert!(authorship(Some(&token)).await.is_some());
+    }
+
+    async fn push(room: &RoomChannel, user_id: usize, edit: Edit, request_id: &str) {
+        room.send(UserMessage::Push {
+            user_id,
+            data: vec![edit],
+            silent: false,
+            request_id: request_id.into(),
+        })
+        .await
+        .unwrap();
+    }
+
+    fn ack(msg: Msg) -> Option<(String, u64)> {
+        match msg {
+            Msg::Ack(ack) => Some((ack.request_id, ack.seq)),
+            _ => None,
+        }
+    }
+
+    fn push_seq(msg: Msg) -> Option<u64> {
+        match msg {
+            Msg::PushData(data) => Some(data.seq),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn operations_are_acked_with_their_seq() {
+        let (room, token) = get_room_sample().await;
+        let owner = join_owner(&room, 1, &token).await;
+        let peer = join(&room, 2).await;
+        let edit = get_add_sample(DEFAULT_PAGE_ID, 10.0);
+        let action_id = edit.edit.as_ref().unwrap().id().to_owned();
+
+        push(&room, 1, edit, "first").await;
+        assert_eq!(expect(&owner, ack).await, ("first".to_owned(), 1));
+        assert_eq!(expect(&peer, push_seq).await, 1);
+        push(&room, 1, get_add_sample(DEFAULT_PAGE_ID, 10.0), "second").await;
+        assert_eq!(expect(&owner, ack).await, ("second".to_owned(), 2));
+        assert_eq!(expect(&peer, push_seq).await, 2);
+        room.send(UserMessage::UndoRedo {
+            user_id: 1,
+            action_type: ActionType::Undo,
+            action_id: action_id.into(),
+            request_id: "undo".into(),
+        })
+        .await
+        .unwrap();
+        assert_eq!(expect(&owner, ack).await, ("undo".to_owned(), 3));
+        let undo_seq = expect(&peer, |msg| match msg {
+            Msg::UndoRedoData(data) => Some(data.seq),
+            _ => None,
+        })
+        .await;
+        assert_eq!(undo_seq, 3);
+    }
+
+    fn info(msg: Msg) -> Option<(String, ErrorCode)> {
+        match msg {
+            Msg::Info(info) => Some((info.request_id.clone(), info.code())),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_carry_code_and_request_id() {
+        let (room, token) = get_room_sample().await;
+        let owner = join_owner(&room, 1, &token).await;
+
+        let page_id = Uuid::now_v7().to_string();
+        push(&room, 1, get_add_sample(&page_id, 10.0), "push").await;
+        assert_eq!(
+            expect(&owner, info).await,
+            ("push".to_owned(), ErrorCode::PageNotFound)
+        );
+        room.send(UserMessage::SetTitle {
+            user_id: 1,
+            title: "t".repeat(37).into(),
+            request_id: "title".into(),
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            expect(&owner, info).await,
+            ("title".to_owned(), ErrorCode::ValueTooLarge)
+        );
+    }
+
+    #[tokio::test]
+    async fn board_changes_are_acked() {
+        let (room, token) = get_room_sample().await;
+        let owner = join_owner(&room, 1, &token).await;
+
+        set_title(&room, 1, "title").await;
+        assert_eq!(expect(&owner, ack).await, ("title".to_owned(), 0));
+        room.send(UserMessage::SetSize {
+            user_id: 1,
+            data: Some(BoardSize {
+                height: 100,
+                width: 100,
+            }),
+            request_id: "size".into(),
+        })
+        .await
+        .unwrap();
+        assert_eq!(expect(&owner, ack).await, ("size".to_owned(), 0));
+    }
+
+    #[tokio::test]
+    async fn seq_continues_after_the_saved_one() {
+        let (room, token) = get_room_sample_with_seq(5).await;
+        let owner = join_owner(&room, 1, &token).await;
+
+        push(&room, 1, get_add_sample(DEFAULT_PAGE_ID, 10.0), "first").await;
+        assert_eq!(expect(&owner, ack).await, ("first".to_owned(), 6));
+    }
+
+    #[tokio::test]
+    async fn resumed_user_receives_missed_operations() {
+        let (room, token) = get_room_sample().await;
+        join_owner(&room, 1, &token).await;
+        let (_, session) = join_session(&room, 2, None).await;
+        room.send(UserMessage::Quit { user_id: 2 }).await.unwrap();
+
+        push(&room, 1, get_add_sample(DEFAULT_PAGE_ID, 10.0), "").await;
+        let (chan, resumed) =
+            join_session(&room, 3, Some((session.token.into(), session.seq))).await;
+        assert!(resumed.resumed);
+        assert_eq!(resumed.seq, 1);
+        assert_eq!(expect(&chan, push_seq).await, 1);
+    }
+
+    #[tokio::test]
+    async fn clocks_are_set_by_server() {
+        let (room, token) = get_room_sample().await;
+        join_owner(&room, 1, &token).await;
+        let peer = join(&room, 2).await;
+        let modify = |clock: u64| Edit {
+            edit: Some(EditInner::Modify(Modify {
+                id: Uuid::now_v7().to_string(),
+                current: vec![Shape::default()],
+                initial: vec![Shape::default()],
+                clock,
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let clock = |msg: Msg| match msg {
+            Msg::PushData(mut data) => match data.data.pop()?.edit? {
+                EditInner::Modify(modify) => Some(modify.clock),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        push(&room, 1, modify(u64::MAX), "").await;
+        assert_eq!(expect(&peer, clock).await, 1);
+        push(&room, 1, modify(0), "").await;
+        assert_eq!(expect(&peer, clock).await, 2);
+    }
+
+    #[tokio::test]
+    async fn resync_resends_board_state() {
+        let (room, _) = get_room_sample().await;
+        let chan = join(&room, 1).await;
+        while chan.try_pop().is_some() {}
+        // overflow the queue, so queued messages are replaced with the hint
+        for _ in 0..=64 {
+            chan.push(Bytes::from_static(b"message"), None);
+        }
+        assert_eq!(chan.try_pop(), Some(Bytes::new()));
+
+        room.send(UserMessage::Pull {
+            user_id: 1,
+            current: vec![],
+            undone: vec![],
+            page_id: None,
+            after_seq: Some(0),
+            viewport: None,
+        })
+        .await
+        .unwrap();
+        expect(&chan, |msg| matches!(msg, Msg::TitleData(_)).then_some(())).await;
+        expect(&chan, |msg| {
+            matches!(msg, Msg::PresenceData(_)).then_some(())
+        })
+        .await;
+        expect(&chan, |msg| matches!(msg, Msg::Authed(_)).then_some(())).await;
+        expect(&chan, |msg| matches!(msg, Msg::PullData(_)).then_some(())).await;
+    }
+
+    fn get_rect_sample(shape_id: &str, x: f32, y: f32) -> Shape {
+        Shape {
+            shape_id: shape_id.to_owned(),
+            x,
+            y,
+            width: 10.0,
+            height: 10.0,
+            ..Default::default()
+        }
+    }
+
+    fn get_move_sample(shape_id: &str, x: f32, y: f32) -> Edit {
+        Edit {
+            edit: Some(EditInner::Modify(Modify {
+                id: Uuid::now_v7().to_string(),
+                current: vec![get_rect_sample(shape_id, x, y)],
+                fields: vec![ShapeField::Position as i32],
+                ..Default::default()
+            })),
+            page_id: DEFAULT_PAGE_ID.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn add_rect(shape_id: &str, x: f32, y: f32) -> Edit {
+        Edit {
+            edit: Some(EditInner::Add(Add {
+                id: Uuid::now_v7().to_string(),
+                shape: Some(get_rect_sample(shape_id, x, y)),
+            })),
+            page_id: DEFAULT_PAGE_ID.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Pulls the area 0..100 of the default page
+    async fn pull_view(room: &RoomChannel, user_id: usize, after_seq: Option<u64>) {
+        room.send(UserMessage::Pull {
+            user_id,
+            current: vec![],
+            undone: vec![],
+            page_id: Some(DEFAULT_PAGE_ID.into()),
+            after_seq,
+            viewport: Some(Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: 100.0,
+                height: 100.0,
+            }),
+        })
+        .await
+        .unwrap();
+    }
+
+    fn pulled(msg: Msg) -> Option<Vec<Edit>> {
+        match msg {
+            Msg::PullData(data) => Some(data.current.unwrap_or_default().should_be_created_edits),
+            _ => None,
+        }
+    }
+
+    fn pushed(msg: Msg) -> Option<Vec<Edit>> {
+        match msg {
+            Msg::PushData(data) => Some(data.data),
+            _ => None,
+        }
+    }
+
+    fn shape_ids(edits: &[Edit]) -> Vec<&str> {
+        edits
+            .iter()
+            .flat_map(edit_shapes)
+            .map(|shape| shape.shape_id.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn viewport_pull_sends_edits_of_visible_shapes() {
+        let (room, token) = get_room_sample().await;
+        let owner = join_owner(&room, 1, &token).await;
+        let peer = join(&room, 2).await;
+        push(&room, 1, add_rect("a", 10.0, 10.0), "").await;
+        // b passes through the view and leaves it
+        push(&room, 1, add_rect("b", 1000.0, 1000.0), "").await;
+        push(&room, 1, get_move_sample("b", 50.0, 50.0), "").await;
+        push(&room, 1, get_move_sample("b", 2000.0, 2000.0), "").await;
+        push(&room, 1, add_rect("c", 1000.0, 1000.0), "last").await;
+        expect(&owner, ack).await;
+
+        pull_view(&room, 2, None).await;
+        assert_eq!(shape_ids(&expect(&peer, pulled).await), vec!["a"]);
+    }
+
+    #[tokio::test]
+    async fn shapes_entering_view_are_sent_with_their_edits() {
+        let (room, token) = get_room_sample().await;
+        let owner = join_owner(&room, 1, &token).await;
+        let peer = join(&room, 2).await;
+        pull_view(&room, 2, None).await;
+        expect(&peer, pulled).await;
+
+        // the shape is added outside of the view
+        push(&room, 1, add_rect("a", 1000.0, 1000.0), "").await;
+        assert!(expect(&peer, pushed).await.is_empty());
+        // the shape is moved into the view, the user receives its Add first
+        push(&room, 1, get_move_sample("a", 50.0, 50.0), "").await;
+        let edits = expect(&peer, pushed).await;
+        assert_eq!(edits.len(), 2);
+        assert!(matches!(edits[0].edit, Some(EditInner::Add(_))));
+        assert!(matches!(edits[1].edit, Some(EditInner::Modify(_))));
+        // the shape leaves the view, the user still receives its changes
+        let moved = get_move_sample("a", 2000.0, 2000.0);
+        let action_id = moved.edit.as_ref().unwrap().id().to_owned();
+        push(&room, 1, moved, "").await;
+        assert_eq!(shape_ids(&expect(&peer, pushed).await), vec!["a"]);
+        room.send(UserMessage::UndoRedo {
+            user_id: 1,
+            action_type: ActionType::Undo,
+            action_id: action_id.as_str().into(),
+            request_id: "undo".into(),
+        })
+        .await
+        .unwrap();
+        expect(&owner, |msg| match msg {
+            Msg::Ack(ack) if ack.request_id == "undo" => Some(()),
+            _ => None,
+        })
+        .await;
+        let action = expect(&peer, |msg| match msg {
+            Msg::UndoRedoData(data) => Some(data.action_id),
+            Msg::PushData(_) => Some(String::new()),
+            _ => None,
+        })
+        .await;
+        assert_eq!(action, action_id);
+    }
+
+    #[tokio::test]
+    async fn undo_of_shapes_outside_of_view_sends_seq_only() {
+        let (room, token) = get_room_sample().await;
+        let owner = join_owner(&room, 1, &token).await;
+        let peer = join(&room, 2).await;
+        pull_view(&room, 2, None).await;
+        expect(&peer, pulled).await;
+        let add = add_rect("a", 1000.0, 1000.0);
+        let action_id = add.edit.as_ref().unwrap().id().to_owned();
+        push(&room, 1, add, "").await;
+        assert!(expect(&peer, pushed).await.is_empty());
+
+        room.send(UserMessage::UndoRedo {
+            user_id: 1,
+            action_type: ActionType::Undo,
+            action_id: action_id.as_str().into(),
+            request_id: "undo".into(),
+        })
+        .await
+        .unwrap();
+        let seq = expect(&owner, |msg| match msg {
+            Msg::Ack(ack) if ack.request_id == "undo" => Some(ack.seq),
+            _ => None,
+        })
+        .await;
+        let data = expect(&peer, |msg| match msg {
+            Msg::PushData(data) => Some(data),
+            Msg::UndoRedoData(_) => panic!("the undo is sent to the user without the shape"),
+            _ => None,
+        })
+        .await;
+        assert_eq!((data.data.len(), data.seq), (0, seq));
+    }
+
+    #[tokio::test]
+    async fn missed_operations_are_filtered_by_view() {
+        let (room, token) = get_room_sample().await;
+        let owner = join_owner(&room, 1, &token).await;
+        let peer = join(&room, 2).await;
+        push(&room, 1, add_rect("a", 10.0, 10.0), "").await;
+        push(&room, 1, add_rect("b", 1000.0, 1000.0), "").await;
+        push(&room, 1, get_move_sample("a", 20.0, 20.0), "").await;
+        push(&room, 1, get_move_sample("b", 2000.0, 2000.0), "last").await;
+        expect(&owner, ack).await;
+        // the operations have reached the user, who pulls them again
+        while expect(&peer, push_seq).await < 4 {}
+
+        pull_view(&room, 2, Some(0)).await;
+        let mut received = Vec::new();
+        loop {
+            let msg = expect(&peer, |msg| match msg {
+                Msg::PushData(data) => Some(Some(data.data)),
+                Msg::PullData(_) => Some(None),
+                _ => None,
+            })
+            .await;
+            match msg {
+                Some(edits) => received.extend(edits),
+                None => break,
+            }
+        }
+        assert_eq!(shape_ids(&received), vec!["a", "a"]);
     }
 }
@@ -0,0 +1,110 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use super::state::User;
+
+/// Users who have lost their connection, they are kept for the grace period
+/// so they can resume their sessions by tokens
+///
+/// users - suspended users and the time they have left by session tokens
+pub struct SuspendedSessions {
+    users: HashMap<Box<str>, (User, SystemTime)>,
+    grace: Duration,
+}
+
+impl SuspendedSessions {
+    pub fn new(grace: Duration) -> Self {
+        SuspendedSessions {
+            users: HashMap::new(),
+            grace,
+        }
+    }
+
+    pub fn suspend(&mut self, user: User, now: SystemTime) {
+        self.prune(now);
+        self.users.insert(user.session().into(), (user, now));
+    }
+
+    /// Returns the suspended user if the session has not expired
+    /// and belongs to the same registered user, db_id is None for anonymous users
+    pub fn resume(&mut self, token: &str, db_id: Option<i32>, now: SystemTime) -> Option<User> {
+        self.prune(now);
+        match self.users.get(token) {
+            Some((user, _)) if user.db_id() == db_id => self.users.remove(token).map(|(u, _)| u),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the session has not expired, regardless of its user
+    pub fn contains(&mut self, token: &str, now: SystemTime) -> bool {
+        self.prune(now);
+        self.users.contains_key(token)
+    }
+
+    /// Returns true if there are sessions that can be resumed
+    pub fn has_pending(&mut self, now: SystemTime) -> bool {
+        self.count(now) > 0
+    }
+
+    /// Returns the number of sessions that can be resumed
+    pub fn count(&mut self, now: SystemTime) -> usize {
+        self.prune(now);
+        self.users.len()
+    }
+
+    fn prune(&mut self, now: SystemTime) {
+        let grace = self.grace;
+        self.users.retain(|_, (_, left_at)| {
+            now.duration_since(*left_at)
+                .is_ok_and(|elapsed| elapsed <= grace)
+        });
+    }
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::user_queue::{QueuePolicy, UserQueue};
+    use axum::body::Bytes;
+
+    const GRACE: Duration = Duration::from_secs(60);
+
+    fn get_user_sample(session: &str) -> User {
+        let queue = UserQueue::new(1, QueuePolicy::Resync, Bytes::new());
+        User::new(queue, None, session.into())
+    }
+
+    #[test]
+    fn resume_returns_user_once() {
+        let now = SystemTime::now();
+        let mut sessions = SuspendedSessions::new(GRACE);
+        sessions.suspend(get_user_sample("a"), now);
+
+        assert!(sessions.resume("b", None, now).is_none());
+        assert!(sessions.resume("a", Some(1), now).is_none());
+        assert_eq!(
+            sessions
+                .resume("a", None, now)
+                .map(|u| u.session().to_owned()),
+            Some("a".to_owned())
+        );
+        assert!(sessions.resume("a", None, now).is_none());
+    }
+
+    #[test]
+    fn sessions_expire_after_grace() {
+        let now = SystemTime::now();
+        let mut sessions = SuspendedSessions::new(GRACE);
+        sessions.suspend(get_user_sample("a"), now);
+
+        sessions.suspend(get_user_sample("b"), now + GRACE);
+        assert_eq!(sessions.count(now + GRACE), 2);
+        assert_eq!(sessions.count(now + GRACE * 2), 1);
+        assert!(sessions.resume("a", None, now + GRACE * 2).is_none());
+        assert!(!sessions.has_pending(now + GRACE * 3));
+    }
+}
@@ -0,0 +1,310 @@
+use protocol::board_protocol::{Modify, Shape, ShapeField};
+
+// constants
+
+/// Groups a Modify edit without fields changes
+pub const ALL_FIELDS: [ShapeField; 6] = [
+    ShapeField::Position,
+    ShapeField::Color,
+    ShapeField::Points,
+    ShapeField::Transform,
+    ShapeField::Style,
+    ShapeField::Content,
+];
+
+// functions
+
+/// Returns groups of properties the edit changes
+pub fn fields(modify: &Modify) -> Vec<ShapeField> {
+    if modify.fields.is_empty() {
+        return ALL_FIELDS.to_vec();
+    }
+    modify.fields().collect()
+}
+
+/// Returns true if the edit refers only to existing groups
+pub fn has_valid_fields(modify: &Modify) -> bool {
+    modify
+        .fields
+        .iter()
+        .all(|field| ShapeField::try_from(*field).is_ok())
+}
+
+/// Copies values of the group from one shape to another
+pub fn copy_field(from: &Shape, to: &mut Shape, field: ShapeField) {
+    match field {
+        ShapeField::Position => {
+            to.x = from.x;
+            to.y = from.y;
+        }
+        ShapeField::Color => {
+            to.color.clone_from(&from.color);
+            to.fill.clone_from(&from.fill);
+        }
+        ShapeField::Points => {
+            to.points.clone_from(&from.points);
+            to.connected.clone_from(&from.connected);
+        }
+        ShapeField::Transform => {
+            to.width = from.width;
+            to.height = from.height;
+            to.radius_x = from.radius_x;
+            to.radius_y = from.radius_y;
+            to.rotation = from.rotation;
+            to.scale_x = from.scale_x;
+            to.scale_y = from.scale_y;
+            to.skew_x = from.skew_x;
+            to.skew_y = from.skew_y;
+        }
+        ShapeField::Style => {
+            to.line_size = from.line_size;
+            to.line_type = from.line_type;
+            to.font_size = from.font_size;
+            to.font_family.clone_from(&from.font_family);
+        }
+        ShapeField::Content => {
+            to.text.clone_from(&from.text);
+            to.url.clone_from(&from.url);
+        }
+    }
+}
+
+// merge
+
+/// Stamp of the edit that has set a group, the greater one wins.
+/// Edits pushed before clocks were introduced have clock 0, their ids are ignored,
+/// so they are applied in order like before
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct Stamp {
+    clock: u64,
+    id: Box<str>,
+}
+
+impl Stamp {
+    fn new(modify: &Modify) -> Self {
+        match modify.clock {
+            0 => Stamp::default(),
+            clock => Stamp {
+                clock,
+                id: modify.id.as_str().into(),
+            },
+        }
+    }
+}
+
+/// Shape with stamps of the edits that have set its groups
+#[derive(Clone)]
+pub struct MergedShape {
+    pub shape: Shape,
+    stamps: [Stamp; ALL_FIELDS.len()],
+}
+
+impl MergedShape {
+    pub fn new(shape: Shape) -> Self {
+        MergedShape {
+            shape,
+            stamps: Default::default(),
+        }
+    }
+
+    /// Sets groups the edit changes to values of the modified shape
+    /// unless they are set by an edit with a greater stamp
+    pub fn merge(&mut self, modified: &Shape, modify: &Modify) {
+        let stamp = Stamp::new(modify);
+        for field in fields(modify) {
+            let current = &mut self.stamps[field as usize];
+            if stamp >= *current {
+                *current = stamp.clone();
+                copy_field(modified, &mut self.shape, field);
+            }
+        }
+    }
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_shape_sample() -> Shape {
+        Shape {
+            shape_id: "shape".to_owned(),
+            color: "black".to_owned(),
+            points: vec![0, 0, 10, 10],
+            scale_x: 1.0,
+            scale_y: 1.0,
+            ..Default::default()
+        }
+    }
+
+    fn get_modify_sample(id: &str, clock: u64, fields: &[ShapeField], shape: Shape) -> Modify {
+        Modify {
+            id: id.to_owned(),
+            current: vec![shape],
+            initial: vec![get_shape_sample()],
+            fields: fields.iter().map(|field| *field as i32).collect(),
+            clock,
+        }
+    }
+
+    /// Concurrent edits of two users and a late one that has seen both of them
+    fn get_modifies_sample() -> Vec<Modify> {
+        vec![
+            // the first user drags the shape
+            get_modify_sample(
+                "a",
+                1,
+                &[ShapeField::Position],
+                Shape {
+                    x: 10.0,
+                    y: 20.0,
+                    color: "stale".to_owned(),
+                    ..get_shape_sample()
+                },
+            ),
+            // the second user recolors it at the same time
+            get_modify_sample(
+                "b",
+                1,
+                &[ShapeField::Color],
+                Shape {
+                    x: -1.0,
+                    color: "red".to_owned(),
+                    ..get_shape_sample()
+                },
+            ),
+            // both of them rotate it, the greater id wins
+            get_modify_sample(
+                "c",
+                2,
+                &[ShapeField::Transform],
+                Shape {
+                    rotation: 90.0,
+                    ..get_shape_sample()
+                },
+            ),
+            get_modify_sample(
+                "d",
+                2,
+                &[ShapeField::Transform],
+                Shape {
+                    rotation: 45.0,
+                    ..get_shape_sample()
+                },
+            ),
+            // then the first user moves it again
+            get_modify_sample(
+                "e",
+                3,
+                &[ShapeField::Position, ShapeField::Points],
+                Shape {
+                    x: 30.0,
+                    y: 40.0,
+                    points: vec![0, 0, 20, 20],
+                    ..get_shape_sample()
+                },
+            ),
+        ]
+    }
+
+    fn permutations(len: usize) -> Vec<Vec<usize>> {
+        if len == 0 {
+            return vec![vec![]];
+        }
+        let mut result = Vec::new();
+        for order in permutations(len - 1) {
+            for position in 0..=order.len() {
+                let mut order = order.clone();
+                order.insert(position, len - 1);
+                result.push(order);
+            }
+        }
+        result
+    }
+
+    fn merge_in_order(modifies: &[Modify], order: &[usize]) -> Shape {
+        let mut merged = MergedShape::new(get_shape_sample());
+        for i in order {
+            merged.merge(&modifies[*i].current[0], &modifies[*i]);
+        }
+        merged.shape
+    }
+
+    #[test]
+    fn concurrent_edits_converge() {
+        let modifies = get_modifies_sample();
+        let orders = permutations(modifies.len());
+        assert_eq!(orders.len(), 120);
+
+        let expected = Shape {
+            x: 30.0,
+            y: 40.0,
+            color: "red".to_owned(),
+            rotation: 45.0,
+            points: vec![0, 0, 20, 20],
+            ..get_shape_sample()
+        };
+        for order in orders {
+            assert_eq!(merge_in_order(&modifies, &order), expected, "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn merge_is_idempotent() {
+        let modifies = get_modifies_sample();
+
+        assert_eq!(
+            merge_in_order(&modifies, &[0, 1, 2, 3, 4]),
+            merge_in_order(&modifies, &[4, 0, 3, 1, 2, 4, 1, 0, 3, 2])
+        );
+    }
+
+    #[test]
+    fn undone_edits_revert_only_their_fields() {
+        let modifies = get_modifies_sample();
+
+        // the recoloring is undone, so the edit is not applied
+        let shape = merge_in_order(&modifies, &[4, 3, 2, 0]);
+        assert_eq!(shape.color, "black");
+        assert_eq!((shape.x, shape.y, shape.rotation), (30.0, 40.0, 45.0));
+    }
+
+    #[test]
+    fn edits_without_clock_are_applied_in_order() {
+        let first = get_modify_sample(
+            "b",
+            0,
+            &[],
+            Shape {
+                color: "red".to_owned(),
+                ..get_shape_sample()
+            },
+        );
+        let second = get_modify_sample(
+            "a",
+            0,
+            &[],
+            Shape {
+                color: "blue".to_owned(),
+                ..get_shape_sample()
+            },
+        );
+        let clocked = get_modify_sample(
+            "c",
+            1,
+            &[ShapeField::Position],
+            Shape {
+                x: 5.0,
+                ..get_shape_sample()
+            },
+        );
+        let modifies = [first, second, clocked];
+
+        let shape = merge_in_order(&modifies, &[0, 1, 2]);
+        assert_eq!((shape.color.as_str(), shape.x), ("blue", 5.0));
+        // edits without fields change all groups
+        let shape = merge_in_order(&modifies, &[2, 0, 1]);
+        assert_eq!((shape.color.as_str(), shape.x), ("blue", 5.0));
+    }
+}
@@ -0,0 +1,551 @@
+use super::{
+    render::svg::{DEFAULT_FONT_SIZE, POINTER_LENGTH, POINTER_WIDTH},
+    shape_merge::MergedShape,
+};
+use protocol::board_protocol::{edit::Edit as EditInner, Edit, Shape, ShapeType, Viewport};
+use rstar::{
+    primitives::{GeomWithData, Rectangle},
+    RTree, AABB,
+};
+use std::collections::{HashMap, HashSet};
+
+// constants
+
+/// Approximate width of a character relative to the font size, used for text shapes without width
+const CHAR_WIDTH: f32 = 0.6;
+
+// bounds
+
+/// Axis-aligned rectangle on a page
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    min: [f32; 2],
+    max: [f32; 2],
+}
+
+impl Bounds {
+    /// Returns the smallest rectangle containing the points, non-finite ones are ignored
+    fn from_points(points: &[[f32; 2]]) -> Self {
+        let mut bounds = Bounds {
+            min: [f32::MAX; 2],
+            max: [f32::MIN; 2],
+        };
+        for point in points.iter().filter(|p| p.iter().all(|c| c.is_finite())) {
+            for (i, c) in point.iter().enumerate() {
+                bounds.min[i] = bounds.min[i].min(*c);
+                bounds.max[i] = bounds.max[i].max(*c);
+            }
+        }
+        if bounds.min[0] > bounds.max[0] {
+            return Bounds {
+                min: [0.0; 2],
+                max: [0.0; 2],
+            };
+        }
+        bounds
+    }
+
+    pub fn of_viewport(viewport: &Viewport) -> Self {
+        Bounds::from_points(&[
+            [viewport.x, viewport.y],
+            [viewport.x + viewport.width, viewport.y + viewport.height],
+        ])
+    }
+
+    /// Returns the area the shape covers the way the client draws it
+    pub fn of_shape(shape: &Shape) -> Self {
+        let mut pad = shape.line_size.abs() / 2.0;
+        let local = match ShapeType::try_from(shape.shape_type) {
+            Ok(ShapeType::Line | ShapeType::Arrow) => {
+                if shape.shape_type == ShapeType::Arrow as i32 {
+                    pad += POINTER_LENGTH.max(POINTER_WIDTH);
+                }
+                let points: Vec<[f32; 2]> = shape
+                    .points
+                    .chunks_exact(2)
+                    .map(|p| [p[0] as f32, p[1] as f32])
+                    .collect();
+                Bounds::from_points(&points)
+            }
+            Ok(ShapeType::Ellipse) => Bounds::from_points(&[
+                [-shape.radius_x.abs(), -shape.radius_y.abs()],
+                [shape.radius_x.abs(), shape.radius_y.abs()],
+            ]),
+            Ok(ShapeType::Text) if shape.width == 0.0 || shape.height == 0.0 => {
+                let font_size = match shape.font_size > 0.0 {
+                    true => shape.font_size,
+                    false => DEFAULT_FONT_SIZE,
+                };
+                let lines = shape.text.lines().count().max(1) as f32;
+                let longest = shape
+                    .text
+                    .lines()
+                    .map(|line| line.chars().count())
+                    .max()
+                    .unwrap_or(0) as f32;
+                Bounds::from_points(&[
+                    [0.0, 0.0],
+                    [longest * font_size * CHAR_WIDTH, lines * font_size],
+                ])
+            }
+            _ => Bounds::from_points(&[[0.0, 0.0], [shape.width, shape.height]]),
+        };
+        let corners = [
+            [local.min[0] - pad, local.min[1] - pad],
+            [local.max[0] + pad, local.min[1] - pad],
+            [local.max[0] + pad, local.max[1] + pad],
+            [local.min[0] - pad, local.max[1] + pad],
+        ];
+        Bounds::from_points(&corners.map(|point| transform(shape, point)))
+    }
+
+    pub fn intersects(&self, other: &Bounds) -> bool {
+        (0..2).all(|i| self.min[i] <= other.max[i] && other.min[i] <= self.max[i])
+    }
+
+    fn envelope(&self) -> AABB<[f32; 2]> {
+        AABB::from_corners(self.min, self.max)
+    }
+}
+
+/// Applies the shape's transform to the point in the same order konva does(see render::svg)
+fn transform(shape: &Shape, [x, y]: [f32; 2]) -> [f32; 2] {
+    // zero scale means that the field is unset
+    let scale_x = match shape.scale_x == 0.0 {
+        true => 1.0,
+        false => shape.scale_x,
+    };
+    let scale_y = match shape.scale_y == 0.0 {
+        true => 1.0,
+        false => shape.scale_y,
+    };
+    let (x, y) = (x * scale_x, y * scale_y);
+    let (x, y) = (x + shape.skew_x * y, shape.skew_y * x + y);
+    let (sin, cos) = shape.rotation.to_radians().sin_cos();
+    let (x, y) = (x * cos - y * sin, x * sin + y * cos);
+    [x + shape.x, y + shape.y]
+}
+
+// view
+
+/// Area of the board a user sees
+///
+/// page_id - page the user sees, every page if is_none
+/// shapes - shapes the user has received, their changes are sent even after they leave the area
+#[derive(Clone, Debug)]
+pub struct View {
+    pub page_id: Option<Box<str>>,
+    pub area: Bounds,
+    shapes: HashSet<Box<str>>,
+}
+
+impl View {
+    pub fn new(page_id: Option<Box<str>>, area: Bounds) -> Self {
+        View {
+            page_id,
+            area,
+            shapes: HashSet::new(),
+        }
+    }
+
+    /// Returns true if the user has received one of the edit's shapes
+    pub fn contains(&self, edit: &Edit) -> bool {
+        edit_shapes(edit).any(|shape| self.shapes.contains(shape.shape_id.as_str()))
+    }
+
+    /// Returns true if the user has received one of the shapes
+    pub fn has_any(&self, shape_ids: &HashSet<Box<str>>) -> bool {
+        shape_ids.iter().any(|id| self.shapes.contains(id))
+    }
+
+    /// Returns the shapes which are in the view now, but have not been received by the user
+    pub fn entering<'a>(
+        &self,
+        shape_ids: impl Iterator<Item = &'a str>,
+        index: &SpatialIndex,
+    ) -> HashSet<Box<str>> {
+        shape_ids
+            .filter(|id| !self.shapes.contains(*id))
+            .filter(|id| index.is_visible(id, self))
+            .map(Into::into)
+            .collect()
+    }
+
+    /// Marks the shapes as received by the user
+    pub fn receive(&mut self, shape_ids: impl IntoIterator<Item = Box<str>>) {
+        self.shapes.extend(shape_ids);
+    }
+
+    /// Marks the shapes received in the previous view as received
+    pub fn receive_from(&mut self, previous: &View) {
+        self.shapes.extend(previous.shapes.iter().cloned());
+    }
+}
+
+/// Returns shapes the edit contains
+pub fn edit_shapes(edit: &Edit) -> Box<dyn Iterator<Item = &Shape> + '_> {
+    match &edit.edit {
+        Some(EditInner::Add(add)) => Box::new(add.shape.iter()),
+        Some(EditInner::Remove(remove)) => Box::new(remove.shapes.iter()),
+        Some(EditInner::Modify(modify)) => {
+            Box::new(modify.current.iter().chain(modify.initial.iter()))
+        }
+        None => Box::new(std::iter::empty()),
+    }
+}
+
+/// Returns a copy of the edit which changes only the shapes
+fn retain_shapes(edit: &Edit, shape_ids: &HashSet<&str>) -> Edit {
+    let is_kept = |shape: &Shape| shape_ids.contains(shape.shape_id.as_str());
+    let mut edit = edit.clone();
+    match &mut edit.edit {
+        Some(EditInner::Add(add)) => add.shape = add.shape.take().filter(is_kept),
+        Some(EditInner::Remove(remove)) => remove.shapes.retain(is_kept),
+        Some(EditInner::Modify(modify)) => {
+            modify.current.retain(is_kept);
+            modify.initial.retain(is_kept);
+        }
+        None => (),
+    }
+    edit
+}
+
+// index
+
+type Item = GeomWithData<Rectangle<[f32; 2]>, Box<str>>;
+
+#[derive(Clone)]
+struct Entry {
+    page_id: Box<str>,
+    merged: MergedShape,
+    bounds: Bounds,
+}
+
+/// Areas covered by shapes of the board's pages, lets edits be selected by a visible area.
+/// Shapes are kept merged the way the client draws them, so they are found where they are now
+///
+/// pages - r-trees of the pages' shapes
+/// shapes - shapes by their ids
+#[derive(Clone, Default)]
+pub struct SpatialIndex {
+    pages: HashMap<Box<str>, RTree<Item>>,
+    shapes: HashMap<Box<str>, Entry>,
+}
+
+impl SpatialIndex {
+    /// Builds the index from applied edits of all pages in order
+    pub fn new(current: &[Edit]) -> Self {
+        let mut index = SpatialIndex::default();
+        for edit in current {
+            index.apply(edit);
+        }
+        // trees are loaded at once, it is faster than inserting shapes one by one
+        let mut items: HashMap<&str, Vec<Item>> = HashMap::new();
+        for (id, entry) in index.shapes.iter() {
+            items
+                .entry(&entry.page_id)
+                .or_default()
+                .push(Item::new(rectangle(&entry.bounds), id.clone()));
+        }
+        index.pages = items
+            .into_iter()
+            .map(|(page_id, items)| (page_id.into(), RTree::bulk_load(items)))
+            .collect();
+        index
+    }
+
+    /// Applies the edit pushed to the board
+    pub fn push(&mut self, edit: &Edit) {
+        for (page_id, item, inserted) in self.apply(edit) {
+            let tree = self.pages.entry(page_id).or_default();
+            match inserted {
+                true => tree.insert(item),
+                false => {
+                    tree.remove(&item);
+                }
+            }
+        }
+    }
+
+    /// Returns ids of shapes intersecting the area of the page or of every page if page_id is none
+    pub fn query(&self, page_id: Option<&str>, area: &Bounds) -> HashSet<&str> {
+        let envelope = area.envelope();
+        self.pages
+            .iter()
+            .filter(|(id, _)| page_id.is_none_or(|page_id| page_id == id.as_ref()))
+            .flat_map(|(_, tree)| tree.locate_in_envelope_intersecting(&envelope))
+            .map(|item| item.data.as_ref())
+            .collect()
+    }
+
+    /// Returns true if the shape exists and is in the view
+    pub fn is_visible(&self, shape_id: &str, view: &View) -> bool {
+        self.shapes.get(shape_id).is_some_and(|entry| {
+            view.page_id
+                .as_ref()
+                .is_none_or(|page_id| *page_id == entry.page_id)
+                && view.area.intersects(&entry.bounds)
+        })
+    }
+
+    /// Sets the shapes to the state of their current edits,
+    /// used when edits of the shapes are undone or redone
+    pub fn refresh(&mut self, shape_ids: &HashSet<&str>, current: &[Edit]) {
+        for id in shape_ids {
+            if let Some((page_id, item, _)) = self.remove(id) {
+                if let Some(tree) = self.pages.get_mut(&page_id) {
+                    tree.remove(&item);
+                }
+            }
+        }
+        for edit in current.iter().filter(|edit| {
+            edit_shapes(edit).any(|shape| shape_ids.contains(shape.shape_id.as_str()))
+        }) {
+            self.push(&retain_shapes(edit, shape_ids));
+        }
+    }
+
+    /// Removes shapes of the page
+    pub fn delete_page(&mut self, page_id: &str) {
+        self.pages.remove(page_id);
+        self.shapes
+            .retain(|_, entry| entry.page_id.as_ref() != page_id);
+    }
+
+    /// Removes all shapes
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.shapes.clear();
+    }
+
+    /// Updates shapes, returns changes of the trees: page, item and true if it is inserted
+    fn apply(&mut self, edit: &Edit) -> Vec<(Box<str>, Item, bool)> {
+        let mut changes = Vec::new();
+        match &edit.edit {
+            Some(EditInner::Add(add)) => {
+                if let Some(shape) = &add.shape {
+                    changes.extend(self.remove(&shape.shape_id));
+                    changes.push(self.insert(&edit.page_id, MergedShape::new(shape.clone())));
+                }
+            }
+            Some(EditInner::Remove(remove)) => {
+                for shape in remove.shapes.iter() {
+                    changes.extend(self.remove(&shape.shape_id));
+                }
+            }
+            Some(EditInner::Modify(modify)) => {
+                for modified in modify.current.iter() {
+                    let Some(entry) = self.shapes.get_mut(modified.shape_id.as_str()) else {
+                        continue;
+                    };
+                    entry.merged.merge(modified, modify);
+                    let bounds = Bounds::of_shape(&entry.merged.shape);
+                    if bounds == entry.bounds {
+                        continue;
+                    }
+                    let (page_id, old) = (entry.page_id.clone(), entry.bounds);
+                    entry.bounds = bounds;
+                    let id: Box<str> = modified.shape_id.as_str().into();
+                    changes.push((
+                        page_id.clone(),
+                        Item::new(rectangle(&old), id.clone()),
+                        false,
+                    ));
+                    changes.push((page_id, Item::new(rectangle(&bounds), id), true));
+                }
+            }
+            None => (),
+        }
+        changes
+    }
+
+    fn insert(&mut self, page_id: &str, merged: MergedShape) -> (Box<str>, Item, bool) {
+        let bounds = Bounds::of_shape(&merged.shape);
+        let id: Box<str> = merged.shape.shape_id.as_str().into();
+        self.shapes.insert(
+            id.clone(),
+            Entry {
+                page_id: page_id.into(),
+                merged,
+                bounds,
+            },
+        );
+        (page_id.into(), Item::new(rectangle(&bounds), id), true)
+    }
+
+    fn remove(&mut self, shape_id: &str) -> Option<(Box<str>, Item, bool)> {
+        let entry = self.shapes.remove(shape_id)?;
+        let item = Item::new(rectangle(&entry.bounds), shape_id.into());
+        Some((entry.page_id, item, false))
+    }
+}
+
+fn rectangle(bounds: &Bounds) -> Rectangle<[f32; 2]> {
+    Rectangle::from_corners(bounds.min, bounds.max)
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::board_protocol::{Add, Modify, Remove, ShapeField};
+
+    fn get_rect_sample(id: &str, x: f32, y: f32) -> Shape {
+        Shape {
+            shape_id: id.to_owned(),
+            shape_type: ShapeType::Rect as i32,
+            x,
+            y,
+            width: 10.0,
+            height: 20.0,
+            scale_x: 1.0,
+            scale_y: 1.0,
+            ..Default::default()
+        }
+    }
+
+    fn get_edit_sample(edit: EditInner) -> Edit {
+        Edit {
+            edit: Some(edit),
+            page_id: "page".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn area(x: f32, y: f32, size: f32) -> Bounds {
+        Bounds::of_viewport(&Viewport {
+            x,
+            y,
+            width: size,
+            height: size,
+        })
+    }
+
+    #[test]
+    fn bounds_follow_transform() {
+        let rect = Shape {
+            rotation: 90.0,
+            ..get_rect_sample("rect", 100.0, 100.0)
+        };
+        let bounds = Bounds::of_shape(&rect);
+
+        // rotated clockwise around its origin
+        for (actual, expected) in bounds
+            .min
+            .iter()
+            .chain(bounds.max.iter())
+            .zip([80.0, 100.0, 100.0, 110.0])
+        {
+            assert!((actual - expected).abs() < 0.001);
+        }
+        let scaled = Shape {
+            scale_x: 2.0,
+            ..get_rect_sample("rect", 0.0, 0.0)
+        };
+        assert_eq!(Bounds::of_shape(&scaled).max, [20.0, 20.0]);
+    }
+
+    #[test]
+    fn query_finds_shapes_where_they_are_now() {
+        let edits = vec![
+            get_edit_sample(EditInner::Add(Add {
+                id: "0".to_owned(),
+                shape: Some(get_rect_sample("a", 0.0, 0.0)),
+            })),
+            get_edit_sample(EditInner::Add(Add {
+                id: "1".to_owned(),
+                shape: Some(get_rect_sample("b", 0.0, 0.0)),
+            })),
+        ];
+        let mut index = SpatialIndex::new(&edits);
+        assert_eq!(index.query(Some("page"), &area(-5.0, -5.0, 10.0)).len(), 2);
+        assert!(index
+            .query(Some("other"), &area(-5.0, -5.0, 10.0))
+            .is_empty());
+
+        // a shape is moved away and the other one is removed
+        index.push(&get_edit_sample(EditInner::Modify(Modify {
+            id: "2".to_owned(),
+            current: vec![get_rect_sample("a", 1000.0, 1000.0)],
+            fields: vec![ShapeField::Position as i32],
+            clock: 1,
+            ..Default::default()
+        })));
+        index.push(&get_edit_sample(EditInner::Remove(Remove {
+            id: "3".to_owned(),
+            shapes: vec![get_rect_sample("b", 0.0, 0.0)],
+        })));
+        assert!(index.query(None, &area(-5.0, -5.0, 10.0)).is_empty());
+        assert_eq!(
+            index.query(None, &area(995.0, 995.0, 10.0)),
+            HashSet::from(["a"])
+        );
+    }
+
+    #[test]
+    fn view_contains_edits_of_received_shapes() {
+        let add = get_edit_sample(EditInner::Add(Add {
+            id: "0".to_owned(),
+            shape: Some(get_rect_sample("a", 1000.0, 1000.0)),
+        }));
+        let index = SpatialIndex::new(std::slice::from_ref(&add));
+        let mut view = View::new(Some("page".into()), area(0.0, 0.0, 100.0));
+        assert!(view.entering(["a"].into_iter(), &index).is_empty());
+
+        // the shape passes through the view and leaves it
+        let mut index = index;
+        let moved = |id: &str, x: f32| {
+            get_edit_sample(EditInner::Modify(Modify {
+                id: id.to_owned(),
+                current: vec![get_rect_sample("a", x, x)],
+                fields: vec![ShapeField::Position as i32],
+                clock: id.parse().unwrap(),
+                ..Default::default()
+            }))
+        };
+        index.push(&moved("1", 50.0));
+        assert_eq!(
+            view.entering(["a"].into_iter(), &index),
+            HashSet::from(["a".into()])
+        );
+        assert!(!view.contains(&moved("2", 1000.0)));
+        view.receive(["a".into()]);
+        // changes of received shapes are sent even outside of the view
+        assert!(view.contains(&moved("2", 1000.0)));
+        assert!(view.entering(["a"].into_iter(), &index).is_empty());
+    }
+
+    #[test]
+    fn refreshed_shapes_follow_current_edits() {
+        let add = get_edit_sample(EditInner::Add(Add {
+            id: "0".to_owned(),
+            shape: Some(get_rect_sample("a", 0.0, 0.0)),
+        }));
+        let other = get_edit_sample(EditInner::Add(Add {
+            id: "1".to_owned(),
+            shape: Some(get_rect_sample("b", 0.0, 0.0)),
+        }));
+        let modify = get_edit_sample(EditInner::Modify(Modify {
+            id: "2".to_owned(),
+            current: vec![
+                get_rect_sample("a", 1000.0, 1000.0),
+                get_rect_sample("b", 1000.0, 1000.0),
+            ],
+            fields: vec![ShapeField::Position as i32],
+            clock: 1,
+            ..Default::default()
+        }));
+        let mut index = SpatialIndex::new(&[add.clone(), other.clone(), modify]);
+        assert!(index.query(None, &area(-5.0, -5.0, 10.0)).is_empty());
+
+        // the Modify is undone
+        index.refresh(&HashSet::from(["a", "b"]), &[add, other]);
+        assert_eq!(index.query(None, &area(-5.0, -5.0, 10.0)).len(), 2);
+        assert!(index.query(None, &area(995.0, 995.0, 10.0)).is_empty());
+
+        index.delete_page("page");
+        assert!(index.query(None, &area(-5.0, -5.0, 10.0)).is_empty());
+        assert!(index.shapes.is_empty());
+    }
+}
@@ -1,26 +1,39 @@
 use crate::{
-    entities::edit::{sync_with_queue, EditState, EditStatus},
-    libs::db_queue::EditReadChunk,
-    CACHE_CLEANUP_INTERVAL_SECONDS, OPERATION_QUEUE_SIZE,
+    entities::{
+        edit::{sync_with_queue, EditState, EditStatus},
+        invite::Invite,
+    },
+    libs::{db_queue::EditReadChunk, render, shape_merge},
+    CACHE_CLEANUP_INTERVAL_SECONDS, OPERATION_LOG_SIZE, OPERATION_QUEUE_SIZE, PRESENCE_THROTTLE_MS,
+    SESSION_GRACE_SECONDS,
 };
 
 use super::{
-    db_queue::{DbQueueSender, EditDeleteChunk},
-    room::{UserChannel, UserMessage},
+    assets::{hash, is_hash, parse_url},
+    auth::UserData,
+    db_queue::{BoardUpdateChunk, DbQueueSender, EditDeleteChunk},
+    edit_index::{CommandError, EditIndex},
+    operation_log::OperationLog,
+    registry::RoomRegistry,
+    room::UserChannel,
+    sessions::SuspendedSessions,
+    spatial_index::{edit_shapes, SpatialIndex, View},
 };
 use bb8::PooledConnection;
 use bb8_postgres::PostgresConnectionManager;
 use data_encoding::BASE64URL;
 use jwt_simple::algorithms::HS256Key;
 use protocol::board_protocol::{
-    edit::Edit as EditInner, BoardSize, Edit, EditData, PullData, Shape,
+    edit::Edit as EditInner, Add, BoardSize, Edit, EditData, ErrorCode, Member, Modify, Page,
+    PullData, Remove, Role, ServerMessage, Shape, Viewport,
 };
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
-use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::sync::oneshot;
 use tokio_postgres::NoTls;
 use uuid::Uuid;
 
@@ -28,37 +41,102 @@ use uuid::Uuid;
 /// undone - edits that are not accepted
 /// size - canvas size
 /// title - board's title
+/// pages - ordered list of board's pages, the first one is always the default page
 /// co_editor_private_id - token for co-editors, may change if author asks
+/// index - statuses of edits by their ids, is_none until the first command
+/// spatial - areas covered by shapes, is_none until the first pull of a view, then it is updated in place
+/// per_author_undo - if true, users can undo and redo only their own edits
+/// seq - sequence number of the last accepted operation, grows monotonically
+/// seq_limit - saved upper bound of seq, the board continues after it when it is loaded again,
+/// so numbers sent before a crash are never reused
 #[derive(Clone)]
 pub struct Board {
     db_queue: &'static DbQueueSender,
     db_cache: Option<EditState>,
+    index: Option<EditIndex>,
+    spatial: Option<SpatialIndex>,
+    per_author_undo: bool,
+    seq: u64,
+    seq_limit: u64,
     db_cache_used_at: SystemTime,
     queue: Vec<QueueOp>,
     size: BoardSize,
     title: Box<str>,
+    pages: Vec<Page>,
     public_id: Uuid,
 }
 
+/// connected - users with open connections
+/// unresponsive - connected users who have not answered the last heartbeat
+/// suspended - users who can resume their sessions
+/// queued, largest_queue - messages waiting to be sent to all users and to the slowest one
+/// overflows - how many times queues of connected users have been full
+#[derive(Debug, Default)]
+pub struct RoomHealth {
+    pub connected: usize,
+    pub unresponsive: usize,
+    pub suspended: usize,
+    pub queued: usize,
+    pub largest_queue: usize,
+    pub overflows: u64,
+}
+
+/// current - applied edits of all pages in order of their application
+/// undone - undone edits of all pages
+#[derive(Serialize, Deserialize)]
+pub struct BoardSnapshot {
+    pub title: Box<str>,
+    pub size: BoardSize,
+    pub pages: Vec<Page>,
+    pub current: Vec<Edit>,
+    pub undone: Vec<Edit>,
+}
+
 // queue
 
+// most of queued operations are pushes, so boxing edits would not save memory
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone)]
 pub enum QueueOp {
     Push(SystemTime, Edit),
     Undo(SystemTime, Box<str>),
     Redo(SystemTime, Box<str>),
+    DeletePage(Box<str>),
 }
 
+// seq
+
+/// Number of operations accepted before the saved upper bound of seq is raised again
+const SEQ_RESERVATION: u64 = 1000;
+
+// pages
+
+/// The default page holds edits created before pages were introduced, it cannot be deleted
+pub const DEFAULT_PAGE_ID: &str = "";
+const MAX_PAGES_COUNT: usize = 100;
+
 // shape
 
 const MAX_DIMENSION_SIZE: f32 = 10_000_f32;
-const MAX_IMAGE_LENGTH: u16 = 65_534;
+/// Max length of an embedded image, larger images are stored as assets
+pub const MAX_IMAGE_LENGTH: u16 = 65_534;
+const MAX_TEXT_LENGTH: usize = 10_000;
+const MAX_FONT_SIZE: f32 = 1_000_f32;
+const MAX_FONT_FAMILY_LENGTH: usize = 64;
+const MAX_COLOR_LENGTH: usize = 32;
+
+// authors
+
+/// Author of changes made with the board's private id
+pub const OWNER_AUTHOR: &str = "owner";
 
 // commands
 
+/// author - is_some if the command can be applied only to the author's edits
 pub struct Command {
     pub name: CommandName,
     pub id: Box<str>,
+    pub author: Option<Box<str>>,
 }
 
 #[derive(Debug)]
@@ -67,15 +145,38 @@ pub enum CommandName {
     Redo,
 }
 
+/// Missing - a required field is not set
+/// TooLarge - a size, length or count is above the limit
 #[derive(Debug)]
 pub enum PushError {
+    Missing(&'static str),
     WrongValue(&'static str),
+    TooLarge(&'static str),
+    PageNotFound,
+    PageExists,
+    TooManyPages,
+}
+
+impl PushError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Missing(_) => ErrorCode::MissingField,
+            Self::WrongValue(_) => ErrorCode::InvalidValue,
+            Self::TooLarge(_) => ErrorCode::ValueTooLarge,
+            Self::PageNotFound => ErrorCode::PageNotFound,
+            Self::PageExists => ErrorCode::PageExists,
+            Self::TooManyPages => ErrorCode::TooManyPages,
+        }
+    }
 }
 
 impl ToString for PushError {
     fn to_string(&self) -> String {
         match self {
-            Self::WrongValue(msg) => msg.to_string(),
+            Self::Missing(msg) | Self::WrongValue(msg) | Self::TooLarge(msg) => msg.to_string(),
+            Self::PageNotFound => "page does not exist".to_owned(),
+            Self::PageExists => "page already exists".to_owned(),
+            Self::TooManyPages => "too many pages".to_owned(),
         }
     }
 }
@@ -94,16 +195,41 @@ impl ExposeId for EditInner {
     }
 }
 
+/// author - id of the user who pushed the edit
+/// name - display name of the author if they were authed
+/// kind - "add", "remove" or "modify"
+/// edit_id, created_at, page_id - properties of the edit
+#[derive(Serialize, Deserialize)]
+pub struct EditAuthorship {
+    pub author: String,
+    pub name: Option<String>,
+    pub edit_id: String,
+    pub kind: Box<str>,
+    pub created_at: u64,
+    pub page_id: String,
+}
+
 impl Board {
-    pub fn new(db_queue: &'static DbQueueSender, title: Box<str>, size: BoardSize) -> Self {
+    pub fn new(
+        db_queue: &'static DbQueueSender,
+        title: Box<str>,
+        size: BoardSize,
+        pages: Vec<Page>,
+    ) -> Self {
         Board {
             db_queue,
             db_cache: None,
+            index: None,
+            spatial: None,
+            per_author_undo: false,
+            seq: 0,
+            seq_limit: 0,
             db_cache_used_at: SystemTime::now(),
             queue: Vec::with_capacity(*OPERATION_QUEUE_SIZE),
             public_id: Uuid::now_v7(),
             size,
             title,
+            pages: Board::with_default_page(pages),
         }
     }
 
@@ -111,17 +237,38 @@ impl Board {
         db_queue: &'static DbQueueSender,
         title: Box<str>,
         size: BoardSize,
+        pages: Vec<Page>,
         public_id: Uuid,
     ) -> Self {
         Board {
             db_queue,
             db_cache: None,
+            index: None,
+            spatial: None,
+            per_author_undo: false,
+            seq: 0,
+            seq_limit: 0,
             db_cache_used_at: SystemTime::now(),
             queue: Vec::with_capacity(*OPERATION_QUEUE_SIZE),
             public_id,
             size,
             title,
+            pages: Board::with_default_page(pages),
+        }
+    }
+
+    /// Adds the default page to the beginning of the list if it is not there
+    fn with_default_page(mut pages: Vec<Page>) -> Vec<Page> {
+        if !pages.iter().any(|page| page.id == DEFAULT_PAGE_ID) {
+            pages.insert(
+                0,
+                Page {
+                    id: DEFAULT_PAGE_ID.to_owned(),
+                    title: String::new(),
+                },
+            );
         }
+        pages
     }
 
     pub fn clear_db_cache(&mut self) {
@@ -138,16 +285,13 @@ impl Board {
         }
     }
 
-    /// Returns a diff which lets user sync his state with server's
+    /// Returns edits from db merged with the queue's changes.
+    /// If page_id is some, only the page's edits are returned
     ///
     /// # Panics
     ///
     /// Panics if there is an edit without id property
-    pub async fn pull(
-        &mut self,
-        user_current: Vec<Box<str>>,
-        user_undone: Vec<Box<str>>,
-    ) -> PullData {
+    pub async fn edit_state(&mut self, page_id: Option<&str>) -> EditState {
         // update timestamp to extend cache lifetime
         self.db_cache_used_at = SystemTime::now();
         // try using values from cache or fetch them from db
@@ -197,8 +341,162 @@ impl Board {
                         full_current.push(edit);
                     }
                 }
+                QueueOp::DeletePage(page_id) => {
+                    full_current.retain(|edit| edit.page_id != page_id.as_ref());
+                    full_undone.retain(|edit| edit.page_id != page_id.as_ref());
+                }
+            }
+        }
+        // leave only requested page's edits
+        if let Some(page_id) = page_id {
+            full_current.retain(|edit| edit.page_id == page_id);
+            full_undone.retain(|edit| edit.page_id == page_id);
+        }
+        EditState {
+            current: full_current,
+            undone: full_undone,
+            compacted: res.compacted,
+        }
+    }
+
+    /// Brings back the target pages deleted from the board and pushes edits which turn shapes
+    /// of the board's pages into the target ones, so restoring can be undone like any other change.
+    /// Returns the pushed edits
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any of the pages or edits is invalid,
+    /// the board is not changed in this case
+    pub async fn restore(
+        &mut self,
+        pages: Vec<Page>,
+        target: &[Edit],
+        author: &str,
+    ) -> Result<Vec<Edit>, PushError> {
+        let mut missing: Vec<Page> = Vec::new();
+        for page in pages {
+            if !self.has_page(&page.id) && !missing.iter().any(|p| p.id == page.id) {
+                Self::validate_page(&page)?;
+                missing.push(page);
             }
         }
+        if self.pages.len() + missing.len() > MAX_PAGES_COUNT {
+            return Err(PushError::TooManyPages);
+        }
+        let EditState { current, .. } = self.edit_state(None).await;
+        let mut edits = Vec::new();
+        for page in self.pages.iter().chain(missing.iter()) {
+            let current_shapes = render::shapes(&current, &page.id);
+            let target_shapes = render::shapes(target, &page.id);
+            let current_ids: HashMap<&str, &Shape> = current_shapes
+                .iter()
+                .map(|shape| (shape.shape_id.as_str(), shape))
+                .collect();
+            let target_ids: HashSet<&str> = target_shapes
+                .iter()
+                .map(|shape| shape.shape_id.as_str())
+                .collect();
+            let edit = |edit: EditInner| Edit {
+                edit: Some(edit),
+                page_id: page.id.clone(),
+                ..Default::default()
+            };
+            // shapes created after the target state
+            let removed: Vec<Shape> = current_shapes
+                .iter()
+                .filter(|shape| !target_ids.contains(shape.shape_id.as_str()))
+                .cloned()
+                .collect();
+            if !removed.is_empty() {
+                edits.push(edit(EditInner::Remove(Remove {
+                    id: Uuid::now_v7().to_string(),
+                    shapes: removed,
+                })));
+            }
+            // shapes changed after the target state
+            let (initial, modified): (Vec<Shape>, Vec<Shape>) = target_shapes
+                .iter()
+                .filter_map(|shape| {
+                    current_ids
+                        .get(shape.shape_id.as_str())
+                        .filter(|current| **current != shape)
+                        .map(|current| ((*current).clone(), shape.clone()))
+                })
+                .unzip();
+            if !modified.is_empty() {
+                edits.push(edit(EditInner::Modify(Modify {
+                    id: Uuid::now_v7().to_string(),
+                    current: modified,
+                    initial,
+                    ..Default::default()
+                })));
+            }
+            // shapes deleted after the target state
+            for shape in target_shapes
+                .iter()
+                .filter(|shape| !current_ids.contains_key(shape.shape_id.as_str()))
+            {
+                edits.push(edit(EditInner::Add(Add {
+                    id: Uuid::now_v7().to_string(),
+                    shape: Some(shape.clone()),
+                })));
+            }
+        }
+        // every edit is checked before the board is changed, so restoring is never partial
+        for edit in edits.iter() {
+            Self::validate_edit(edit)?;
+        }
+        self.pages.extend(missing);
+        for edit in edits.iter_mut() {
+            Board::sign(edit, author);
+        }
+        for edit in edits.iter_mut() {
+            self.stamp(edit).await;
+            self.push(edit.clone()).await?;
+        }
+        Ok(edits)
+    }
+
+    /// Returns the board's state needed to render it
+    pub async fn snapshot(&mut self) -> BoardSnapshot {
+        let EditState {
+            current, undone, ..
+        } = self.edit_state(None).await;
+        BoardSnapshot {
+            title: self.title.clone(),
+            size: self.size.clone(),
+            pages: self.pages.clone(),
+            current,
+            undone,
+        }
+    }
+
+    /// Returns a diff which lets user sync his state with server's.
+    /// If page_id is some, only the page's edits are compared with user's ones,
+    /// so user should provide ids of this page only.
+    /// If visible is some, only edits of the shapes are sent,
+    /// the user's edits of other shapes are not deleted
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is an edit without id property
+    pub async fn pull(
+        &mut self,
+        user_current: Vec<Box<str>>,
+        user_undone: Vec<Box<str>>,
+        page_id: Option<&str>,
+        visible: Option<&HashSet<Box<str>>>,
+    ) -> PullData {
+        let EditState {
+            current: full_current,
+            undone: full_undone,
+            ..
+        } = self.edit_state(page_id).await;
+        let in_view = |edit: &Edit| {
+            visible.is_none_or(|visible| {
+                edit_shapes(edit).any(|shape| visible.contains(shape.shape_id.as_str()))
+            })
+        };
         // convert Maps' keys to HashSets
         let current: HashSet<&str> = HashSet::from_iter(
             full_current
@@ -251,6 +549,7 @@ impl Board {
                 should_be_created_edits: full_current
                     .into_iter()
                     .filter(|edit| current_create.contains(edit.edit.as_ref().unwrap().id()))
+                    .filter(in_view)
                     .collect(),
                 should_be_deleted_ids: Vec::from_iter(current_delete.into_iter().map(|v| v.into())),
             }),
@@ -258,12 +557,35 @@ impl Board {
                 should_be_created_edits: full_undone
                     .into_iter()
                     .filter(|edit| undone_create.contains(edit.edit.as_ref().unwrap().id()))
+                    .filter(in_view)
                     .collect(),
                 should_be_deleted_ids: Vec::from_iter(undone_delete.into_iter().map(|v| v.into())),
             }),
+            seq: self.seq,
+            reset: false,
         };
     }
 
+    /// Returns all edits, so user can replace his state with them.
+    /// If page_id is some, only the page's edits are returned
+    pub async fn pull_all(&mut self, page_id: Option<&str>) -> PullData {
+        let EditState {
+            current, undone, ..
+        } = self.edit_state(page_id).await;
+        PullData {
+            current: Some(EditData {
+                should_be_created_edits: current,
+                should_be_deleted_ids: vec![],
+            }),
+            undone: Some(EditData {
+                should_be_created_edits: undone,
+                should_be_deleted_ids: vec![],
+            }),
+            seq: self.seq,
+            reset: true,
+        }
+    }
+
     /// Pushes a new edit to self.current or saves current buffer to db
     ///
     /// # Errors
@@ -274,6 +596,9 @@ impl Board {
     /// - if the edit_type is not add or remove or modify
     pub async fn push(&mut self, edit: Edit) -> Result<(), PushError> {
         Self::validate_edit(&edit)?;
+        if !self.has_page(&edit.page_id) {
+            return Err(PushError::PageNotFound);
+        }
         // if the queue will be overflowed, clear it and save to db
         if self.queue.len() + 1 > *OPERATION_QUEUE_SIZE {
             self.db_cache = None;
@@ -284,13 +609,50 @@ impl Board {
             )
             .await;
         }
+        if let Some(index) = &mut self.index {
+            index.push(&edit);
+        }
+        if let Some(spatial) = &mut self.spatial {
+            spatial.push(&edit);
+        }
         self.queue.push(QueueOp::Push(SystemTime::now(), edit));
         Ok(())
     }
 
+    /// Sets the clock of a Modify edit, so the edit wins over every known one.
+    /// Clocks sent by users are overwritten, so they cannot make their edits win forever
+    pub async fn stamp(&mut self, edit: &mut Edit) {
+        if let Some(EditInner::Modify(modify)) = &mut edit.edit {
+            modify.clock = self.edit_index().await.clock().saturating_add(1);
+        }
+    }
+
+    /// Sets the edit's author and creation time, values sent by users are overwritten
+    pub fn sign(edit: &mut Edit, author: &str) {
+        edit.author = author.to_owned();
+        edit.created_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+    }
+
+    /// Signs edits imported with a new board by the importer, so authors sent by users
+    /// cannot attribute edits to others, and renumbers clocks of Modify edits in order,
+    /// so imported clocks cannot make the edits win over live ones
+    pub fn adopt_imported<'a>(edits: impl Iterator<Item = &'a mut Edit>, author: &str) {
+        let mut clock = 0;
+        for edit in edits {
+            Self::sign(edit, author);
+            if let Some(EditInner::Modify(modify)) = &mut edit.edit {
+                clock += 1;
+                modify.clock = clock;
+            }
+        }
+    }
+
     pub fn validate_edit(edit: &Edit) -> Result<(), PushError> {
         if edit.edit.is_none() {
-            return Err(PushError::WrongValue("edit is None"));
+            return Err(PushError::Missing("edit is None"));
         }
         // check id
         if Uuid::parse_str(edit.edit.as_ref().unwrap().id()).is_err()
@@ -300,11 +662,13 @@ impl Board {
                 "id must be valid uuid with hyphens(36 symbols)",
             ));
         }
+        // check page id
+        Self::validate_page_id(&edit.page_id)?;
         // check shapes
         match edit.edit.as_ref().unwrap() {
             EditInner::Add(ref e) => {
                 if e.shape.is_none() {
-                    return Err(PushError::WrongValue("shape is None"));
+                    return Err(PushError::Missing("shape is None"));
                 }
                 Board::validate_shape(&e.shape.as_ref().unwrap())?;
             }
@@ -314,6 +678,9 @@ impl Board {
                 }
             }
             EditInner::Modify(ref e) => {
+                if !shape_merge::has_valid_fields(e) {
+                    return Err(PushError::WrongValue("fields contain unknown values"));
+                }
                 for shape in e.current.iter() {
                     Board::validate_shape(&shape)?;
                 }
@@ -326,64 +693,138 @@ impl Board {
         Ok(())
     }
 
-    fn validate_shape(shape: &Shape) -> Result<(), PushError> {
+    pub fn validate_shape(shape: &Shape) -> Result<(), PushError> {
         if shape.line_size > MAX_DIMENSION_SIZE {
-            return Err(PushError::WrongValue("line_size is too large"));
+            return Err(PushError::TooLarge("line_size is too large"));
         }
         if shape.height > MAX_DIMENSION_SIZE || shape.width > MAX_DIMENSION_SIZE {
-            return Err(PushError::WrongValue("height or width is too large"));
+            return Err(PushError::TooLarge("height or width is too large"));
         }
         if shape.radius_x > MAX_DIMENSION_SIZE || shape.radius_y > MAX_DIMENSION_SIZE {
-            return Err(PushError::WrongValue("radius_x or radius_y is too large"));
+            return Err(PushError::TooLarge("radius_x or radius_y is too large"));
         }
         if shape.scale_x > MAX_DIMENSION_SIZE || shape.scale_y > MAX_DIMENSION_SIZE {
-            return Err(PushError::WrongValue("scale_x or scale_y is too large"));
+            return Err(PushError::TooLarge("scale_x or scale_y is too large"));
         }
         if shape.url.len().try_into().unwrap_or(u16::MAX) > MAX_IMAGE_LENGTH {
-            return Err(PushError::WrongValue("image is too large"));
+            return Err(PushError::TooLarge("image is too large"));
+        }
+        if parse_url(&shape.url).is_some_and(|hash| !is_hash(hash)) {
+            return Err(PushError::WrongValue("asset url is invalid"));
+        }
+        if shape.text.len() > MAX_TEXT_LENGTH {
+            return Err(PushError::TooLarge("text is too long"));
+        }
+        if !(0.0..=MAX_FONT_SIZE).contains(&shape.font_size) {
+            return Err(PushError::WrongValue("font_size is too large or negative"));
+        }
+        if shape.font_family.len() > MAX_FONT_FAMILY_LENGTH {
+            return Err(PushError::TooLarge("font_family is too long"));
+        }
+        if shape.fill.len() > MAX_COLOR_LENGTH {
+            return Err(PushError::TooLarge("fill is too long"));
         }
 
         Ok(())
     }
 
-    /// Executes Undo and Redo commands
+    /// Executes Undo and Redo commands.
+    /// Returns false if the edit already has the status the command sets, nothing is done then
     ///
     /// # Errors
     ///
-    /// This function will return an error if command.id does not exit in self.current or self.undone
-    pub async fn exec_command(&mut self, command: Command) -> Result<(), &'static str> {
-        match command.name {
-            CommandName::Undo => {
-                // if undone will be overflowed, clear it and save to db
-                if self.queue.len() + 1 > *OPERATION_QUEUE_SIZE {
-                    self.db_cache = None;
-                    sync_with_queue(
-                        self.db_queue,
-                        self.public_id,
-                        self.queue.drain(..).collect(),
-                    )
-                    .await;
-                }
-                self.queue
-                    .push(QueueOp::Undo(SystemTime::now(), command.id));
-            }
-            CommandName::Redo => {
-                // if current will be overflowed, clear it and save to db
-                if self.queue.len() + 1 > *OPERATION_QUEUE_SIZE {
-                    self.db_cache = None;
-                    sync_with_queue(
-                        self.db_queue,
-                        self.public_id,
-                        self.queue.drain(..).collect(),
-                    )
-                    .await;
-                }
-                self.queue
-                    .push(QueueOp::Redo(SystemTime::now(), command.id));
-            }
+    /// This function will return an error if command.id does not exist in self.current or self.undone,
+    /// if it is an edit restored from a snapshot or if the edit belongs to another author
+    pub async fn exec_command(&mut self, command: Command) -> Result<bool, CommandError> {
+        let changed =
+            self.edit_index()
+                .await
+                .apply(&command.name, &command.id, command.author.as_deref())?;
+        if !changed {
+            return Ok(false);
+        }
+        // if the queue will be overflowed, clear it and save to db
+        if self.queue.len() + 1 > *OPERATION_QUEUE_SIZE {
+            self.db_cache = None;
+            sync_with_queue(
+                self.db_queue,
+                self.public_id,
+                self.queue.drain(..).collect(),
+            )
+            .await;
+        }
+        let op = match command.name {
+            CommandName::Undo => QueueOp::Undo(SystemTime::now(), command.id.clone()),
+            CommandName::Redo => QueueOp::Redo(SystemTime::now(), command.id.clone()),
         };
+        self.queue.push(op);
+        // undoing a Modify restores groups of shapes set by earlier edits,
+        // so the edit's shapes get the state of their current edits
+        if self.spatial.is_some() {
+            let shape_ids = self.edit_shape_ids(&command.id).await;
+            let state = self.edit_state(None).await;
+            if let Some(spatial) = &mut self.spatial {
+                spatial.refresh(
+                    &shape_ids.iter().map(|id| id.as_ref()).collect(),
+                    &state.current,
+                );
+            }
+        }
 
-        Ok(())
+        Ok(true)
+    }
+
+    /// Returns the index of edits, builds it from the board's edits if it does not exist
+    async fn edit_index(&mut self) -> &mut EditIndex {
+        if self.index.is_none() {
+            let state = self.edit_state(None).await;
+            self.index = Some(EditIndex::new(&state));
+        }
+        self.index.as_mut().unwrap()
+    }
+
+    /// Returns the spatial index, builds it from the board's edits if it does not exist
+    pub async fn spatial_index(&mut self) -> &SpatialIndex {
+        if self.spatial.is_none() {
+            let state = self.edit_state(None).await;
+            self.spatial = Some(SpatialIndex::new(&state.current));
+        }
+        self.spatial.as_ref().unwrap()
+    }
+
+    pub fn spatial(&self) -> Option<&SpatialIndex> {
+        self.spatial.as_ref()
+    }
+
+    /// Returns ids of shapes in the view, shapes are found where they are now, not where they have been added
+    pub async fn visible_shapes(&mut self, view: &View) -> HashSet<Box<str>> {
+        self.spatial_index()
+            .await
+            .query(view.page_id.as_deref(), &view.area)
+            .into_iter()
+            .map(Into::into)
+            .collect()
+    }
+
+    /// Returns current edits of the shapes in order
+    pub async fn shape_edits(&mut self, shape_ids: &HashSet<Box<str>>) -> Vec<Edit> {
+        let EditState { current, .. } = self.edit_state(None).await;
+        current
+            .into_iter()
+            .filter(|edit| {
+                edit_shapes(edit).any(|shape| shape_ids.contains(shape.shape_id.as_str()))
+            })
+            .collect()
+    }
+
+    /// Returns ids of shapes the edit changes, the edit can be current or undone
+    pub async fn edit_shape_ids(&mut self, edit_id: &str) -> HashSet<Box<str>> {
+        self.edit_index()
+            .await
+            .shape_ids(edit_id)
+            .iter()
+            .cloned()
+            .collect()
     }
 
     /// clears self.current
@@ -400,12 +841,19 @@ impl Board {
             .delete_edit
             .send(EditDeleteChunk {
                 public_id: self.public_id,
-                status: EditStatus::Current,
+                status: Some(EditStatus::Current),
+                page_id: None,
                 ready: tx,
             })
             .await
             .unwrap();
         rx.await.unwrap();
+        if let Some(index) = &mut self.index {
+            index.empty(EditStatus::Current);
+        }
+        if let Some(spatial) = &mut self.spatial {
+            spatial.clear();
+        }
     }
 
     /// clears self.undone
@@ -422,12 +870,16 @@ impl Board {
             .delete_edit
             .send(EditDeleteChunk {
                 public_id: self.public_id,
-                status: EditStatus::Undone,
+                status: Some(EditStatus::Undone),
+                page_id: None,
                 ready: tx,
             })
             .await
             .unwrap();
         rx.await.unwrap();
+        if let Some(index) = &mut self.index {
+            index.empty(EditStatus::Undone);
+        }
     }
 
     pub fn set_title(&mut self, title: Box<str>) -> Result<(), PushError> {
@@ -438,14 +890,14 @@ impl Board {
 
     pub fn validate_title(title: &str) -> Result<(), PushError> {
         if title.len() > 36 {
-            return Err(PushError::WrongValue("size is too big"));
+            return Err(PushError::TooLarge("size is too big"));
         }
         Ok(())
     }
 
     pub fn validate_size(height: u32, width: u32) -> Result<(), PushError> {
         if height > MAX_DIMENSION_SIZE as u32 || width > MAX_DIMENSION_SIZE as u32 {
-            return Err(PushError::WrongValue("size is too big"));
+            return Err(PushError::TooLarge("size is too big"));
         }
 
         Ok(())
@@ -458,48 +910,512 @@ impl Board {
         Ok(())
     }
 
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn seq_limit(&self) -> u64 {
+        self.seq_limit
+    }
+
+    /// Sets the saved seq, it is the upper bound of numbers sent before
+    pub fn set_seq(&mut self, seq: u64) {
+        self.seq = seq;
+        self.seq_limit = seq;
+    }
+
+    /// Returns the sequence number for a newly accepted operation.
+    /// The upper bound is saved before the number is returned if it is reached
+    pub async fn next_seq(&mut self) -> u64 {
+        self.seq = self.seq.saturating_add(1);
+        if self.seq > self.seq_limit {
+            self.seq_limit = self.seq.saturating_add(SEQ_RESERVATION);
+            let (tx, rx) = oneshot::channel();
+            let sent = self
+                .db_queue
+                .update_board
+                .send(BoardUpdateChunk {
+                    public_id: self.public_id,
+                    title: self.title.clone(),
+                    size: self.size.clone(),
+                    seq: self.seq_limit,
+                    ready: tx,
+                })
+                .await;
+            if sent.is_ok() {
+                let _ = rx.await;
+            }
+        }
+        self.seq
+    }
+
+    pub fn per_author_undo(&self) -> bool {
+        self.per_author_undo
+    }
+
+    pub fn set_per_author_undo(&mut self, value: bool) {
+        self.per_author_undo = value;
+    }
+
+    /// Returns current edits that have changed the shape, the first one is usually its creation
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is an edit without id property
+    pub async fn authorship(&mut self, shape_id: &str) -> Vec<EditAuthorship> {
+        let EditState { current, .. } = self.edit_state(None).await;
+        current
+            .into_iter()
+            .filter_map(|edit| {
+                let (kind, touches) = match edit.edit.as_ref().unwrap() {
+                    EditInner::Add(e) => (
+                        "add",
+                        e.shape.as_ref().is_some_and(|s| s.shape_id == shape_id),
+                    ),
+                    EditInner::Remove(e) => {
+                        ("remove", e.shapes.iter().any(|s| s.shape_id == shape_id))
+                    }
+                    EditInner::Modify(e) => {
+                        ("modify", e.current.iter().any(|s| s.shape_id == shape_id))
+                    }
+                };
+                if !touches {
+                    return None;
+                }
+                Some(EditAuthorship {
+                    edit_id: edit.edit.as_ref().unwrap().id().to_owned(),
+                    name: None,
+                    author: edit.author,
+                    kind: kind.into(),
+                    created_at: edit.created_at,
+                    page_id: edit.page_id,
+                })
+            })
+            .collect()
+    }
+
+    pub fn size(&self) -> &BoardSize {
+        &self.size
+    }
+
+    // pages
+
+    pub fn pages(&self) -> &Vec<Page> {
+        &self.pages
+    }
+
+    pub fn has_page(&self, id: &str) -> bool {
+        self.pages.iter().any(|page| page.id == id)
+    }
+
+    pub fn validate_page_id(id: &str) -> Result<(), PushError> {
+        if id != DEFAULT_PAGE_ID && (id.len() != 36 || Uuid::parse_str(id).is_err()) {
+            return Err(PushError::WrongValue(
+                "page id must be empty or valid uuid with hyphens(36 symbols)",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn validate_page(page: &Page) -> Result<(), PushError> {
+        Self::validate_page_id(&page.id)?;
+        if page.title.len() > 36 {
+            return Err(PushError::TooLarge("page title is too long"));
+        }
+        Ok(())
+    }
+
+    pub fn create_page(&mut self, page: Page) -> Result<(), PushError> {
+        Self::validate_page(&page)?;
+        if self.has_page(&page.id) {
+            return Err(PushError::PageExists);
+        }
+        if self.pages.len() >= MAX_PAGES_COUNT {
+            return Err(PushError::TooManyPages);
+        }
+        self.pages.push(page);
+        Ok(())
+    }
+
+    pub fn rename_page(&mut self, id: &str, title: Box<str>) -> Result<(), PushError> {
+        if title.len() > 36 {
+            return Err(PushError::TooLarge("page title is too long"));
+        }
+        match self.pages.iter_mut().find(|page| page.id == id) {
+            Some(page) => {
+                page.title = title.into();
+                Ok(())
+            }
+            None => Err(PushError::PageNotFound),
+        }
+    }
+
+    /// Moves the page to the position, the position is clamped to the pages' count
+    pub fn move_page(&mut self, id: &str, position: usize) -> Result<(), PushError> {
+        match self.pages.iter().position(|page| page.id == id) {
+            Some(i) => {
+                let page = self.pages.remove(i);
+                let position = position.min(self.pages.len());
+                self.pages.insert(position, page);
+                Ok(())
+            }
+            None => Err(PushError::PageNotFound),
+        }
+    }
+
+    /// Removes the page and all of its edits
+    pub async fn delete_page(&mut self, id: Box<str>) -> Result<(), PushError> {
+        if id.as_ref() == DEFAULT_PAGE_ID {
+            return Err(PushError::WrongValue("default page cannot be deleted"));
+        }
+        if !self.has_page(&id) {
+            return Err(PushError::PageNotFound);
+        }
+        self.pages.retain(|page| page.id != id.as_ref());
+        if let Some(index) = &mut self.index {
+            index.delete_page(&id);
+        }
+        if let Some(spatial) = &mut self.spatial {
+            spatial.delete_page(&id);
+        }
+        // edits of the page are deleted before the pages are saved,
+        // so they are not left in db without the page
+        self.queue.push(QueueOp::DeletePage(id));
+        self.db_cache = None;
+        sync_with_queue(
+            self.db_queue,
+            self.public_id,
+            self.queue.drain(..).collect(),
+        )
+        .await;
+        Ok(())
+    }
+
     pub fn op_queue(self) -> Vec<QueueOp> {
         self.queue
     }
 }
 
+/// chan - channel to send messages to the user
+/// db_id - id of the user in db, is_some if the user has connected with jwt
+/// name - display name, taken from jwt if the user is authed
+/// anonymous - true if the user has connected without jwt
+/// author - id recorded in the user's edits, based on db_id or the session for anonymous users
+/// session - token which lets the user resume the connection after it drops
+/// auth_token - token provided in the last Auth message, used to check the role on resuming
+/// role - role granted to the user in db
+/// token_role - role granted by the token provided in Auth message
+/// invite_id - id of the invite used to auth, is_some if the user has authed via invite
+/// cursor - last known cursor position
+/// viewport - last known visible area of the board
+/// view - area the user has pulled, only changes of its shapes are sent to the user.
+/// is_none if the user receives every change
+/// cursor_sent_at, viewport_sent_at - time of the last broadcast, used for throttling
+/// cursor_pending, viewport_pending - true if the latest value is throttled and not broadcasted yet
+pub struct User {
+    chan: UserChannel,
+    db_id: Option<i32>,
+    name: Box<str>,
+    anonymous: bool,
+    author: Box<str>,
+    session: Box<str>,
+    auth_token: Option<Box<str>>,
+    role: Role,
+    token_role: Option<Role>,
+    invite_id: Option<i32>,
+    cursor: (f32, f32),
+    viewport: Option<Viewport>,
+    view: Option<View>,
+    cursor_sent_at: SystemTime,
+    viewport_sent_at: SystemTime,
+    cursor_pending: bool,
+    viewport_pending: bool,
+    missed_heartbeats: u32,
+}
+
+const ANONYMOUS_NAME: &str = "anonymous";
+pub const USER_AUTHOR_PREFIX: &str = "user:";
+const ANONYMOUS_AUTHOR_PREFIX: &str = "anonymous:";
+const ANONYMOUS_AUTHOR_LENGTH: usize = 32;
+/// size of the key session tokens are encoded from
+const SESSION_TOKEN_SIZE: usize = 32;
+
+impl User {
+    pub fn new(chan: UserChannel, user_data: Option<UserData>, session: Box<str>) -> Self {
+        let (db_id, name, anonymous) = match user_data {
+            Some(data) => (
+                Some(data.id),
+                format!("{} {}", data.first_name, data.second_name).into_boxed_str(),
+                false,
+            ),
+            None => (None, ANONYMOUS_NAME.into(), true),
+        };
+        let author = match db_id {
+            Some(id) => format!("{USER_AUTHOR_PREFIX}{id}"),
+            // the session is secret, so only a part of its hash is shown to others
+            None => format!(
+                "{ANONYMOUS_AUTHOR_PREFIX}{}",
+                &hash(session.as_bytes())[..ANONYMOUS_AUTHOR_LENGTH]
+            ),
+        };
+        User {
+            chan,
+            db_id,
+            name,
+            anonymous,
+            author: author.into_boxed_str(),
+            session,
+            auth_token: None,
+            role: Role::Viewer,
+            token_role: None,
+            invite_id: None,
+            cursor: (0.0, 0.0),
+            viewport: None,
+            view: None,
+            cursor_sent_at: SystemTime::UNIX_EPOCH,
+            viewport_sent_at: SystemTime::UNIX_EPOCH,
+            cursor_pending: false,
+            viewport_pending: false,
+            missed_heartbeats: 0,
+        }
+    }
+
+    pub fn chan(&self) -> &UserChannel {
+        &self.chan
+    }
+
+    pub fn db_id(&self) -> Option<i32> {
+        self.db_id
+    }
+
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    pub fn auth_token(&self) -> Option<&str> {
+        self.auth_token.as_deref()
+    }
+
+    pub fn set_auth_token(&mut self, token: Option<Box<str>>) {
+        self.auth_token = token;
+    }
+
+    /// Binds the suspended user to a new connection
+    pub fn resume(&mut self, chan: UserChannel) {
+        self.chan = chan;
+        self.missed_heartbeats = 0;
+    }
+
+    pub fn missed_heartbeats(&self) -> u32 {
+        self.missed_heartbeats
+    }
+
+    pub fn set_missed_heartbeats(&mut self, missed: u32) {
+        self.missed_heartbeats = missed;
+    }
+
+    /// Returns the highest of granted roles
+    pub fn role(&self) -> Role {
+        match self.token_role {
+            Some(role) => role.max(self.role),
+            None => self.role,
+        }
+    }
+
+    pub fn set_role(&mut self, role: Role) {
+        self.role = role;
+    }
+
+    pub fn set_token_role(&mut self, role: Option<Role>) {
+        self.token_role = role;
+    }
+
+    pub fn invite_id(&self) -> Option<i32> {
+        self.invite_id
+    }
+
+    pub fn set_invite_id(&mut self, id: Option<i32>) {
+        self.invite_id = id;
+    }
+
+    pub fn member(&self, id: usize) -> Member {
+        Member {
+            id: id as u64,
+            name: self.name.to_string(),
+            anonymous: self.anonymous,
+            cursor_x: self.cursor.0,
+            cursor_y: self.cursor.1,
+            viewport: self.viewport.clone(),
+        }
+    }
+
+    /// Saves cursor position and returns true if it should be broadcasted,
+    /// otherwise it is returned by flush_presence after the throttle
+    pub fn set_cursor(&mut self, x: f32, y: f32) -> bool {
+        self.cursor = (x, y);
+        self.cursor_pending = !User::pass_throttle(&mut self.cursor_sent_at);
+        !self.cursor_pending
+    }
+
+    pub fn view(&self) -> Option<&View> {
+        self.view.as_ref()
+    }
+
+    pub fn view_mut(&mut self) -> Option<&mut View> {
+        self.view.as_mut()
+    }
+
+    pub fn set_view(&mut self, view: Option<View>) {
+        self.view = view;
+    }
+
+    /// Saves viewport and returns true if it should be broadcasted,
+    /// otherwise it is returned by flush_presence after the throttle
+    pub fn set_viewport(&mut self, viewport: Viewport) -> bool {
+        self.viewport = Some(viewport);
+        self.viewport_pending = !User::pass_throttle(&mut self.viewport_sent_at);
+        !self.viewport_pending
+    }
+
+    /// Returns when the throttled cursor or viewport can be broadcasted, None if nothing is pending
+    pub fn presence_deadline(&self) -> Option<SystemTime> {
+        [
+            (self.cursor_pending, self.cursor_sent_at),
+            (self.viewport_pending, self.viewport_sent_at),
+        ]
+        .into_iter()
+        .filter(|(pending, _)| *pending)
+        .map(|(_, sent_at)| sent_at + *PRESENCE_THROTTLE_MS)
+        .min()
+    }
+
+    /// Returns the throttled cursor and viewport if they can be broadcasted now
+    pub fn flush_presence(&mut self) -> (Option<(f32, f32)>, Option<Viewport>) {
+        let cursor = match self.cursor_pending && User::pass_throttle(&mut self.cursor_sent_at) {
+            true => {
+                self.cursor_pending = false;
+                Some(self.cursor)
+            }
+            false => None,
+        };
+        let viewport =
+            match self.viewport_pending && User::pass_throttle(&mut self.viewport_sent_at) {
+                true => {
+                    self.viewport_pending = false;
+                    self.viewport.clone()
+                }
+                false => None,
+            };
+        (cursor, viewport)
+    }
+
+    fn pass_throttle(sent_at: &mut SystemTime) -> bool {
+        let now = SystemTime::now();
+        match now.duration_since(*sent_at) {
+            Ok(d) if d < *PRESENCE_THROTTLE_MS => false,
+            _ => {
+                *sent_at = now;
+                true
+            }
+        }
+    }
+}
+
 /// public_id - id for connection to the room
 /// private_id - author's token for editing, invites, deletion etc.
 /// users - room's connected users
 /// board - state of the room
 /// onwer_id - id of the creator, is_some if the author was authed
+/// roles - roles granted to registered users by their ids
+/// invites - active invite links
+/// log - messages of the latest accepted operations with their sequence numbers
+/// suspended - users who have lost their connection and can resume it
 pub struct Room {
     private_id: Box<str>,
     co_editor_private_id: Box<str>,
-    users: HashMap<usize, UserChannel>,
+    users: HashMap<usize, User>,
+    owner_id: Option<i32>,
+    roles: HashMap<i32, Role>,
+    invites: Vec<Invite>,
+    log: OperationLog,
+    suspended: SuspendedSessions,
     pub board: Board,
 }
 
 impl Room {
-    pub async fn new(board: Board) -> Self {
+    pub async fn new(board: Board, owner_id: Option<i32>) -> Self {
         Room {
             private_id: Room::generate_private_id().await,
             co_editor_private_id: Room::generate_editor_private_id().await,
             users: HashMap::with_capacity(20),
+            owner_id,
+            roles: HashMap::new(),
+            invites: vec![],
+            log: OperationLog::new(*OPERATION_LOG_SIZE),
+            suspended: SuspendedSessions::new(Duration::from_secs(*SESSION_GRACE_SECONDS)),
             board,
         }
     }
 
-    pub async fn load(board: Board, private_id: Box<str>) -> Self {
+    /// Creates a room from saved data, generates co_editor_private_id if it is None
+    pub async fn load(
+        board: Board,
+        private_id: Box<str>,
+        co_editor_private_id: Option<Box<str>>,
+        owner_id: Option<i32>,
+        roles: HashMap<i32, Role>,
+        invites: Vec<Invite>,
+    ) -> Self {
+        let co_editor_private_id = match co_editor_private_id {
+            Some(id) => id,
+            None => Room::generate_editor_private_id().await,
+        };
         Room {
             private_id,
-            co_editor_private_id: Room::generate_editor_private_id().await,
+            co_editor_private_id,
             users: HashMap::with_capacity(20),
+            owner_id,
+            roles,
+            invites,
+            log: OperationLog::new(*OPERATION_LOG_SIZE),
+            suspended: SuspendedSessions::new(Duration::from_secs(*SESSION_GRACE_SECONDS)),
             board,
         }
     }
 
     // getters
 
-    pub fn users(&self) -> &HashMap<usize, UserChannel> {
+    pub fn users(&self) -> &HashMap<usize, User> {
         &self.users
     }
 
+    pub fn user_mut(&mut self, id: &usize) -> Option<&mut User> {
+        self.users.get_mut(id)
+    }
+
+    /// Returns the earliest time throttled presence of users should be broadcasted
+    pub fn presence_deadline(&self) -> Option<SystemTime> {
+        self.users
+            .values()
+            .filter_map(|user| user.presence_deadline())
+            .min()
+    }
+
+    pub fn members(&self) -> Vec<Member> {
+        self.users
+            .iter()
+            .map(|(id, user)| user.member(*id))
+            .collect()
+    }
+
     pub fn public_id(&self) -> Uuid {
         self.board.public_id
     }
@@ -516,17 +1432,161 @@ impl Room {
         &self.board.title
     }
 
+    /// Returns the role of the connected user, Viewer if there is no such user
+    pub fn role(&self, id: &usize) -> Role {
+        match self.users.get(id) {
+            Some(user) => user.role(),
+            None => Role::Viewer,
+        }
+    }
+
+    /// Returns true if the token is valid or the registered user is the owner or a member of the board
+    pub fn has_access(&self, token: Option<&str>, db_id: Option<i32>) -> bool {
+        let by_token = token.is_some_and(|token| self.token_role(token).is_some());
+        let by_user =
+            db_id.is_some_and(|id| self.owner_id == Some(id) || self.roles.contains_key(&id));
+        by_token || by_user
+    }
+
+    /// Returns the role granted in db to the registered user
+    pub fn granted_role(&self, db_id: Option<i32>) -> Role {
+        match db_id {
+            Some(id) if self.owner_id == Some(id) => Role::Owner,
+            Some(id) => self.roles.get(&id).copied().unwrap_or(Role::Viewer),
+            None => Role::Viewer,
+        }
+    }
+
+    /// Returns a role provided by the token and the id of the invite if the token belongs to one.
+    /// Returns None if the token is invalid
+    pub fn token_role(&self, token: &str) -> Option<(Role, Option<i32>)> {
+        if token == self.private_id() {
+            return Some((Role::Owner, None));
+        }
+        if token == self.co_editor_private_id() {
+            return Some((Role::Editor, None));
+        }
+        let now = SystemTime::now();
+        self.invites
+            .iter()
+            .find(|invite| invite.token.as_ref() == token && invite.is_valid(now))
+            .map(|invite| (invite.role.into(), Some(invite.id)))
+    }
+
+    pub fn add_invite(&mut self, invite: Invite) {
+        self.invites.push(invite);
+    }
+
+    /// Removes the invite and returns ids of connections that have used it
+    pub fn revoke_invite(&mut self, id: i32) -> Vec<usize> {
+        self.invites.retain(|invite| invite.id != id);
+        self.users
+            .iter_mut()
+            .filter(|(_, user)| user.invite_id() == Some(id))
+            .map(|(user_id, user)| {
+                user.set_token_role(None);
+                user.set_invite_id(None);
+                *user_id
+            })
+            .collect()
+    }
+
+    /// Increments the use counter of the invite
+    pub fn use_invite(&mut self, id: i32) {
+        if let Some(invite) = self.invites.iter_mut().find(|invite| invite.id == id) {
+            invite.uses += 1;
+        }
+    }
+
+    /// Saves the message of an accepted operation, so users can catch up with it later
+    pub fn log_operation(&mut self, seq: u64, msg: ServerMessage) {
+        self.log.push(seq, msg);
+    }
+
+    /// Returns messages of operations accepted after seq
+    /// or None if some of them are not in the log, the full state should be sent then
+    pub fn operations_after(&self, seq: u64) -> Option<Vec<ServerMessage>> {
+        self.log.after(seq, self.board.seq())
+    }
+
     pub fn size(&self) -> &BoardSize {
         &self.board.size
     }
 
     // setters
 
-    pub fn add_user(&mut self, id: usize, chan: UserChannel) {
-        self.users.insert(id, chan);
+    pub fn add_user(&mut self, id: usize, mut user: User) {
+        user.set_role(self.granted_role(user.db_id()));
+        self.users.insert(id, user);
     }
-    pub fn remove_user(&mut self, id: &usize) {
-        self.users.remove(id);
+
+    /// Updates the role of the registered user and returns ids of affected connections
+    pub fn set_granted_role(&mut self, db_id: i32, role: Option<Role>) -> Vec<usize> {
+        match role {
+            Some(role) => self.roles.insert(db_id, role),
+            None => self.roles.remove(&db_id),
+        };
+        let role = self.granted_role(Some(db_id));
+        self.users
+            .iter_mut()
+            .filter(|(_, user)| user.db_id() == Some(db_id))
+            .map(|(id, user)| {
+                user.set_role(role);
+                *id
+            })
+            .collect()
+    }
+
+    /// Removes the user and keeps the session for the grace period
+    pub fn suspend_user(&mut self, id: &usize) {
+        if let Some(user) = self.users.remove(id) {
+            self.suspended.suspend(user, SystemTime::now());
+        }
+    }
+
+    /// Returns the suspended user with the session token.
+    /// The role granted by the user's token is checked again, because it may be revoked
+    pub fn resume_user(&mut self, token: &str, db_id: Option<i32>) -> Option<User> {
+        let mut user = self.suspended.resume(token, db_id, SystemTime::now())?;
+        let token_role = user.auth_token().and_then(|token| self.token_role(token));
+        user.set_token_role(token_role.map(|(role, _)| role));
+        user.set_invite_id(token_role.and_then(|(_, invite_id)| invite_id));
+        Some(user)
+    }
+
+    /// Returns true if a connected or suspended user has the session token
+    pub fn has_session(&mut self, token: &str) -> bool {
+        self.users.values().any(|user| user.session() == token)
+            || self.suspended.contains(token, SystemTime::now())
+    }
+
+    /// Returns true if there are users who can resume their sessions
+    pub fn has_suspended_users(&mut self) -> bool {
+        self.suspended.has_pending(SystemTime::now())
+    }
+
+    pub fn health(&mut self) -> RoomHealth {
+        RoomHealth {
+            connected: self.users.len(),
+            unresponsive: self
+                .users
+                .values()
+                .filter(|user| user.missed_heartbeats() > 0)
+                .count(),
+            suspended: self.suspended.count(SystemTime::now()),
+            queued: self.users.values().map(|user| user.chan().len()).sum(),
+            largest_queue: self
+                .users
+                .values()
+                .map(|user| user.chan().len())
+                .max()
+                .unwrap_or_default(),
+            overflows: self
+                .users
+                .values()
+                .map(|user| user.chan().overflows())
+                .sum(),
+        }
     }
 
     async fn generate_private_id() -> Box<str> {
@@ -554,6 +1614,37 @@ impl Room {
         rx.await.unwrap()
     }
 
+    /// Returns true if the token has the format of generated session tokens
+    pub fn is_session_token(token: &str) -> bool {
+        BASE64URL
+            .decode(token.as_bytes())
+            .is_ok_and(|key| key.len() == SESSION_TOKEN_SIZE)
+    }
+
+    pub async fn generate_session_token() -> Box<str> {
+        let (tx, rx) = oneshot::channel();
+        tokio::task::spawn_blocking(move || {
+            tx.send(
+                BASE64URL
+                    .encode(&HS256Key::generate().to_bytes())
+                    .into_boxed_str(),
+            )
+            .unwrap()
+        });
+        rx.await.unwrap()
+    }
+
+    pub async fn generate_invite_token() -> Box<str> {
+        let (tx, rx) = oneshot::channel();
+        tokio::task::spawn_blocking(move || {
+            tx.send(
+                (BASE64URL.encode(&HS256Key::generate().to_bytes()) + "_invite").into_boxed_str(),
+            )
+            .unwrap()
+        });
+        rx.await.unwrap()
+    }
+
     /// Updates self.co_editor_private_id and returns new one
     pub async fn update_editor_private_id(&mut self) -> Box<str> {
         self.co_editor_private_id = Room::generate_editor_private_id().await;
@@ -562,5 +1653,131 @@ impl Room {
     }
 }
 
-pub type Rooms = Arc<RwLock<HashMap<Uuid, mpsc::Sender<UserMessage>>>>;
+pub type Rooms = Arc<RoomRegistry>;
 pub type DbClient<'a> = PooledConnection<'static, PostgresConnectionManager<NoTls>>;
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::db_queue::new_db_queue;
+    use protocol::board_protocol::ShapeType;
+
+    fn get_text_sample(shape_type: ShapeType) -> Shape {
+        Shape {
+            shape_type: shape_type.into(),
+            text: "label".to_owned(),
+            font_size: 16.0,
+            font_family: "sans-serif".to_owned(),
+            fill: "#ffeb3b".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn imported_edits_are_signed_by_importer() {
+        let mut edits = [Edit {
+            author: format!("{USER_AUTHOR_PREFIX}42"),
+            created_at: 1,
+            ..Default::default()
+        }];
+
+        Board::adopt_imported(edits.iter_mut(), OWNER_AUTHOR);
+        assert_eq!(edits[0].author, OWNER_AUTHOR);
+        assert!(edits[0].created_at > 1);
+    }
+
+    fn get_modify_sample(clock: u64) -> Edit {
+        Edit {
+            edit: Some(EditInner::Modify(Modify {
+                id: Uuid::now_v7().to_string(),
+                clock,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    fn clock(edit: &Edit) -> u64 {
+        match &edit.edit {
+            Some(EditInner::Modify(modify)) => modify.clock,
+            _ => 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn live_modify_wins_over_imported_clock() {
+        let mut edits = [get_modify_sample(u64::MAX), get_modify_sample(3)];
+        Board::adopt_imported(edits.iter_mut(), OWNER_AUTHOR);
+        assert_eq!(edits.iter().map(clock).collect::<Vec<_>>(), [1, 2]);
+        // the board is loaded with the imported edits
+        let (db_queue, mut db) = new_db_queue();
+        let state = EditState {
+            current: edits.to_vec(),
+            ..Default::default()
+        };
+        tokio::spawn(async move {
+            while let Some(chunk) = db.read_edit.recv().await {
+                let _ = chunk.ready.send(state.clone());
+            }
+        });
+        let mut board = Board::load(
+            db_queue,
+            "title".into(),
+            BoardSize::default(),
+            vec![],
+            Uuid::now_v7(),
+        );
+
+        let mut live = get_modify_sample(0);
+        board.stamp(&mut live).await;
+        assert_eq!(clock(&live), 3);
+    }
+
+    #[test]
+    fn text_shapes_are_valid() {
+        assert!(Board::validate_shape(&get_text_sample(ShapeType::Text)).is_ok());
+        assert!(Board::validate_shape(&get_text_sample(ShapeType::StickyNote)).is_ok());
+    }
+
+    #[test]
+    fn long_text_is_rejected() {
+        let mut shape = get_text_sample(ShapeType::Text);
+        shape.text = "a".repeat(MAX_TEXT_LENGTH + 1);
+        assert!(matches!(
+            Board::validate_shape(&shape),
+            Err(PushError::TooLarge(_))
+        ));
+        shape.text = "a".repeat(MAX_TEXT_LENGTH);
+        assert!(Board::validate_shape(&shape).is_ok());
+    }
+
+    #[test]
+    fn invalid_font_size_is_rejected() {
+        for font_size in [-1.0, MAX_FONT_SIZE + 1.0, f32::NAN] {
+            let mut shape = get_text_sample(ShapeType::Text);
+            shape.font_size = font_size;
+            assert!(matches!(
+                Board::validate_shape(&shape),
+                Err(PushError::WrongValue(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn sticky_note_fields_are_limited() {
+        let mut shape = get_text_sample(ShapeType::StickyNote);
+        shape.fill = "f".repeat(MAX_COLOR_LENGTH + 1);
+        assert!(matches!(
+            Board::validate_shape(&shape),
+            Err(PushError::TooLarge(_))
+        ));
+        let mut shape = get_text_sample(ShapeType::StickyNote);
+        shape.font_family = "f".repeat(MAX_FONT_FAMILY_LENGTH + 1);
+        assert!(matches!(
+            Board::validate_shape(&shape),
+            Err(PushError::TooLarge(_))
+        ));
+    }
+}
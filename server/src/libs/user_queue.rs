@@ -0,0 +1,228 @@
+use axum::body::Bytes;
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Notify;
+
+// policy
+
+/// What to do when a user does not read messages as fast as they are sent
+///
+/// Coalesce - replace queued presence updates with newer ones of the same user,
+/// drop new ones if the queue is full and resync like Resync for other messages
+/// Resync - drop queued messages and ask the user to pull what they have missed
+/// Disconnect - close the connection, the user can resume the session after reconnecting
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueuePolicy {
+    Coalesce,
+    Resync,
+    Disconnect,
+}
+
+impl FromStr for QueuePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "coalesce" => Ok(Self::Coalesce),
+            "resync" => Ok(Self::Resync),
+            "disconnect" => Ok(Self::Disconnect),
+            _ => Err(format!("unknown queue policy: {}", s)),
+        }
+    }
+}
+
+/// Identifies messages that make older ones with the same key useless,
+/// e.g. cursor positions of a user
+pub type CoalesceKey = (&'static str, usize);
+
+// queue
+
+/// key - is_some for messages that can be coalesced
+struct Entry {
+    key: Option<CoalesceKey>,
+    msg: Bytes,
+}
+
+/// overflows - how many times the queue has been full
+/// resynced - true if queued messages have been dropped since the last take_resync
+struct Inner {
+    entries: VecDeque<Entry>,
+    closed: bool,
+    overflows: u64,
+    resynced: bool,
+}
+
+/// Bounded queue of messages to a user's connection
+///
+/// resync_hint - message put in the queue when the queued ones are dropped
+pub struct UserQueue {
+    inner: Mutex<Inner>,
+    notify: Notify,
+    capacity: usize,
+    policy: QueuePolicy,
+    resync_hint: Bytes,
+}
+
+impl UserQueue {
+    pub fn new(capacity: usize, policy: QueuePolicy, resync_hint: Bytes) -> Arc<Self> {
+        Arc::new(UserQueue {
+            inner: Mutex::new(Inner {
+                entries: VecDeque::with_capacity(capacity),
+                closed: false,
+                overflows: 0,
+                resynced: false,
+            }),
+            notify: Notify::new(),
+            capacity,
+            policy,
+            resync_hint,
+        })
+    }
+
+    /// Puts the message in the queue applying the policy if it is full.
+    /// Returns false if the queue is closed
+    pub fn push(&self, msg: Bytes, key: Option<CoalesceKey>) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.closed {
+            return false;
+        }
+        if let (Some(key), QueuePolicy::Coalesce) = (key, self.policy) {
+            if let Some(entry) = inner.entries.iter_mut().find(|e| e.key == Some(key)) {
+                entry.msg = msg;
+                return true;
+            }
+        }
+        if inner.entries.len() >= self.capacity {
+            inner.overflows += 1;
+            match (self.policy, key) {
+                (QueuePolicy::Coalesce, Some(_)) => return true,
+                (QueuePolicy::Coalesce | QueuePolicy::Resync, _) => {
+                    inner.entries.clear();
+                    inner.resynced = true;
+                    inner.entries.push_back(Entry {
+                        key: None,
+                        msg: self.resync_hint.clone(),
+                    });
+                    // the user pulls operations after the hint, so the message is not needed
+                    self.notify.notify_one();
+                    return true;
+                }
+                (QueuePolicy::Disconnect, _) => {
+                    inner.closed = true;
+                    self.notify.notify_one();
+                    return false;
+                }
+            }
+        }
+        inner.entries.push_back(Entry { key, msg });
+        self.notify.notify_one();
+        true
+    }
+
+    /// Waits for the next message, returns None if the queue is closed
+    pub async fn pop(&self) -> Option<Bytes> {
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                if inner.closed {
+                    return None;
+                }
+                if let Some(entry) = inner.entries.pop_front() {
+                    return Some(entry.msg);
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Returns the next message without waiting
+    pub fn try_pop(&self) -> Option<Bytes> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.closed {
+            true => None,
+            false => inner.entries.pop_front().map(|entry| entry.msg),
+        }
+    }
+
+    /// Drops queued messages, the connection is closed after that
+    pub fn close(&self) {
+        self.inner.lock().unwrap().closed = true;
+        self.notify.notify_one();
+    }
+
+    /// Returns true once after queued messages have been dropped,
+    /// messages without seq are not pulled, so they are sent again
+    pub fn take_resync(&self) -> bool {
+        std::mem::take(&mut self.inner.lock().unwrap().resynced)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn overflows(&self) -> u64 {
+        self.inner.lock().unwrap().overflows
+    }
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HINT: &[u8] = b"hint";
+
+    fn get_queue_sample(policy: QueuePolicy) -> Arc<UserQueue> {
+        let queue = UserQueue::new(2, policy, Bytes::from_static(HINT));
+        assert!(queue.push(Bytes::from_static(b"a"), Some(("cursor", 1))));
+        assert!(queue.push(Bytes::from_static(b"b"), None));
+        queue
+    }
+
+    fn drain(queue: &UserQueue) -> Vec<Bytes> {
+        let mut messages = Vec::new();
+        while let Some(msg) = queue.try_pop() {
+            messages.push(msg);
+        }
+        messages
+    }
+
+    #[test]
+    fn coalesce_replaces_queued_messages() {
+        let queue = get_queue_sample(QueuePolicy::Coalesce);
+
+        assert!(queue.push(Bytes::from_static(b"c"), Some(("cursor", 1))));
+        // there is no room for a cursor of another user
+        assert!(queue.push(Bytes::from_static(b"d"), Some(("cursor", 2))));
+        assert_eq!(queue.overflows(), 1);
+        assert_eq!(drain(&queue), vec![&b"c"[..], &b"b"[..]]);
+    }
+
+    #[test]
+    fn overflow_is_resynced() {
+        for policy in [QueuePolicy::Coalesce, QueuePolicy::Resync] {
+            let queue = get_queue_sample(policy);
+
+            assert!(queue.push(Bytes::from_static(b"c"), None));
+            assert_eq!(drain(&queue), vec![HINT]);
+            assert!(queue.take_resync());
+            assert!(!queue.take_resync());
+            // the queue is usable after the hint
+            assert!(queue.push(Bytes::from_static(b"d"), None));
+            assert_eq!(queue.len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn overflow_closes_queue() {
+        let queue = get_queue_sample(QueuePolicy::Disconnect);
+
+        assert!(!queue.push(Bytes::from_static(b"c"), Some(("cursor", 2))));
+        assert_eq!(queue.pop().await, None);
+        assert!(!queue.push(Bytes::from_static(b"d"), None));
+    }
+}
@@ -13,10 +13,7 @@ pub async fn cleanup_cache(rooms: Rooms) {
         // wait for duration
         interval.tick().await;
         // remove unused rooms
-        let rooms_p = rooms.read().await;
-        let rooms = rooms_p.clone();
-        drop(rooms_p);
-        for (_, chan) in rooms.iter() {
+        for (_, chan) in rooms.rooms() {
             let _ = chan.send(UserMessage::TryExpireCache).await;
         }
     }
@@ -1,50 +1,130 @@
-use std::collections::HashSet;
-
-use crate::libs::state::Rooms;
-use crate::{libs::room::UserMessage, CLEANUP_INTERVAL_MINUTES};
+use crate::libs::{cluster::Cluster, state::Rooms};
+use crate::{entities::lease, libs::room::UserMessage, PoolWrapper, CLEANUP_INTERVAL_MINUTES};
 use log::{error, info};
 use tokio::{
     sync::oneshot,
     time::{self, Duration},
 };
+use uuid::Uuid;
 
 /// Creates an infinite loop which scans for rooms without users and saves them to db.
 /// The function waits for provided duration intil start of a new cycle
-pub async fn cleanup(rooms: Rooms) {
+pub async fn cleanup(rooms: Rooms, pool: &'static PoolWrapper, cluster: &'static Cluster) {
     let mut interval = time::interval(Duration::from_secs(*CLEANUP_INTERVAL_MINUTES * 60));
     loop {
         // wait for duration
         interval.tick().await;
-        // remove unused rooms
-        let mut rooms_p = rooms.write().await;
-        let mut expired_rooms = HashSet::new();
-        let mut receivers = Vec::with_capacity(rooms_p.len());
-        // send messages to rooms
-        for (_, room) in rooms_p.iter() {
-            let (tx, rx) = oneshot::channel();
-            let _ = room.send(UserMessage::HasUsers(tx)).await;
-            receivers.push(rx);
-        }
-        // collect and expire unused rooms
-        for (i, (id, _)) in rooms_p.iter().enumerate() {
-            let rx = &mut receivers[i];
-            match rx.await {
-                Ok(res) => {
-                    if !res {
-                        expired_rooms.insert(id.to_owned());
-                    }
-                }
-                Err(e) => {
-                    error!("cannot receive response to HasUsers msg: {}", e);
-                    expired_rooms.insert(id.to_owned());
-                }
+        let expired_rooms = sweep(&rooms).await;
+        // let other nodes host the rooms
+        if !expired_rooms.is_empty() {
+            let client = pool.get().await;
+            if let Err(e) = lease::release(&client, &expired_rooms, cluster.node_id()).await {
+                error!("cannot release leases of unused rooms: {}", e);
             }
         }
-        rooms_p.retain(|id, _| !expired_rooms.contains(id));
         // cleanup log
         info!("{} unused room(s) have been deleted", expired_rooms.len());
         info!("===========");
         // shrink capacity
-        rooms_p.shrink_to(20);
+        rooms.shrink();
+    }
+}
+
+/// Removes rooms without users from the registry and stops them once they are saved.
+/// Returns ids of the removed rooms
+async fn sweep(rooms: &Rooms) -> Vec<Uuid> {
+    // do not lock the rooms while waiting for them
+    let rooms_p = rooms.rooms();
+    let mut expired_rooms = Vec::new();
+    let mut unused_rooms = Vec::new();
+    let mut receivers = Vec::with_capacity(rooms_p.len());
+    // send messages to rooms
+    for (_, room) in rooms_p.iter() {
+        let (tx, rx) = oneshot::channel();
+        let _ = room.send(UserMessage::HasUsers(tx)).await;
+        receivers.push(rx);
+    }
+    // collect unused rooms and expire broken ones
+    for ((id, _), rx) in rooms_p.iter().zip(receivers) {
+        match rx.await {
+            Ok(has_users) => {
+                if !has_users {
+                    unused_rooms.push(*id);
+                }
+            }
+            Err(e) => {
+                error!("cannot receive response to HasUsers msg: {}", e);
+                rooms.remove(id);
+                expired_rooms.push(*id);
+            }
+        }
+    }
+    drop(rooms_p);
+    // users could join the rooms meanwhile, so only rooms nobody refers to are removed,
+    // the removed ones cannot be reached anymore, so they are stopped after that
+    let mut receivers = Vec::with_capacity(unused_rooms.len());
+    for id in unused_rooms {
+        let Some(room) = rooms.remove_unused(&id) else {
+            continue;
+        };
+        let (tx, rx) = oneshot::channel();
+        let _ = room.send(UserMessage::Expire(tx)).await;
+        receivers.push(rx);
+        expired_rooms.push(id);
+    }
+    // leases are released after the rooms are saved
+    for rx in receivers {
+        let _ = rx.await;
+    }
+    expired_rooms
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::registry::RoomRegistry;
+    use std::sync::Arc;
+    use tokio::sync::mpsc::channel;
+
+    #[tokio::test]
+    async fn rooms_joined_during_cleanup_are_kept() {
+        let rooms: Rooms = Arc::new(RoomRegistry::default());
+        let public_id = Uuid::now_v7();
+        let (tx, mut rx) = channel(8);
+        rooms.insert(public_id, tx);
+        // the room has no users, but a user joins it before cleanup removes it
+        let (joined_tx, joined_rx) = oneshot::channel();
+        let mut joined_tx = Some(joined_tx);
+        let registry = rooms.clone();
+        let room_task = tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                match msg {
+                    UserMessage::HasUsers(sender) => {
+                        if let Some(joined) = joined_tx.take() {
+                            let _ = joined.send(registry.get(&public_id));
+                        }
+                        let _ = sender.send(false);
+                    }
+                    UserMessage::Expire(completed) => {
+                        let _ = completed.send(());
+                        return true;
+                    }
+                    _ => (),
+                }
+            }
+            false
+        });
+
+        assert!(sweep(&rooms).await.is_empty());
+        let connection = joined_rx.await.unwrap().unwrap();
+        assert!(!connection.is_closed());
+        assert!(rooms.get(&public_id).is_some());
+        // the user leaves, so the room is saved and stopped
+        drop(connection);
+        assert_eq!(sweep(&rooms).await, vec![public_id]);
+        assert!(rooms.get(&public_id).is_none());
+        assert!(room_task.await.unwrap());
     }
 }
@@ -0,0 +1,72 @@
+use crate::{
+    entities::lease,
+    libs::{cluster::Cluster, room::UserMessage, state::Rooms},
+    PoolWrapper, LEASE_TTL,
+};
+use log::{error, warn};
+use tokio::{
+    sync::oneshot,
+    time::{interval, timeout, Instant},
+};
+use uuid::Uuid;
+
+/// Creates an infinite loop which renews leases of rooms hosted by the node
+/// and unloads rooms which leases are taken by other nodes.
+/// If leases have not been renewed for their ttl, other nodes may host the rooms already,
+/// so all rooms of the node are unloaded.
+/// Local users of rooms hosted by gone nodes are disconnected, so they can join the rooms again
+pub async fn keep_leases(rooms: Rooms, pool: &'static PoolWrapper, cluster: &'static Cluster) {
+    let period = *LEASE_TTL / 3;
+    let mut interval = interval(period);
+    // start of the last successful renewal
+    let mut renewed_at = Instant::now();
+    loop {
+        interval.tick().await;
+        let started_at = Instant::now();
+        let board_ids = rooms.ids();
+        if board_ids.is_empty() {
+            renewed_at = started_at;
+        }
+        // a slow pool must not hold the loop past the ttl
+        let client = match timeout(period, pool.try_get()).await {
+            Ok(Ok(client)) => Some(client),
+            Ok(Err(e)) => {
+                error!("cannot get a client to renew leases: {}", e);
+                None
+            }
+            Err(_) => {
+                error!("cannot get a client to renew leases in time");
+                None
+            }
+        };
+        if let Some(client) = &client {
+            if !board_ids.is_empty() {
+                match lease::renew(client, &board_ids, cluster.node_id(), *LEASE_TTL).await {
+                    Ok(lost) => {
+                        renewed_at = started_at;
+                        for id in lost {
+                            warn!("lease of room {} is lost, unloading the room", id);
+                            unload(&rooms, &id).await;
+                        }
+                    }
+                    Err(e) => error!("cannot renew leases: {}", e),
+                }
+            }
+            cluster.check_hosts(client).await;
+        }
+        if renewed_at.elapsed() >= *LEASE_TTL {
+            warn!("leases have not been renewed for their ttl, unloading all rooms");
+            for id in rooms.ids() {
+                unload(&rooms, &id).await;
+            }
+            renewed_at = Instant::now();
+        }
+    }
+}
+
+async fn unload(rooms: &Rooms, public_id: &Uuid) {
+    if let Some(room) = rooms.remove(public_id) {
+        let (tx, _) = oneshot::channel();
+        let _ = room.send(UserMessage::Expire(tx)).await;
+    }
+}
@@ -0,0 +1,33 @@
+use log::{error, info};
+use tokio::time::{self, Duration};
+
+use crate::{entities::snapshot, PoolWrapper, COMPACTION_INTERVAL_MINUTES, UNDO_HORIZON};
+
+const BOARDS_PER_CYCLE: i64 = 100;
+
+/// Creates an infinite loop which folds edits beyond the undo horizon into snapshots.
+/// Only boards that are not hosted by any node are compacted, so rooms never see their edits disappear
+pub async fn compact(pool: &PoolWrapper) {
+    let mut interval = time::interval(Duration::from_secs(*COMPACTION_INTERVAL_MINUTES * 60));
+    loop {
+        // wait for duration
+        interval.tick().await;
+        let mut client = pool.get().await;
+        let candidates =
+            match snapshot::read_candidates(&client, *UNDO_HORIZON, BOARDS_PER_CYCLE).await {
+                Ok(ids) => ids,
+                Err(e) => {
+                    error!("cannot read boards to compact: {}", e);
+                    continue;
+                }
+            };
+        let mut folded = 0;
+        for public_id in candidates {
+            match snapshot::compact(&mut client, public_id, *UNDO_HORIZON).await {
+                Ok(count) => folded += count,
+                Err(e) => error!("cannot compact board {}: {}", public_id, e),
+            }
+        }
+        info!("{} edit(s) have been compacted", folded);
+    }
+}
@@ -1,11 +1,15 @@
 mod cache_cleaner;
 mod cleanup;
+mod cluster;
+mod compaction;
 mod monitor;
 mod on_shutdown;
 mod retrive_room;
 
 pub use cache_cleaner::cleanup_cache;
 pub use cleanup::cleanup;
+pub use cluster::keep_leases;
+pub use compaction::compact;
 pub use monitor::monitor;
 pub use on_shutdown::on_shutdown;
-pub use retrive_room::retrive_room_channel;
+pub use retrive_room::{find_room, retrive_room};
@@ -1,6 +1,12 @@
-use crate::{libs::state::Rooms, MONITOR_INTERVAL_MINUTES};
-use log::info;
-use tokio::time::{interval, Duration};
+use crate::{
+    libs::{room::UserMessage, state::Rooms},
+    MONITOR_INTERVAL_MINUTES,
+};
+use log::{error, info};
+use tokio::{
+    sync::oneshot,
+    time::{interval, Duration},
+};
 
 /// Creates an infinite loop which logs some useful data about the server's state.
 /// The function waites for provided duration intil start of a new cycle
@@ -8,7 +14,30 @@ pub async fn monitor(rooms: Rooms) {
     let mut interval = interval(Duration::from_secs(*MONITOR_INTERVAL_MINUTES * 60));
     loop {
         interval.tick().await;
-        info!("Active rooms count: {}", rooms.read().await.len());
+        // do not lock the rooms while waiting for them
+        let rooms_p = rooms.rooms();
+        info!("Active rooms count: {}", rooms_p.len());
+        // connection health
+        for (id, room) in rooms_p {
+            let (tx, rx) = oneshot::channel();
+            if room.send(UserMessage::GetHealth(tx)).await.is_err() {
+                continue;
+            }
+            match rx.await {
+                Ok(health) => info!(
+                    "Room {}: {} connected, {} unresponsive, {} suspended, \
+                     {} queued messages, {} in the largest queue, {} overflows",
+                    id,
+                    health.connected,
+                    health.unresponsive,
+                    health.suspended,
+                    health.queued,
+                    health.largest_queue,
+                    health.overflows
+                ),
+                Err(e) => error!("cannot receive response to GetHealth msg: {}", e),
+            }
+        }
         info!("===========");
     }
 }
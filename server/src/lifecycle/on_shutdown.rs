@@ -1,18 +1,29 @@
+use log::error;
 use tokio::sync::oneshot;
+use uuid::Uuid;
 
-use crate::libs::{room::UserMessage, state::Rooms};
+use crate::{
+    entities::lease,
+    libs::{cluster::Cluster, room::UserMessage, state::Rooms},
+    PoolWrapper,
+};
 
-pub async fn on_shutdown(rooms: Rooms) {
-    let rooms = rooms.read().await;
+pub async fn on_shutdown(rooms: Rooms, pool: &'static PoolWrapper, cluster: &'static Cluster) {
+    let rooms = rooms.rooms();
     let mut receivers = Vec::with_capacity(rooms.len());
     // send Expire message
     for (_, room) in rooms.iter() {
         let (tx, rx) = oneshot::channel();
-        let _ = room.send(UserMessage::Expire(tx));
+        let _ = room.send(UserMessage::Expire(tx)).await;
         receivers.push(rx);
     }
     // await for saving
     for rx in receivers {
         let _ = rx.await;
     }
+    // the rooms are saved, so other nodes can host them
+    let ids: Vec<Uuid> = rooms.iter().map(|(id, _)| *id).collect();
+    if let Err(e) = lease::release(&pool.get().await, &ids, cluster.node_id()).await {
+        error!("cannot release leases: {}", e);
+    }
 }
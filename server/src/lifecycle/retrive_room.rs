@@ -1,43 +1,152 @@
+use axum::http::StatusCode;
+use log::error;
+use std::fmt;
 use tokio::sync::mpsc::channel;
 use uuid::Uuid;
 
 use crate::{
-    entities::board,
+    entities::{board, invite, lease, role},
     libs::{
+        cluster::{RemoteRoom, RoomLink},
         room::{self, RoomChannel},
         state::Room,
     },
-    AppState,
+    AppState, LEASE_TTL,
 };
 
-pub async fn retrive_room_channel(state: AppState, public_id: Uuid) -> Result<RoomChannel, Uuid> {
-    let rooms_p = state.rooms.read().await;
-
-    match rooms_p.get(&public_id) {
-        Some(r) => return Ok(r.to_owned()),
-        None => {
-            // drop previous rooms pointer to prevent deadlock
-            drop(rooms_p);
-            match board::get(state.pool, state.db_queue, public_id).await {
-                // if there is a room in db, spawn it
-                Ok((private_id, board)) => {
-                    // we don't need to provide owner_id here, because
-                    // it had already been saved in db earler during
-                    // last cleanup.
-                    let room = Room::load(board, private_id).await;
-                    // spawn room_task
-                    let (tx, rx) = channel(1);
-                    let public_id_c = public_id.clone();
-                    tokio::spawn(async move {
-                        room::task(public_id_c, room, state.pool, state.db_queue, rx).await;
-                    });
-                    // add new room
-                    let mut rooms_p = state.rooms.write().await;
-                    rooms_p.insert(public_id, tx.to_owned());
-                    return Ok(tx);
-                }
-                Err(_) => return Err(public_id),
-            }
+// errors
+
+#[derive(Debug)]
+pub enum RetriveError {
+    NotFound(Uuid),
+    /// the room is hosted by the node with the id
+    Remote(Box<str>),
+}
+
+impl RetriveError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Remote(_) => StatusCode::MISDIRECTED_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for RetriveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(public_id) => write!(f, "{}", public_id),
+            Self::Remote(node_id) => write!(f, "the room is hosted by node {}", node_id),
         }
     }
 }
+
+// functions
+
+/// Returns the room hosted by the node or a link to the node hosting it
+pub async fn retrive_room(state: AppState, public_id: Uuid) -> Result<RoomLink, RetriveError> {
+    let cluster = state.cluster;
+    match retrive_room_channel(state, public_id).await {
+        Ok(room) => Ok(RoomLink::Local(room)),
+        Err(RetriveError::Remote(host)) => {
+            Ok(RoomLink::Remote(RemoteRoom::new(cluster, public_id, host)))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the room if it is hosted by the node or another one, the room is not loaded
+pub async fn find_room(state: AppState, public_id: Uuid) -> Option<RoomLink> {
+    if let Some(room) = state.rooms.get(&public_id) {
+        return Some(RoomLink::Local(room));
+    }
+    let client = match state.pool.try_get().await {
+        Ok(client) => client,
+        Err(e) => {
+            error!("cannot get a client to find room {}: {}", public_id, e);
+            return None;
+        }
+    };
+    match lease::holder(&client, public_id).await {
+        Ok(Some(host)) if host != state.cluster.node_id() => Some(RoomLink::Remote(
+            RemoteRoom::new(state.cluster, public_id, host.into()),
+        )),
+        Ok(_) => None,
+        Err(e) => {
+            error!("cannot read lease of room {}: {}", public_id, e);
+            None
+        }
+    }
+}
+
+/// Returns the room if the node hosts it, the room is loaded if its lease is free
+async fn retrive_room_channel(
+    state: AppState,
+    public_id: Uuid,
+) -> Result<RoomChannel, RetriveError> {
+    // users joining an unloaded room at the same time wait for the first one to load it
+    let rooms = state.rooms.clone();
+    rooms
+        .get_or_spawn(public_id, || spawn_room(state, public_id))
+        .await
+}
+
+/// Loads the board from db and spawns its room
+async fn spawn_room(state: AppState, public_id: Uuid) -> Result<RoomChannel, RetriveError> {
+    let record = match board::get(state.pool, state.db_queue, public_id).await {
+        Ok(record) => record,
+        Err(_) => return Err(RetriveError::NotFound(public_id)),
+    };
+    let client = state.pool.get().await;
+    // only the node holding the lease hosts the room
+    let node_id = state.cluster.node_id();
+    match lease::acquire(&client, public_id, node_id, *LEASE_TTL).await {
+        Ok(holder) if holder != node_id => return Err(RetriveError::Remote(holder.into())),
+        Ok(_) => (),
+        Err(e) => {
+            error!("cannot acquire lease of room {}: {}", public_id, e);
+            return Err(RetriveError::NotFound(public_id));
+        }
+    }
+    let roles = role::read_by_board(&client, public_id)
+        .await
+        .unwrap_or_default();
+    let invites = invite::read_by_board(&client, public_id)
+        .await
+        .unwrap_or_default();
+    let has_co_editor_private_id = record.co_editor_private_id.is_some();
+    let room = Room::load(
+        record.board,
+        record.private_id,
+        record.co_editor_private_id,
+        record.owner_id,
+        roles,
+        invites,
+    )
+    .await;
+    // save generated co-editor token for boards created before it was persisted
+    if !has_co_editor_private_id {
+        let _ = board::update_co_editor_private_id(&client, public_id, room.co_editor_private_id())
+            .await;
+    }
+    drop(client);
+    // spawn room_task
+    let (tx, rx) = channel(1);
+    tokio::spawn(
+        state
+            .cluster
+            .host_task(public_id, tx.downgrade(), state.rooms.clone()),
+    );
+    tokio::spawn(async move {
+        room::task(
+            public_id,
+            room,
+            state.pool,
+            state.db_queue,
+            state.assets,
+            rx,
+        )
+        .await;
+    });
+    Ok(tx)
+}
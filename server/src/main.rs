@@ -5,7 +5,11 @@ use bb8_postgres::PostgresConnectionManager;
 use fast_log::config::Config;
 use jwt_simple::prelude::*;
 use lazy_static::lazy_static;
+use libs::assets::{AssetBackend, LocalDisk};
+use libs::bus::{Bus, LocalBus, PgBus};
+use libs::cluster::Cluster;
 use libs::db_queue::{new_db_queue, queue_task, DbQueueSender};
+use libs::user_queue::QueuePolicy;
 use log::info;
 use std::{env, fs, path::PathBuf, sync::atomic::AtomicUsize};
 use std::{error::Error, path::Path};
@@ -14,9 +18,9 @@ use tokio::sync::oneshot;
 use tokio_postgres::NoTls;
 use tower_http::services::{ServeDir, ServeFile};
 
-use crate::websocket::ws_handler;
+use crate::websocket::{replay_handler, ws_handler};
 use libs::state::{DbClient, Rooms};
-use lifecycle::{cleanup, monitor};
+use lifecycle::{cleanup, compact, keep_leases, monitor};
 use lifecycle::{cleanup_cache, on_shutdown};
 
 // modules
@@ -86,6 +90,86 @@ lazy_static! {
         }
         Err(_) => 100,
     };
+    pub static ref OPERATION_LOG_SIZE: usize = match &env::var("OPERATION_LOG_SIZE") {
+        Ok(s) => {
+            let parsed = s
+                .parse()
+                .expect("$OPERATION_LOG_SIZE must be usize integer > 0");
+
+            assert!(parsed > 0, "$OPERATION_LOG_SIZE must be > 0");
+
+            parsed
+        }
+        Err(_) => 500,
+    };
+    pub static ref SESSION_GRACE_SECONDS: u64 = match &env::var("SESSION_GRACE_SECONDS") {
+        Ok(v) => v
+            .parse()
+            .expect("$SESSION_GRACE_SECONDS must be u64 integer"),
+        Err(_) => 60,
+    };
+    // outbound queues
+    pub static ref OUTBOUND_QUEUE_SIZE: usize = match &env::var("OUTBOUND_QUEUE_SIZE") {
+        Ok(v) => {
+            let v = v.parse().expect("$OUTBOUND_QUEUE_SIZE must be usize integer");
+            assert!(v > 0, "$OUTBOUND_QUEUE_SIZE must be greater than 0");
+            v
+        },
+        Err(_) => 256,
+    };
+    pub static ref OUTBOUND_QUEUE_POLICY: QueuePolicy = match &env::var("OUTBOUND_QUEUE_POLICY") {
+        Ok(v) => v
+            .parse()
+            .expect("$OUTBOUND_QUEUE_POLICY must be coalesce, resync or disconnect"),
+        Err(_) => QueuePolicy::Coalesce,
+    };
+    // heartbeat
+    pub static ref HEARTBEAT_INTERVAL_SECONDS: u64 = match &env::var("HEARTBEAT_INTERVAL_SECONDS") {
+        Ok(v) => {
+            let v = v
+                .parse()
+                .expect("$HEARTBEAT_INTERVAL_SECONDS must be u64 integer");
+            assert!(v > 0, "$HEARTBEAT_INTERVAL_SECONDS must be greater than 0");
+            v
+        },
+        Err(_) => 15,
+    };
+    pub static ref HEARTBEAT_MISSES: u32 = match &env::var("HEARTBEAT_MISSES") {
+        Ok(v) => {
+            let v = v.parse().expect("$HEARTBEAT_MISSES must be u32 integer");
+            assert!(v > 0, "$HEARTBEAT_MISSES must be greater than 0");
+            v
+        },
+        Err(_) => 3,
+    };
+    // cluster
+    pub static ref NODE_ID: String = match env::var("NODE_ID") {
+        Ok(v) => {
+            assert!(!v.is_empty() && v.len() <= 64, "$NODE_ID must be from 1 to 64 characters long");
+            v
+        },
+        Err(_) => "local".to_owned(),
+    };
+    pub static ref CLUSTER_BUS: String = env::var("CLUSTER_BUS").unwrap_or("local".to_owned());
+    pub static ref LEASE_TTL: std::time::Duration = match &env::var("LEASE_TTL_SECONDS") {
+        Ok(v) => {
+            let v = v.parse().expect("$LEASE_TTL_SECONDS must be u64 integer");
+            assert!(v >= 3, "$LEASE_TTL_SECONDS must be at least 3");
+            std::time::Duration::from_secs(v)
+        },
+        Err(_) => std::time::Duration::from_secs(30),
+    };
+    // presence
+    pub static ref PRESENCE_THROTTLE_MS: std::time::Duration = match &env::var("PRESENCE_THROTTLE_MS") {
+        Ok(v) => {
+            let v = v
+                .parse()
+                .expect("$PRESENCE_THROTTLE_MS must be u64 integer");
+
+            std::time::Duration::from_millis(v)
+        },
+        Err(_) => std::time::Duration::from_millis(50),
+    };
     // cleanup
     pub static ref CLEANUP_INTERVAL_MINUTES: u64 = match &env::var("CLEANUP_INTERVAL_MINUTES") {
         Ok(t) => {
@@ -111,6 +195,27 @@ lazy_static! {
         },
         Err(_) => 10,
     };
+    // compaction
+    pub static ref COMPACTION_INTERVAL_MINUTES: u64 = match &env::var("COMPACTION_INTERVAL_MINUTES") {
+        Ok(t) => {
+            let t = t
+                .parse()
+                .expect("$COMPACTION_INTERVAL_MINUTES must be u64 integer");
+
+            assert!(t > 0, "$COMPACTION_INTERVAL_MINUTES must be greater than 0");
+
+            t
+        },
+        Err(_) => 60,
+    };
+    pub static ref UNDO_HORIZON: usize = match &env::var("UNDO_HORIZON") {
+        Ok(v) => {
+            let v = v.parse().expect("$UNDO_HORIZON must be usize integer");
+            assert!(v > 0, "$UNDO_HORIZON must be greater than 0");
+            v
+        },
+        Err(_) => 500,
+    };
     // monitoring
     pub static ref MONITOR_INTERVAL_MINUTES: u64 = match &env::var("MONITOR_INTERVAL_MINUTES") {
         Ok(t) => {
@@ -124,6 +229,23 @@ lazy_static! {
         },
         Err(_) => 5,
     };
+    // assets
+    pub static ref MAX_ASSET_SIZE: usize = match &env::var("MAX_ASSET_SIZE") {
+        Ok(v) => {
+            let v = v.parse().expect("$MAX_ASSET_SIZE must be usize integer");
+            assert!(v > 0, "$MAX_ASSET_SIZE must be greater than 0");
+            v
+        },
+        Err(_) => 10 * 1024 * 1024,
+    };
+    pub static ref ASSET_QUOTA: i64 = match &env::var("ASSET_QUOTA") {
+        Ok(v) => {
+            let v = v.parse().expect("$ASSET_QUOTA must be i64 integer");
+            assert!(v > 0, "$ASSET_QUOTA must be greater than 0");
+            v
+        },
+        Err(_) => 100 * 1024 * 1024,
+    };
     // paths
     pub static ref JWT_SECRET_KEY: &'static HS256Key = {
         let key = fs::read_to_string(
@@ -176,6 +298,8 @@ struct AppState {
     db_queue: &'static DbQueueSender,
     pool: &'static PoolWrapper,
     rooms: Rooms,
+    assets: &'static dyn AssetBackend,
+    cluster: &'static Cluster,
 }
 
 pub static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
@@ -196,11 +320,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
     .expect("db_password is not found");
     let init_sql =
         fs::read_to_string(&env::var("DB_INIT_PATH").expect("$DB_INIT_PATH is not provided"))?;
-    let manager = PostgresConnectionManager::new_from_stringlike(
-        &format!("host={db_host} port={db_port} user={db_user} password={db_password}"),
-        NoTls,
-    )
-    .expect("failed to create db connection pool");
+    let db_config = format!("host={db_host} port={db_port} user={db_user} password={db_password}");
+    let manager = PostgresConnectionManager::new_from_stringlike(db_config.as_str(), NoTls)
+        .expect("failed to create db connection pool");
     let pool = Box::leak(Box::new(
         Pool::builder()
             .max_size(*CONNECTION_POOL_SIZE)
@@ -219,12 +341,30 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .await?;
     // create db queue
     let (db_queue_sender, db_queue_receiver) = new_db_queue();
+    // create asset storage
+    let assets = Box::leak(Box::new(LocalDisk::new(
+        env::var("ASSETS_PATH").unwrap_or("assets".to_owned()),
+    )));
+    // connect to other nodes
+    let bus: Box<dyn Bus> = match CLUSTER_BUS.as_str() {
+        "local" => Box::new(LocalBus::default()),
+        "postgres" => Box::new(
+            PgBus::connect(&db_config)
+                .await
+                .expect("failed to connect the cluster bus"),
+        ),
+        _ => panic!("$CLUSTER_BUS must be local or postgres"),
+    };
+    let cluster: &'static Cluster = Box::leak(Box::new(Cluster::new(NODE_ID.as_str().into(), bus)));
+    info!("Running as node {}", cluster.node_id());
     // create state of the app
     let rooms = Rooms::default();
     let state = AppState {
         db_queue: db_queue_sender,
         pool: pool_wrapper,
         rooms: rooms.clone(),
+        assets,
+        cluster,
     };
     // start edit_queue task
     tokio::spawn(async move {
@@ -236,6 +376,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     routes = routes.nest("/api", api::api(state.clone()));
     // ws route
     routes = routes.route("/ws/board/:public_id", get(ws_handler));
+    routes = routes.route("/ws/replay/:public_id", get(replay_handler));
     // static paths
     let mut index_path = PathBuf::new();
     index_path.push(*PUBLIC_PATH);
@@ -253,11 +394,17 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // cleanup task
     let rooms_cleanup = rooms.clone();
     tokio::spawn(async move {
-        cleanup(rooms_cleanup).await;
+        cleanup(rooms_cleanup, state.pool, state.cluster).await;
     });
+    // cluster tasks
+    tokio::spawn(cluster.relay_task());
+    let rooms_leases = rooms.clone();
+    tokio::spawn(async move { keep_leases(rooms_leases, state.pool, state.cluster).await });
     // cache cleanup task
     let rooms_cache_cleanup = rooms.clone();
     tokio::spawn(async move { cleanup_cache(rooms_cache_cleanup).await });
+    // compaction task
+    tokio::spawn(async move { compact(state.pool).await });
     // create monitoring task
     let rooms_to_monitor = rooms.clone();
     tokio::spawn(async move {
@@ -271,8 +418,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .await
         .expect("Failed to create tcp socket on 3000 port");
     info!("Bind tcp socket: 0.0.0.0:3000");
+    let server_state = state.clone();
     tokio::spawn(async move {
-        axum::serve(listener, routes.with_state(state))
+        axum::serve(listener, routes.with_state(server_state))
             .with_graceful_shutdown(async { rx.await.unwrap() })
             .await
             .unwrap();
@@ -281,13 +429,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
     tokio::select! {
         _ = tokio::signal::ctrl_c() => {
             if !*NO_PERSIST {
-                on_shutdown(rooms.clone()).await;
+                on_shutdown(rooms.clone(), state.pool, state.cluster).await;
             }
             tx.send(()).unwrap();
         },
         _ = stream.recv() => {
             if !*NO_PERSIST {
-                on_shutdown(rooms.clone()).await;
+                on_shutdown(rooms.clone(), state.pool, state.cluster).await;
             }
             tx.send(()).unwrap();
         }
@@ -80,7 +80,11 @@ fn pull() -> Vec<u8> {
         msg: Some(Msg::Pull(Pull {
             current: vec![],
             undone: vec![],
+            page_id: None,
+            after_seq: None,
+            viewport: None,
         })),
+        request_id: String::new(),
     };
     encode_user_msg(msg)
 }
@@ -110,13 +114,22 @@ fn push() -> Vec<u8> {
             points: vec![100, 100, 200, 200],
             connected: vec![],
             url: "".to_owned(),
+            text: "".to_owned(),
+            font_size: 0.0,
+            font_family: "".to_owned(),
+            fill: "".to_owned(),
         }),
     });
     let msg = UserMessage {
         msg: Some(Msg::Push(Push {
-            data: vec![Edit { edit: Some(edit) }],
+            data: vec![Edit {
+                edit: Some(edit),
+                page_id: String::new(),
+                ..Default::default()
+            }],
             silent: false,
         })),
+        request_id: Uuid::now_v7().to_string(),
     };
     encode_user_msg(msg)
 }
@@ -124,6 +137,7 @@ fn push() -> Vec<u8> {
 fn auth(token: String) -> Vec<u8> {
     let msg = UserMessage {
         msg: Some(Msg::Auth(Auth { token })),
+        request_id: String::new(),
     };
     encode_user_msg(msg)
 }
@@ -1,31 +1,101 @@
 use crate::{
-    libs::room::{RoomChannel, UserMessage},
-    lifecycle::retrive_room_channel,
-    AppState, NEXT_USER_ID,
+    libs::{
+        auth::UserData,
+        cluster::RoomLink,
+        room::{RoomChannel, UserChannel, UserMessage},
+        user_queue::UserQueue,
+    },
+    lifecycle::retrive_room,
+    AppState, HEARTBEAT_INTERVAL_SECONDS, HEARTBEAT_MISSES, NEXT_USER_ID, OUTBOUND_QUEUE_POLICY,
+    OUTBOUND_QUEUE_SIZE,
 };
 use axum::body::Bytes;
 use fastwebsockets::{upgrade, FragmentCollectorRead, Frame, OpCode, Payload, WebSocketError};
 use log::{debug, warn};
 use protocol::{
     board_protocol::{
-        server_message::Msg::Info as InfoVariant, user_message::Msg as ProtcolUserMessageVariant,
-        ActionType, EmptyActionType, Info, ServerMessage as ProtocolServerMessage,
-        UserMessage as ProtocolUserMessage,
+        server_message::Msg::{Info as InfoVariant, ResyncData as ResyncDataVariant},
+        user_message::Msg as ProtcolUserMessageVariant,
+        ActionType, EmptyActionType, ErrorCode, Info, ResyncData,
+        ServerMessage as ProtocolServerMessage, UserMessage as ProtocolUserMessage,
     },
     decode_user_msg, encode_server_msg,
 };
-use std::{sync::atomic::Ordering, time::Duration};
-use tokio::{
+use std::{
+    fmt,
     sync::{
-        mpsc::{error::SendError, unbounded_channel},
-        oneshot,
+        atomic::{AtomicU32, Ordering},
+        Arc,
     },
-    time::timeout,
+    time::Duration,
+};
+use tokio::{
+    select,
+    sync::{mpsc::error::SendError, oneshot},
+    time::{interval_at, timeout, Instant},
 };
 use uuid::Uuid;
 
+// errors
+
+#[derive(Debug)]
+pub enum MessageError {
+    /// the message has a value the protocol does not define,
+    /// contains the id the sender has given to the message
+    Decode(Box<str>),
+    /// the room has stopped
+    Closed(SendError<UserMessage>),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Decode(_) => write!(f, "the message has an unknown value"),
+            Self::Closed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<SendError<UserMessage>> for MessageError {
+    fn from(e: SendError<UserMessage>) -> Self {
+        Self::Closed(e)
+    }
+}
+
+// heartbeats
+
+/// Counts heartbeats sent since the last frame from the user
+/// limit - how many heartbeats the user can miss before the connection is dropped
+struct Heartbeats {
+    missed: AtomicU32,
+    limit: u32,
+}
+
+impl Heartbeats {
+    fn new(limit: u32) -> Self {
+        Heartbeats {
+            missed: AtomicU32::new(0),
+            limit,
+        }
+    }
+
+    /// Counts the next heartbeat, returns how many heartbeats are missed before it
+    /// and false if the user has missed too many of them
+    fn tick(&self) -> (u32, bool) {
+        let missed = self.missed.fetch_add(1, Ordering::Relaxed);
+        (missed, missed < self.limit)
+    }
+
+    /// Any frame proves the connection is alive
+    fn reset(&self) {
+        self.missed.store(0, Ordering::Relaxed);
+    }
+}
+
 pub async fn handle_client(
     public_id: Box<str>,
+    user_data: Option<UserData>,
+    resume: Option<(Box<str>, u64)>,
     app_state: AppState,
     fut: upgrade::UpgradeFut,
 ) -> Result<(), WebSocketError> {
@@ -37,7 +107,7 @@ pub async fn handle_client(
     // try to find room in RAM or DB
     // if there is a room, join it
     // otherwise, close the connection
-    let room_chan = match retrive_room_channel(app_state, public_id).await {
+    let room = match retrive_room(app_state, public_id).await {
         Ok(c) => c,
         Err(e) => {
             debug!(
@@ -53,60 +123,112 @@ pub async fn handle_client(
     let ws = fut.await?;
     let (rx_s, mut tx_s) = ws.split(tokio::io::split);
     let mut rx_s = FragmentCollectorRead::new(rx_s);
-    let (tx_m, mut rx_m) = unbounded_channel();
-    let _ = room_chan
-        .send(UserMessage::Join {
-            user_id,
-            chan: tx_m.to_owned(),
-        })
-        .await;
+    let queue = user_queue();
+    room.join(user_id, queue.clone(), user_data, resume).await;
     // read/write messages
+    let heartbeats = Arc::new(Heartbeats::new(*HEARTBEAT_MISSES));
     let (tx_disconnect, mut rx_disconnect) = oneshot::channel();
+    let (tx_stop, mut rx_stop) = oneshot::channel::<()>();
+    let writer_heartbeats = heartbeats.clone();
+    let writer_room = room.clone();
+    let writer_queue = queue.clone();
     tokio::spawn(async move {
-        while let Some(msg) = rx_m.recv().await {
-            let payload = Payload::Borrowed(msg.as_ref());
-            let frame = Frame::binary(payload);
-            if let Err(e) = timeout(Duration::from_secs(5), tx_s.write_frame(frame)).await {
-                warn!("failed to send a message, closing the connection: {}", e);
-                let _ = tx_disconnect.send(());
-                break;
+        let period = Duration::from_secs(*HEARTBEAT_INTERVAL_SECONDS);
+        let mut heartbeat = interval_at(Instant::now() + period, period);
+        loop {
+            select! {
+                msg = writer_queue.pop() => {
+                    // the queue is closed because the user is too slow
+                    let Some(msg) = msg else {
+                        warn!("user {} is too slow, closing the connection", user_id);
+                        break;
+                    };
+                    let payload = Payload::Borrowed(msg.as_ref());
+                    let frame = Frame::binary(payload);
+                    if let Err(e) = timeout(Duration::from_secs(5), tx_s.write_frame(frame)).await {
+                        warn!("failed to send a message, closing the connection: {}", e);
+                        break;
+                    }
+                }
+                // the user has left, send what is left in the channel
+                _ = &mut rx_stop => {
+                    while let Some(msg) = writer_queue.try_pop() {
+                        let frame = Frame::binary(Payload::Borrowed(msg.as_ref()));
+                        if timeout(Duration::from_secs(5), tx_s.write_frame(frame)).await.is_err() {
+                            break;
+                        }
+                    }
+                    break;
+                }
+                _ = heartbeat.tick() => {
+                    let (missed, alive) = writer_heartbeats.tick();
+                    writer_room.heartbeat(user_id, missed).await;
+                    // the connection is most likely dead
+                    if !alive {
+                        warn!(
+                            "user {} has missed {} heartbeats, closing the connection",
+                            user_id, missed
+                        );
+                        break;
+                    }
+                    let frame = Frame::new(true, OpCode::Ping, None, Payload::Borrowed(&[]));
+                    if !matches!(
+                        timeout(Duration::from_secs(5), tx_s.write_frame(frame)).await,
+                        Ok(Ok(()))
+                    ) {
+                        warn!("failed to send a heartbeat, closing the connection");
+                        break;
+                    }
+                }
             }
         }
+        let _ = tx_disconnect.send(());
     });
 
-    let mut is_authed = false;
+    let mut obligated_send = |_| async { Ok(()) };
     loop {
-        if let Ok(mut frame) = rx_s
-            .read_frame::<_, WebSocketError>(&mut move |_| async { Ok(()) })
-            .await
-        {
+        // stop reading if the writer has closed the connection
+        let frame = select! {
+            frame = rx_s.read_frame::<_, WebSocketError>(&mut obligated_send) => frame,
+            _ = &mut rx_disconnect => break,
+        };
+        if let Ok(mut frame) = frame {
+            heartbeats.reset();
             match frame.opcode {
                 OpCode::Close => break,
+                OpCode::Pong => (),
                 OpCode::Text | OpCode::Binary => {
-                    match decode_user_msg(frame.payload.to_mut()) {
-                        Ok(msg) => {
-                            if let Err(e) =
-                                handle_message(&room_chan, user_id, &mut is_authed, msg).await
-                            {
-                                // if we can't send a msg, the room was most likely deleted
-                                // So we should disconnect the user
-                                warn!("try to send to non-existent room: {}", e);
+                    let res = match &room {
+                        RoomLink::Local(room_chan) => match decode_user_msg(frame.payload.to_mut())
+                        {
+                            Ok(msg) => match handle_message(room_chan, user_id, msg).await {
+                                Err(MessageError::Decode(request_id)) => {
+                                    queue.push(decode_error_message(&request_id), None);
+                                    warn!(
+                                        "user {} has sent a message with an unknown value",
+                                        user_id
+                                    );
+                                    break;
+                                }
+                                res => res.map_err(|e| e.to_string()),
+                            },
+                            Err(e) => {
+                                queue.push(decode_error_message(""), None);
+                                warn!("cannot decode the message: {}", e);
                                 break;
                             }
-                        }
-                        Err(e) => {
-                            let msg = ProtocolServerMessage {
-                                msg: Some(InfoVariant(Info {
-                                    status: "bad".to_owned(),
-                                    action: "unknown".to_owned(),
-                                    payload: "failed to decode the message".to_owned(),
-                                })),
-                            };
-                            let bytes = Bytes::from(encode_server_msg(&msg).to_vec());
-                            let _ = tx_m.send(bytes);
-                            warn!("cannot decode the message: {}", e);
-                            break;
-                        }
+                        },
+                        // the hosting node decodes the message
+                        RoomLink::Remote(remote) => remote
+                            .frame(user_id, &frame.payload)
+                            .await
+                            .map_err(|e| e.to_string()),
+                    };
+                    if let Err(e) = res {
+                        // if we can't send a msg, the room was most likely deleted
+                        // So we should disconnect the user
+                        warn!("try to send to non-existent room: {}", e);
+                        break;
                     }
                 }
                 _ => {
@@ -116,92 +238,166 @@ pub async fn handle_client(
         } else {
             break;
         }
-        if let Ok(_) = rx_disconnect.try_recv() {
-            break;
-        }
     }
+    let _ = tx_stop.send(());
     // send Quit message after disconnect to remove user from room
-    let _ = room_chan.send(UserMessage::Quit { user_id }).await;
+    room.quit(user_id).await;
     debug!("disconnect user with id: {}", user_id);
     Ok(())
 }
 
-async fn handle_message(
+/// Creates a queue of messages to a user's connection
+pub fn user_queue() -> UserChannel {
+    let resync_hint = ProtocolServerMessage {
+        msg: Some(ResyncDataVariant(ResyncData {})),
+    };
+    UserQueue::new(
+        *OUTBOUND_QUEUE_SIZE,
+        *OUTBOUND_QUEUE_POLICY,
+        Bytes::from(encode_server_msg(&resync_hint)),
+    )
+}
+
+/// Message sent to a user whose message cannot be decoded,
+/// request_id - id the user has given to the message, empty if it cannot be decoded
+pub fn decode_error_message(request_id: &str) -> Bytes {
+    let msg = ProtocolServerMessage {
+        msg: Some(InfoVariant(Info {
+            status: "bad".to_owned(),
+            action: "unknown".to_owned(),
+            payload: "failed to decode the message".to_owned(),
+            request_id: request_id.to_owned(),
+            code: ErrorCode::DecodeFailed.into(),
+        })),
+    };
+    Bytes::from(encode_server_msg(&msg).to_vec())
+}
+
+/// Passes a decoded message of the user to the room
+///
+/// # Errors
+///
+/// This function will return an error if the message has an unknown enum value or the room has stopped
+pub async fn handle_message(
     r: &RoomChannel,
     user_id: usize,
-    is_authed: &mut bool,
     msg: ProtocolUserMessage,
-) -> Result<(), SendError<UserMessage>> {
+) -> Result<(), MessageError> {
     if msg.msg.is_none() {
         debug!("User({}) has sent empty message", user_id);
         return Ok(());
     }
 
+    let request_id: Box<str> = msg.request_id.into();
     match msg.msg.unwrap() {
         ProtcolUserMessageVariant::Auth(data) => {
-            let (sender, receiver) = oneshot::channel();
             r.send(UserMessage::Auth {
                 user_id,
                 token: data.token.into(),
-                sender,
             })
-            .await?;
-            if let Ok(r) = receiver.await {
-                *is_authed = r;
-            }
+            .await?
         }
         ProtcolUserMessageVariant::SetTitle(data) => {
-            if *is_authed {
-                r.send(UserMessage::SetTitle {
-                    user_id,
-                    title: data.title.into(),
-                })
-                .await?
-            }
+            r.send(UserMessage::SetTitle {
+                user_id,
+                title: data.title.into(),
+                request_id,
+            })
+            .await?
         }
         ProtcolUserMessageVariant::Push(data) => {
-            if *is_authed {
-                r.send(UserMessage::Push {
-                    user_id,
-                    data: data.data,
-                    silent: data.silent,
-                })
-                .await?
-            }
+            r.send(UserMessage::Push {
+                user_id,
+                data: data.data,
+                silent: data.silent,
+                request_id,
+            })
+            .await?
         }
         ProtcolUserMessageVariant::UndoRedo(data) => {
-            if *is_authed {
-                r.send(UserMessage::UndoRedo {
-                    user_id,
-                    action_type: ActionType::try_from(data.action_type).unwrap(),
-                    action_id: data.action_id.into(),
-                })
-                .await?
-            }
+            r.send(UserMessage::UndoRedo {
+                user_id,
+                action_type: ActionType::try_from(data.action_type)
+                    .map_err(|_| MessageError::Decode(request_id.clone()))?,
+                action_id: data.action_id.into(),
+                request_id,
+            })
+            .await?
         }
         ProtcolUserMessageVariant::Empty(data) => {
-            if *is_authed {
-                r.send(UserMessage::Empty {
-                    user_id,
-                    action_type: EmptyActionType::try_from(data.action_type).unwrap(),
-                })
-                .await?
-            }
+            r.send(UserMessage::Empty {
+                user_id,
+                action_type: EmptyActionType::try_from(data.action_type)
+                    .map_err(|_| MessageError::Decode(request_id.clone()))?,
+                request_id,
+            })
+            .await?
         }
         ProtcolUserMessageVariant::SetSize(data) => {
-            if *is_authed {
-                r.send(UserMessage::SetSize {
-                    user_id,
-                    data: data.data,
-                })
-                .await?
-            }
+            r.send(UserMessage::SetSize {
+                user_id,
+                data: data.data,
+                request_id,
+            })
+            .await?
+        }
+        ProtcolUserMessageVariant::SetCursor(data) => {
+            r.send(UserMessage::SetCursor {
+                user_id,
+                x: data.x,
+                y: data.y,
+            })
+            .await?
+        }
+        ProtcolUserMessageVariant::SetViewport(data) => {
+            r.send(UserMessage::SetViewport {
+                user_id,
+                data: data.data,
+            })
+            .await?
         }
         ProtcolUserMessageVariant::Pull(data) => {
             r.send(UserMessage::Pull {
                 user_id,
                 current: data.current.into_iter().map(|d| d.into()).collect(),
                 undone: data.undone.into_iter().map(|d| d.into()).collect(),
+                page_id: data.page_id.map(|id| id.into()),
+                after_seq: data.after_seq,
+                viewport: data.viewport,
+            })
+            .await?
+        }
+        ProtcolUserMessageVariant::CreatePage(data) => {
+            r.send(UserMessage::CreatePage {
+                user_id,
+                data: data.data,
+                request_id,
+            })
+            .await?
+        }
+        ProtcolUserMessageVariant::DeletePage(data) => {
+            r.send(UserMessage::DeletePage {
+                user_id,
+                id: data.id.into(),
+                request_id,
+            })
+            .await?
+        }
+        ProtcolUserMessageVariant::MovePage(data) => {
+            r.send(UserMessage::MovePage {
+                user_id,
+                id: data.id.into(),
+                position: data.position as usize,
+                request_id,
+            })
+            .await?
+        }
+        ProtcolUserMessageVariant::RenamePage(data) => {
+            r.send(UserMessage::RenamePage {
+                user_id,
+                id: data.id.into(),
+                title: data.title.into(),
+                request_id,
             })
             .await?
         }
@@ -209,3 +405,58 @@ async fn handle_message(
 
     Ok(())
 }
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::{board_protocol::UndoRedo, decode_server_msg};
+    use tokio::sync::mpsc::channel;
+
+    #[test]
+    fn connection_is_dead_after_missed_heartbeats() {
+        let heartbeats = Heartbeats::new(2);
+        assert_eq!(heartbeats.tick(), (0, true));
+        assert_eq!(heartbeats.tick(), (1, true));
+        assert_eq!(heartbeats.tick(), (2, false));
+    }
+
+    #[test]
+    fn frame_resets_missed_heartbeats() {
+        let heartbeats = Heartbeats::new(2);
+        heartbeats.tick();
+        heartbeats.tick();
+        heartbeats.reset();
+        assert_eq!(heartbeats.tick(), (0, true));
+    }
+
+    #[tokio::test]
+    async fn unknown_enum_value_is_decode_failure() {
+        let (tx, mut rx) = channel(1);
+        let msg = ProtocolUserMessage {
+            msg: Some(ProtcolUserMessageVariant::UndoRedo(UndoRedo {
+                action_type: 99,
+                action_id: "id".to_owned(),
+            })),
+            request_id: "undo".to_owned(),
+        };
+
+        let res = handle_message(&tx, 1, msg).await;
+        let request_id = match res {
+            Err(MessageError::Decode(request_id)) => request_id,
+            _ => panic!("unknown enum value is decoded"),
+        };
+        assert_eq!(&*request_id, "undo");
+        // nothing reaches the room
+        assert!(rx.try_recv().is_err());
+        let reply = decode_server_msg(&decode_error_message(&request_id)).unwrap();
+        match reply.msg {
+            Some(InfoVariant(info)) => {
+                assert_eq!(info.code(), ErrorCode::DecodeFailed);
+                assert_eq!(info.request_id, "undo");
+            }
+            _ => panic!("decode error message is not Info"),
+        }
+    }
+}
@@ -1,5 +1,9 @@
 mod handle_client;
+mod replay;
 mod ws_handler;
 
-pub use handle_client::handle_client;
+pub use handle_client::{
+    decode_error_message, handle_client, handle_message, user_queue, MessageError,
+};
+pub use replay::replay_handler;
 pub use ws_handler::ws_handler;
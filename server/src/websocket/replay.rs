@@ -0,0 +1,223 @@
+use crate::{
+    entities::{board, edit::read_history},
+    AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+};
+use fastwebsockets::{upgrade, Frame, OpCode, Payload, WebSocketError};
+use log::debug;
+use protocol::{
+    board_protocol::{
+        server_message::Msg, ErrorCode, Info, PagesData, PushData, ServerMessage, SizeData,
+        TitleData,
+    },
+    encode_server_msg,
+};
+use serde::Deserialize;
+use std::time::{Duration, SystemTime};
+use tokio::time::sleep;
+use uuid::Uuid;
+
+// constants
+
+const MAX_SPEED: f32 = 1000.0;
+/// Pauses between edits are shortened to this duration, so idle periods are skipped
+const MAX_PAUSE: Duration = Duration::from_secs(1);
+
+/// speed - how many times faster than real time the history is replayed, 1 by default
+#[derive(Deserialize)]
+pub struct ReplayParams {
+    speed: Option<f32>,
+}
+
+pub async fn replay_handler(
+    State(state): State<AppState>,
+    Path(public_id): Path<Box<str>>,
+    Query(params): Query<ReplayParams>,
+    ws: upgrade::IncomingUpgrade,
+) -> impl IntoResponse {
+    let (response, fut) = ws.upgrade().unwrap();
+
+    tokio::task::spawn(async move {
+        if let Err(e) = replay(public_id, params, state, fut).await {
+            eprintln!("Error in replay websocket connection: {}", e);
+        }
+    });
+
+    response
+}
+
+/// Sends the board's title, size, pages and then its edits one by one with pauses between them.
+/// The history is read from db, so the room is neither loaded nor changed
+/// and edits that are not saved yet are not replayed
+async fn replay(
+    public_id: Box<str>,
+    params: ReplayParams,
+    state: AppState,
+    fut: upgrade::UpgradeFut,
+) -> Result<(), WebSocketError> {
+    let mut ws = fut.await?;
+    let speed = match check_speed(params.speed) {
+        Some(speed) => speed,
+        None => {
+            send(
+                &mut ws,
+                &info("bad", ErrorCode::InvalidValue, "speed is out of range"),
+            )
+            .await?;
+            return ws.write_frame(Frame::close(1000, &[])).await;
+        }
+    };
+    // read the board
+    let public_id = match Uuid::try_parse(&public_id) {
+        Ok(id) => id,
+        Err(_) => {
+            send(&mut ws, &info("bad", ErrorCode::InvalidValue, "Not a uuid")).await?;
+            return ws.write_frame(Frame::close(1000, &[])).await;
+        }
+    };
+    let record = match board::get(state.pool, state.db_queue, public_id).await {
+        Ok(record) => record,
+        Err(_) => {
+            send(
+                &mut ws,
+                &info("bad", ErrorCode::RoomNotFound, "no such room"),
+            )
+            .await?;
+            return ws.write_frame(Frame::close(1000, &[])).await;
+        }
+    };
+    let (initial, history) = match read_history(&state.pool.get().await, public_id).await {
+        Ok(history) => history,
+        Err(e) => {
+            debug!("cannot read history of {}: {}", public_id, e);
+            send(
+                &mut ws,
+                &info("bad", ErrorCode::InternalError, "cannot read the history"),
+            )
+            .await?;
+            return ws.write_frame(Frame::close(1000, &[])).await;
+        }
+    };
+    // send the board's info
+    let board = record.board;
+    let messages = [
+        Msg::TitleData(TitleData {
+            title: board.title().to_owned(),
+        }),
+        Msg::SizeData(SizeData {
+            data: Some(board.size().clone()),
+        }),
+        Msg::PagesData(PagesData {
+            pages: board.pages().clone(),
+        }),
+        Msg::PushData(PushData {
+            data: initial,
+            ..Default::default()
+        }),
+    ];
+    for msg in messages {
+        send(&mut ws, &ServerMessage { msg: Some(msg) }).await?;
+    }
+    // replay edits
+    let mut previous = history.first().map(|(stamp, _)| *stamp);
+    for (stamp, edit) in history {
+        sleep(pause(previous, stamp, speed)).await;
+        previous = Some(stamp);
+        let msg = ServerMessage {
+            msg: Some(Msg::PushData(PushData {
+                data: vec![edit],
+                ..Default::default()
+            })),
+        };
+        // the viewer has left
+        if send(&mut ws, &msg).await.is_err() {
+            return Ok(());
+        }
+    }
+    send(&mut ws, &info("good", ErrorCode::NoError, "finished")).await?;
+    ws.write_frame(Frame::close(1000, &[])).await
+}
+
+// helpers
+
+/// Returns the speed if it is in range, 1 if it is not set
+fn check_speed(speed: Option<f32>) -> Option<f32> {
+    let speed = speed.unwrap_or(1.0);
+    (speed > 0.0 && speed <= MAX_SPEED).then_some(speed)
+}
+
+/// Returns the pause before the edit made at stamp, the previous edit is made at previous
+fn pause(previous: Option<SystemTime>, stamp: SystemTime, speed: f32) -> Duration {
+    previous
+        .and_then(|previous| stamp.duration_since(previous).ok())
+        .unwrap_or_default()
+        .div_f32(speed)
+        .min(MAX_PAUSE)
+}
+
+fn info(status: &str, code: ErrorCode, payload: &str) -> ServerMessage {
+    ServerMessage {
+        msg: Some(Msg::Info(Info {
+            status: status.to_owned(),
+            action: "Replay".to_owned(),
+            payload: payload.to_owned(),
+            code: code.into(),
+            ..Default::default()
+        })),
+    }
+}
+
+async fn send(
+    ws: &mut fastwebsockets::WebSocket<hyper_util::rt::TokioIo<hyper::upgrade::Upgraded>>,
+    msg: &ServerMessage,
+) -> Result<(), WebSocketError> {
+    let bytes = encode_server_msg(msg);
+    ws.write_frame(Frame::new(
+        true,
+        OpCode::Binary,
+        None,
+        Payload::Borrowed(&bytes),
+    ))
+    .await
+}
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn speed_is_checked() {
+        assert_eq!(check_speed(None), Some(1.0));
+        assert_eq!(check_speed(Some(MAX_SPEED)), Some(MAX_SPEED));
+        for speed in [0.0, -1.0, MAX_SPEED + 1.0, f32::NAN] {
+            assert_eq!(check_speed(Some(speed)), None);
+        }
+    }
+
+    #[test]
+    fn pause_is_divided_by_speed() {
+        let now = SystemTime::now();
+        assert_eq!(pause(Some(now), now + SEC / 2, 1.0), SEC / 2);
+        assert_eq!(pause(Some(now), now + SEC, 4.0), SEC / 4);
+    }
+
+    #[test]
+    fn pause_is_capped() {
+        let now = SystemTime::now();
+        assert_eq!(pause(Some(now), now + SEC * 60, 2.0), MAX_PAUSE);
+    }
+
+    #[test]
+    fn first_and_backward_edits_are_not_paused() {
+        let now = SystemTime::now();
+        assert_eq!(pause(None, now, 1.0), Duration::ZERO);
+        assert_eq!(pause(Some(now + SEC), now, 1.0), Duration::ZERO);
+    }
+}
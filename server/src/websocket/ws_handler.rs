@@ -1,21 +1,35 @@
-use crate::AppState;
+use crate::{api::common::UserDataFromJWT, AppState};
 
 use super::handle_client;
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::IntoResponse,
 };
 use fastwebsockets::upgrade;
+use serde::Deserialize;
+
+/// session - token from SessionData of the dropped connection
+/// seq - the last sequence number the client has received
+#[derive(Deserialize)]
+pub struct ResumeParams {
+    session: Option<Box<str>>,
+    seq: Option<u64>,
+}
 
 pub async fn ws_handler(
     State(state): State<AppState>,
     Path(public_id): Path<Box<str>>,
+    UserDataFromJWT(user_data): UserDataFromJWT,
+    Query(params): Query<ResumeParams>,
     ws: upgrade::IncomingUpgrade,
 ) -> impl IntoResponse {
     let (response, fut) = ws.upgrade().unwrap();
+    let resume = params
+        .session
+        .map(|token| (token, params.seq.unwrap_or_default()));
 
     tokio::task::spawn(async move {
-        if let Err(e) = handle_client(public_id, state, fut).await {
+        if let Err(e) = handle_client(public_id, user_data, resume, state, fut).await {
             eprintln!("Error in websocket connection: {}", e);
         }
     });